@@ -0,0 +1,120 @@
+use super::endpoint::{Endpoint, Service};
+use super::policy::RequestPolicy;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+struct BootstrapToken {
+    #[serde(rename = "SecretID")]
+    secret_id: String,
+}
+
+/// Create the initial management token of Nomad, or `None` if its ACLs were
+/// bootstrapped before. The token can only be had once, so the request is
+/// never retried. Consul's management token is pre-seeded into its servers
+/// with `acl.tokens.initial_management` instead.
+pub async fn bootstrap(endpoint: &Endpoint, policy: &RequestPolicy) -> Result<Option<String>> {
+    if endpoint.service != Service::Nomad {
+        return Err(anyhow!(
+            "only Nomad's ACLs are bootstrapped, not {}'s",
+            endpoint.service
+        ));
+    }
+    let client = endpoint.client(policy)?;
+    let url = format!("{}/v1/acl/bootstrap", endpoint.addr);
+
+    let response = client
+        .post(&url)
+        .timeout(policy.timeout)
+        .send()
+        .await
+        .with_context(|| format!("failed to query: {}", &url))?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        if text.contains("already done") {
+            return Ok(None);
+        }
+        return Err(anyhow!("{} returned {}: {}", &url, status, text.trim()));
+    }
+
+    let token: BootstrapToken = response
+        .json()
+        .await
+        .with_context(|| format!("failed to decode response from: {}", &url))?;
+    Ok(Some(token.secret_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Requests};
+
+    /// Answers like `nomad agent -dev -acl-enabled`: the first bootstrap
+    /// hands out a token, every later one is refused.
+    async fn nomad_dev() -> (Endpoint, Requests) {
+        let (addr, requests) = testing::serve(|before, _| match before {
+            0 => (
+                "200 OK",
+                r#"{"SecretID":"b5c6e1a2-0000-4000-8000-000000000001"}"#.to_owned(),
+            ),
+            _ => (
+                "400 Bad Request",
+                "ACL bootstrap already done (reset index: 7)".to_owned(),
+            ),
+        })
+        .await;
+
+        let endpoint = Endpoint {
+            service: Service::Nomad,
+            addr,
+            ..Default::default()
+        };
+        (endpoint, requests)
+    }
+
+    #[tokio::test]
+    async fn bootstrap_hands_out_the_token_once() {
+        let (endpoint, requests) = nomad_dev().await;
+        let policy = RequestPolicy::default();
+        assert_eq!(
+            bootstrap(&endpoint, &policy).await.unwrap().as_deref(),
+            Some("b5c6e1a2-0000-4000-8000-000000000001")
+        );
+        assert_eq!(bootstrap(&endpoint, &policy).await.unwrap(), None);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests
+            .iter()
+            .all(|request| request.method == "POST" && request.path == "/v1/acl/bootstrap"));
+    }
+
+    #[tokio::test]
+    async fn bootstrap_leaves_consul_alone() {
+        let endpoint = Endpoint {
+            service: Service::Consul,
+            addr: "http://127.0.0.1:1".to_owned(),
+            ..Default::default()
+        };
+        assert!(bootstrap(&endpoint, &RequestPolicy::default())
+            .await
+            .is_err());
+    }
+
+    /// Against a real `nomad agent -dev -acl-enabled` at `NOMAD_ADDR`; run
+    /// with `cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore]
+    async fn bootstrap_nomad_dev_agent() {
+        let endpoint = Endpoint {
+            service: Service::Nomad,
+            addr: std::env::var("NOMAD_ADDR")
+                .unwrap_or_else(|_| "http://127.0.0.1:4646".to_owned()),
+            ..Default::default()
+        };
+        let policy = RequestPolicy::default();
+        bootstrap(&endpoint, &policy).await.unwrap();
+        assert_eq!(bootstrap(&endpoint, &policy).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,59 @@
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The identity prem clusters encrypt their bootstrap secrets to, relative
+/// to the cluster's repository.
+pub const IDENTITY: &str = "secrets-prem/age-bootstrap";
+
+/// Decrypt an age file with `rage`, like the terraform scripts do.
+pub fn decrypt(identity: &Path, file: &Path) -> Result<String> {
+    let mut cmd = Command::new("rage");
+    cmd.arg("-i").arg(identity).arg("-d").arg(file);
+    info!("cmd: {:?}", cmd);
+
+    let output = cmd.output().with_context(|| "rage command failed")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "decrypting {} failed with {}: {}",
+            file.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    String::from_utf8(output.stdout)
+        .with_context(|| format!("{} doesn't decrypt to text", file.display()))
+}
+
+/// Encrypt `content` to the recipient of `identity` into `file`, ASCII
+/// armored so it diffs sensibly in git.
+pub fn encrypt(identity: &Path, content: &[u8], file: &Path) -> Result<()> {
+    let mut cmd = Command::new("rage");
+    cmd.arg("-i")
+        .arg(identity)
+        .args(["-a", "-e", "-o"])
+        .arg(file)
+        .stdin(Stdio::piped());
+    info!("cmd: {:?}", cmd);
+
+    let mut child = cmd.spawn().with_context(|| "rage command failed")?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(content)?;
+    let status = child
+        .wait()
+        .with_context(|| "rage command didn't finish?")?;
+    if !status.success() {
+        return Err(anyhow!(
+            "encrypting {} failed with {}",
+            file.display(),
+            status
+        ));
+    }
+    Ok(())
+}
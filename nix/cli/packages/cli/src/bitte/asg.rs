@@ -0,0 +1,320 @@
+use super::policy::RequestPolicy;
+use anyhow::{Context, Result};
+use aws_sdk_autoscaling::{
+    model::{AutoScalingGroup, LaunchTemplateSpecification},
+    Client as AsgClient,
+};
+use serde::Serialize;
+use tokio_stream::StreamExt;
+
+/// An auto-scaling group of the cluster's Nomad clients.
+#[derive(Debug, Serialize, Clone)]
+pub struct BitteAsg {
+    pub name: String,
+    pub region: String,
+    pub desired: i32,
+    pub min: i32,
+    pub max: i32,
+    /// The launch template and version, or launch configuration, new
+    /// instances are started from
+    pub launch: Option<String>,
+    /// Whether new instances are protected from scale-in
+    pub protect_new: bool,
+    pub instances: Vec<AsgInstance>,
+}
+
+/// An instance as the auto-scaling group sees it, which includes those that
+/// aren't running yet or anymore.
+#[derive(Debug, Serialize, Clone)]
+pub struct AsgInstance {
+    pub id: String,
+    /// e.g. `InService`, `Pending` or `Terminating`
+    pub lifecycle: String,
+    pub health: String,
+    pub zone: String,
+    /// What the instance was started from, which lags behind the group's
+    /// until the instance is replaced
+    pub launch: Option<String>,
+    pub protected: bool,
+}
+
+impl AsgInstance {
+    pub fn in_service(&self) -> bool {
+        self.lifecycle == "InService"
+    }
+}
+
+impl BitteAsg {
+    /// Instances that are about to join or leave the group.
+    pub fn in_flux(&self) -> usize {
+        self.instances
+            .iter()
+            .filter(|instance| !instance.in_service() && instance.lifecycle != "Standby")
+            .count()
+    }
+
+    /// The groups tagged with the cluster's name in each of `regions`.
+    pub async fn find_asgs(
+        name: &str,
+        regions: &[String],
+        policy: &RequestPolicy,
+    ) -> Result<Vec<BitteAsg>> {
+        let mut result = Vec::new();
+        for region in regions {
+            let client = Self::client(region, policy).await?;
+            let mut pages = client
+                .describe_auto_scaling_groups()
+                .into_paginator()
+                .page_size(100)
+                .send();
+
+            while let Some(page) = pages.next().await {
+                let page = page.with_context(|| {
+                    format!(
+                        "failed to connect to {}",
+                        policy.aws_host("autoscaling", region)
+                    )
+                })?;
+                result.extend(
+                    page.auto_scaling_groups
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter(|group| tag(group, "Cluster") == Some(name))
+                        .map(|group| Self::from_group(group, region)),
+                );
+            }
+        }
+
+        result.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(result)
+    }
+
+    /// Change the desired capacity, right away rather than after the
+    /// group's cooldown; AWS picks which instances to stop when scaling in.
+    pub async fn scale(&self, desired: i32, policy: &RequestPolicy) -> Result<()> {
+        Self::client(&self.region, policy)
+            .await?
+            .set_desired_capacity()
+            .auto_scaling_group_name(&self.name)
+            .desired_capacity(desired)
+            .honor_cooldown(false)
+            .send()
+            .await
+            .with_context(|| format!("failed to scale {} to {}", self.name, desired))?;
+        Ok(())
+    }
+
+    /// Take the instance out of the group but leave it running; the group
+    /// starts a replacement to keep its desired capacity.
+    pub async fn detach(&self, instance: &str, policy: &RequestPolicy) -> Result<()> {
+        Self::client(&self.region, policy)
+            .await?
+            .detach_instances()
+            .auto_scaling_group_name(&self.name)
+            .instance_ids(instance)
+            .should_decrement_desired_capacity(false)
+            .send()
+            .await
+            .with_context(|| format!("failed to detach {} from {}", instance, self.name))?;
+        Ok(())
+    }
+
+    /// Terminate the instance through the group, which starts a replacement
+    /// to keep its desired capacity.
+    pub async fn terminate(&self, instance: &str, policy: &RequestPolicy) -> Result<()> {
+        Self::client(&self.region, policy)
+            .await?
+            .terminate_instance_in_auto_scaling_group()
+            .instance_id(instance)
+            .should_decrement_desired_capacity(false)
+            .send()
+            .await
+            .with_context(|| format!("failed to terminate {} in {}", instance, self.name))?;
+        Ok(())
+    }
+
+    pub async fn client(region: &str, policy: &RequestPolicy) -> Result<AsgClient> {
+        Ok(AsgClient::new(&policy.aws_config(region)?.load().await))
+    }
+
+    fn from_group(group: AutoScalingGroup, region: &str) -> Self {
+        Self {
+            name: group.auto_scaling_group_name.unwrap_or_default(),
+            region: region.to_owned(),
+            desired: group.desired_capacity.unwrap_or_default(),
+            min: group.min_size.unwrap_or_default(),
+            max: group.max_size.unwrap_or_default(),
+            launch: launch(
+                group.launch_template.or_else(|| {
+                    group
+                        .mixed_instances_policy
+                        .and_then(|policy| policy.launch_template)
+                        .and_then(|template| template.launch_template_specification)
+                }),
+                group.launch_configuration_name,
+            ),
+            protect_new: group
+                .new_instances_protected_from_scale_in
+                .unwrap_or_default(),
+            instances: group
+                .instances
+                .unwrap_or_default()
+                .into_iter()
+                .map(|instance| AsgInstance {
+                    id: instance.instance_id.unwrap_or_default(),
+                    lifecycle: instance
+                        .lifecycle_state
+                        .map(|state| state.as_str().to_owned())
+                        .unwrap_or_default(),
+                    health: instance.health_status.unwrap_or_default(),
+                    zone: instance.availability_zone.unwrap_or_default(),
+                    launch: launch(instance.launch_template, instance.launch_configuration_name),
+                    protected: instance.protected_from_scale_in.unwrap_or_default(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// `name:version` of a launch template, or the launch configuration's name.
+fn launch(
+    template: Option<LaunchTemplateSpecification>,
+    configuration: Option<String>,
+) -> Option<String> {
+    match template {
+        Some(template) => {
+            let name = template
+                .launch_template_name
+                .or(template.launch_template_id)
+                .unwrap_or_default();
+            Some(match template.version {
+                Some(version) => format!("{}:{}", name, version),
+                None => name,
+            })
+        }
+        None => configuration,
+    }
+}
+
+fn tag<'a>(group: &'a AutoScalingGroup, key: &str) -> Option<&'a str> {
+    group
+        .tags
+        .as_ref()?
+        .iter()
+        .find(|tag| tag.key.as_deref() == Some(key))
+        .and_then(|tag| tag.value.as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Requests};
+    use std::sync::Arc;
+
+    /// Two groups, only the first of which belongs to cluster `test`.
+    const GROUPS: &str = r#"<DescribeAutoScalingGroupsResponse xmlns="http://autoscaling.amazonaws.com/doc/2011-01-01/">
+  <DescribeAutoScalingGroupsResult>
+    <AutoScalingGroups>
+      <member>
+        <AutoScalingGroupName>client-eu-central-1-test</AutoScalingGroupName>
+        <MinSize>0</MinSize>
+        <MaxSize>4</MaxSize>
+        <DesiredCapacity>2</DesiredCapacity>
+        <LaunchTemplate>
+          <LaunchTemplateName>client</LaunchTemplateName>
+          <Version>3</Version>
+        </LaunchTemplate>
+        <Instances>
+          <member>
+            <InstanceId>i-1</InstanceId>
+            <AvailabilityZone>eu-central-1a</AvailabilityZone>
+            <LifecycleState>InService</LifecycleState>
+            <HealthStatus>Healthy</HealthStatus>
+            <ProtectedFromScaleIn>false</ProtectedFromScaleIn>
+          </member>
+          <member>
+            <InstanceId>i-2</InstanceId>
+            <AvailabilityZone>eu-central-1b</AvailabilityZone>
+            <LifecycleState>Pending</LifecycleState>
+            <HealthStatus>Healthy</HealthStatus>
+            <ProtectedFromScaleIn>false</ProtectedFromScaleIn>
+          </member>
+        </Instances>
+        <Tags>
+          <member>
+            <ResourceId>client-eu-central-1-test</ResourceId>
+            <ResourceType>auto-scaling-group</ResourceType>
+            <Key>Cluster</Key>
+            <Value>test</Value>
+            <PropagateAtLaunch>true</PropagateAtLaunch>
+          </member>
+        </Tags>
+      </member>
+      <member>
+        <AutoScalingGroupName>client-eu-central-1-other</AutoScalingGroupName>
+        <MinSize>0</MinSize>
+        <MaxSize>1</MaxSize>
+        <DesiredCapacity>1</DesiredCapacity>
+        <Tags>
+          <member>
+            <ResourceId>client-eu-central-1-other</ResourceId>
+            <ResourceType>auto-scaling-group</ResourceType>
+            <Key>Cluster</Key>
+            <Value>other</Value>
+            <PropagateAtLaunch>true</PropagateAtLaunch>
+          </member>
+        </Tags>
+      </member>
+    </AutoScalingGroups>
+  </DescribeAutoScalingGroupsResult>
+  <ResponseMetadata><RequestId>1</RequestId></ResponseMetadata>
+</DescribeAutoScalingGroupsResponse>"#;
+
+    const SCALED: &str = r#"<SetDesiredCapacityResponse xmlns="http://autoscaling.amazonaws.com/doc/2011-01-01/">
+  <ResponseMetadata><RequestId>2</RequestId></ResponseMetadata>
+</SetDesiredCapacityResponse>"#;
+
+    /// A stand-in for the AutoScaling API.
+    async fn autoscaling() -> (RequestPolicy, Requests) {
+        let (endpoint, requests) = testing::serve(|_, request| {
+            let xml = if request.body.contains("Action=DescribeAutoScalingGroups") {
+                GROUPS
+            } else {
+                SCALED
+            };
+            ("200 OK", xml.to_owned())
+        })
+        .await;
+
+        let policy = RequestPolicy {
+            aws_endpoint: Some(Arc::from(endpoint)),
+            ..Default::default()
+        };
+        (policy, requests)
+    }
+
+    #[tokio::test]
+    async fn list_and_scale_against_endpoint() {
+        let (policy, requests) = autoscaling().await;
+
+        let asgs = BitteAsg::find_asgs("test", &["eu-central-1".to_owned()], &policy)
+            .await
+            .unwrap();
+        assert_eq!(asgs.len(), 1);
+        let asg = &asgs[0];
+        assert_eq!(asg.name, "client-eu-central-1-test");
+        assert_eq!(asg.region, "eu-central-1");
+        assert_eq!((asg.min, asg.desired, asg.max), (0, 2, 4));
+        assert_eq!(asg.launch.as_deref(), Some("client:3"));
+        assert_eq!(asg.instances.len(), 2);
+        assert_eq!(asg.in_flux(), 1);
+
+        asg.scale(3, &policy).await.unwrap();
+        let requests = requests.lock().unwrap();
+        let scale = &requests.last().unwrap().body;
+        assert!(scale.contains("Action=SetDesiredCapacity"));
+        assert!(scale.contains("AutoScalingGroupName=client-eu-central-1-test"));
+        assert!(scale.contains("DesiredCapacity=3"));
+        assert!(scale.contains("HonorCooldown=false"));
+    }
+}
@@ -0,0 +1,82 @@
+use super::endpoint::Endpoint;
+use super::policy::RequestPolicy;
+use anyhow::{Context, Result};
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+/// A health check registered on a Consul agent.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConsulCheck {
+    #[serde(rename = "Node")]
+    pub node: String,
+    #[serde(rename = "CheckID")]
+    pub id: String,
+    #[serde(rename = "Name")]
+    pub name: String,
+    /// `passing`, `warning` or `critical`
+    #[serde(rename = "Status")]
+    pub status: String,
+    #[serde(rename = "ServiceName", default)]
+    pub service: String,
+    #[serde(rename = "Output", default)]
+    pub output: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CatalogNode {
+    #[serde(rename = "Node")]
+    node: String,
+}
+
+/// The Consul catalog, through the HTTP API of the cluster.
+pub struct Consul {
+    addr: String,
+    client: Client,
+    policy: RequestPolicy,
+}
+
+impl Consul {
+    pub fn new(endpoint: &Endpoint, policy: &RequestPolicy) -> Result<Self> {
+        Ok(Self {
+            addr: endpoint.addr.clone(),
+            client: endpoint.client(policy)?,
+            policy: policy.clone(),
+        })
+    }
+
+    /// The checks of the agent at `ip`; Consul's node names are host names,
+    /// which discovery doesn't know, so the node is looked up by address.
+    pub async fn checks(&self, ip: IpAddr) -> Result<Vec<ConsulCheck>> {
+        let url = format!("{}/v1/catalog/nodes", self.addr);
+        let filter = format!("Address == \"{}\"", ip);
+        let nodes: Vec<CatalogNode> = self
+            .get(self.client.get(&url).query(&[("filter", &filter)]), &url)
+            .await?;
+
+        let mut checks = Vec::new();
+        for node in nodes {
+            let url = format!("{}/v1/health/node/{}", self.addr, node.node);
+            let found: Vec<ConsulCheck> = self.get(self.client.get(&url), &url).await?;
+            checks.extend(found);
+        }
+        Ok(checks)
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+        url: &str,
+    ) -> Result<T> {
+        self.policy
+            .send(request)
+            .await
+            .with_context(|| format!("failed to query: {}", url))?
+            .error_for_status()
+            .with_context(|| format!("failed to query: {}", url))?
+            .json::<T>()
+            .await
+            .with_context(|| format!("failed to decode response from: {}", url))
+    }
+}
@@ -0,0 +1,329 @@
+use super::policy::RequestPolicy;
+use crate::config::{ClusterConfig, EndpointConfig};
+use anyhow::{anyhow, Context, Result};
+use clap::ArgEnum;
+use log::warn;
+use openssl::asn1::Asn1Time;
+use openssl::nid::Nid;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::PKey;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use openssl::stack::Stack;
+use openssl::x509::X509;
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::{Certificate, Client, ClientBuilder, Identity, Url};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// One of the HTTP APIs served by the core nodes.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, ArgEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Service {
+    #[default]
+    Nomad,
+    Consul,
+    Vault,
+}
+
+impl Display for Service {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let service = match *self {
+            Service::Nomad => "nomad",
+            Service::Consul => "consul",
+            Service::Vault => "vault",
+        };
+        write!(f, "{}", service)
+    }
+}
+
+impl Service {
+    pub const ALL: [Service; 3] = [Service::Consul, Service::Nomad, Service::Vault];
+
+    /// The token from the service's variable, or for Vault from the file
+    /// `vault login` writes, like its CLI does.
+    fn token(&self) -> Option<String> {
+        let token = env::var(self.token_var()).ok().or_else(|| match *self {
+            Service::Vault => dirs::home_dir()
+                .and_then(|home| std::fs::read_to_string(home.join(".vault-token")).ok()),
+            _ => None,
+        })?;
+
+        let token = token.trim();
+        (!token.is_empty()).then(|| token.to_owned())
+    }
+
+    /// The variables the service's own CLI reads, in the order address, CA
+    /// certificate, client certificate and client key.
+    fn env_vars(&self) -> [&'static str; 4] {
+        match *self {
+            Service::Nomad => [
+                "NOMAD_ADDR",
+                "NOMAD_CACERT",
+                "NOMAD_CLIENT_CERT",
+                "NOMAD_CLIENT_KEY",
+            ],
+            Service::Consul => [
+                "CONSUL_HTTP_ADDR",
+                "CONSUL_CACERT",
+                "CONSUL_CLIENT_CERT",
+                "CONSUL_CLIENT_KEY",
+            ],
+            Service::Vault => [
+                "VAULT_ADDR",
+                "VAULT_CACERT",
+                "VAULT_CLIENT_CERT",
+                "VAULT_CLIENT_KEY",
+            ],
+        }
+    }
+
+    /// The variable the service's own CLI reads its token from.
+    fn token_var(&self) -> &'static str {
+        match *self {
+            Service::Nomad => "NOMAD_TOKEN",
+            Service::Consul => "CONSUL_HTTP_TOKEN",
+            Service::Vault => "VAULT_TOKEN",
+        }
+    }
+
+    fn token_header(&self) -> &'static str {
+        match *self {
+            Service::Nomad => "X-Nomad-Token",
+            Service::Consul => "X-Consul-Token",
+            Service::Vault => "X-Vault-Token",
+        }
+    }
+}
+
+/// Where and how to reach one of the cluster's APIs.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Endpoint {
+    pub service: Service,
+    pub addr: String,
+    pub cacert: Option<PathBuf>,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    /// Sent with every request; kept out of `info --json`
+    #[serde(skip)]
+    pub token: Option<String>,
+}
+
+/// The endpoints of all APIs of a cluster.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Endpoints {
+    pub nomad: Endpoint,
+    pub consul: Endpoint,
+    pub vault: Endpoint,
+}
+
+impl Endpoint {
+    /// Settings from the cluster's config take precedence, since unlike the
+    /// environment they can't accidentally belong to another cluster.
+    pub fn resolve(service: Service, domain: &str, config: Option<&EndpointConfig>) -> Self {
+        let config = config.cloned().unwrap_or_default();
+        let [addr_var, cacert_var, cert_var, key_var] = service.env_vars();
+        let from_env = |var: &str| env::var_os(var).filter(|value| !value.is_empty());
+
+        let addr = config
+            .addr
+            .or_else(|| from_env(addr_var).and_then(|addr| addr.into_string().ok()))
+            .unwrap_or_else(|| format!("https://{}.{}", service, domain));
+
+        Self {
+            service,
+            addr: normalize_addr(&addr),
+            cacert: config
+                .cacert
+                .or_else(|| from_env(cacert_var).map(PathBuf::from)),
+            client_cert: config
+                .client_cert
+                .or_else(|| from_env(cert_var).map(PathBuf::from)),
+            client_key: config
+                .client_key
+                .or_else(|| from_env(key_var).map(PathBuf::from)),
+            token: service.token(),
+        }
+    }
+
+    /// Apply the policy's timeouts, trust the configured CA bundle in
+    /// addition to the system roots, and present a client certificate if one
+    /// is configured.
+    pub fn client_builder(&self, policy: &RequestPolicy) -> Result<ClientBuilder> {
+        let mut builder = policy.client_builder();
+
+        if let Some(cacert) = &self.cacert {
+            for cert in read_ca_bundle(cacert)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => builder = builder.identity(read_identity(cert, key)?),
+            (None, None) => (),
+            _ => {
+                return Err(anyhow!(
+                    "both a client certificate and key are required for mTLS to {}",
+                    self.addr
+                ))
+            }
+        }
+
+        Ok(builder)
+    }
+
+    /// A client for the API that sends the token, if there is one.
+    pub fn client(&self, policy: &RequestPolicy) -> Result<Client> {
+        let mut headers = HeaderMap::new();
+        if let Some(token) = &self.token {
+            let mut token = HeaderValue::from_str(token)
+                .with_context(|| format!("the {} token is not a valid header", self.service))?;
+            token.set_sensitive(true);
+            headers.insert(self.service.token_header(), token);
+        }
+
+        Ok(self
+            .client_builder(policy)?
+            .default_headers(headers)
+            .gzip(true)
+            .build()?)
+    }
+
+    /// The certificate the API presents, trusted or not, to see when it
+    /// expires. This blocks for up to `timeout`.
+    pub fn peer_certificate(&self, timeout: Duration) -> Result<X509> {
+        let url = Url::parse(&self.addr).with_context(|| format!("{} is not a URL", self.addr))?;
+        let host = url
+            .host_str()
+            .with_context(|| format!("{} has no host", self.addr))?;
+        let port = url.port_or_known_default().unwrap_or(443);
+        let address = (host, port)
+            .to_socket_addrs()
+            .with_context(|| format!("failed to resolve {}", host))?
+            .next()
+            .with_context(|| format!("{} has no addresses", host))?;
+
+        let stream = TcpStream::connect_timeout(&address, timeout)
+            .with_context(|| format!("failed to connect to {}:{}", host, port))?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+
+        let mut connector = SslConnector::builder(SslMethod::tls())?;
+        connector.set_verify(SslVerifyMode::NONE);
+        let stream = connector
+            .build()
+            .configure()?
+            .verify_hostname(false)
+            .connect(host, stream)
+            .map_err(|error| anyhow!("TLS handshake with {}:{} failed: {}", host, port, error))?;
+
+        stream
+            .ssl()
+            .peer_certificate()
+            .with_context(|| format!("{}:{} presented no certificate", host, port))
+    }
+}
+
+impl Endpoints {
+    pub fn get(&self, service: Service) -> &Endpoint {
+        match service {
+            Service::Nomad => &self.nomad,
+            Service::Consul => &self.consul,
+            Service::Vault => &self.vault,
+        }
+    }
+
+    pub fn resolve(domain: &str, config: &ClusterConfig) -> Self {
+        Self {
+            nomad: Endpoint::resolve(Service::Nomad, domain, config.nomad.as_ref()),
+            consul: Endpoint::resolve(Service::Consul, domain, config.consul.as_ref()),
+            vault: Endpoint::resolve(Service::Vault, domain, config.vault.as_ref()),
+        }
+    }
+}
+
+/// Accept `host:port` like the service CLIs do, and drop a trailing slash so
+/// API paths can simply be appended.
+fn normalize_addr(addr: &str) -> String {
+    let addr = addr.trim_end_matches('/');
+    if addr.contains("://") {
+        addr.to_owned()
+    } else {
+        format!("https://{}", addr)
+    }
+}
+
+/// Every certificate in a PEM bundle; `Certificate::from_pem` would only
+/// read the first one, which for a chain like `lib/letsencrypt.pem` is the
+/// intermediate rather than the root.
+fn read_ca_bundle(path: &Path) -> Result<Vec<Certificate>> {
+    let pem = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let certs = X509::stack_from_pem(&pem)
+        .with_context(|| format!("{} is not a PEM certificate bundle", path.display()))?;
+    if certs.is_empty() {
+        return Err(anyhow!("{} contains no certificates", path.display()));
+    }
+
+    let now = Asn1Time::days_from_now(0)?;
+    let mut result = Vec::with_capacity(certs.len());
+    for cert in certs.iter() {
+        if cert.not_after() < now {
+            warn!(
+                "{}: CA certificate \"{}\" expired on {}",
+                path.display(),
+                common_name(cert),
+                cert.not_after()
+            );
+        }
+        result.push(Certificate::from_der(&cert.to_der()?)?);
+    }
+
+    Ok(result)
+}
+
+/// reqwest only takes client identities as PKCS#12 with the native TLS
+/// backend, while Bitte's certificates and (EC) keys are PEM files.
+fn read_identity(cert: &Path, key: &Path) -> Result<Identity> {
+    let cert_pem =
+        std::fs::read(cert).with_context(|| format!("failed to read {}", cert.display()))?;
+    let key_pem =
+        std::fs::read(key).with_context(|| format!("failed to read {}", key.display()))?;
+
+    let mut chain = X509::stack_from_pem(&cert_pem)
+        .with_context(|| format!("{} is not a PEM certificate", cert.display()))?
+        .into_iter();
+    let leaf = chain
+        .next()
+        .with_context(|| format!("{} contains no certificates", cert.display()))?;
+    let pkey = PKey::private_key_from_pem(&key_pem)
+        .with_context(|| format!("{} is not a PEM private key", key.display()))?;
+
+    let mut intermediates = Stack::new()?;
+    for cert in chain {
+        intermediates.push(cert)?;
+    }
+
+    let mut builder = Pkcs12::builder();
+    builder.ca(intermediates);
+    let pkcs12 = builder.build("", "bitte", &pkey, &leaf).with_context(|| {
+        format!(
+            "{} and {} are not a matching certificate and key",
+            cert.display(),
+            key.display()
+        )
+    })?;
+
+    Ok(Identity::from_pkcs12_der(&pkcs12.to_der()?, "")?)
+}
+
+fn common_name(cert: &X509) -> String {
+    cert.subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .next()
+        .and_then(|entry| entry.data().as_utf8().ok())
+        .map(|name| name.to_string())
+        .unwrap_or_default()
+}
@@ -1,21 +1,38 @@
+pub mod acl;
+pub mod age;
+pub mod asg;
+pub mod consul;
+pub mod endpoint;
 pub mod node;
+pub mod policy;
 pub mod provider;
+pub mod raft;
+pub mod snapshot;
+pub mod tunnel;
+pub mod vault;
 
-use super::nomad::{alloc::NomadAlloc, client::NomadClient};
+use super::nomad::{
+    alloc::{AllocHandle, NomadAlloc},
+    client::{ClientHandle, NomadClient, NomadClients},
+};
+use crate::config::{ClusterConfig, Config};
 use anyhow::Result;
 use clap::ArgMatches;
+use endpoint::Endpoints;
+use log::warn;
 use node::BitteNode;
 use node::BitteNodes;
+use node::Discovery;
+use node::RegionDiscovery;
+use policy::RequestPolicy;
 pub use provider::BitteProvider;
-use reqwest::{
-    header::{HeaderMap, HeaderValue},
-    Client,
-};
+use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use std::time::Duration;
 use std::time::SystemTime;
-use tokio::task::JoinHandle;
+use tokio::task::{JoinError, JoinHandle};
+use tunnel::Tunnel;
 use uuid::Uuid;
 
 pub type ClusterHandle = JoinHandle<Result<BitteCluster>>;
@@ -34,6 +51,20 @@ where
         index: &str,
         namespace: &str,
     ) -> Result<(Self::Item, NomadAlloc)>;
+    fn find_with_jobs(
+        self,
+        name: &str,
+        group: &str,
+        index: &str,
+        namespace: &str,
+        statuses: &[&str],
+    ) -> Result<Vec<(Self::Item, NomadAlloc)>>;
+    fn find_job_allocs(
+        self,
+        name: &str,
+        group: Option<&str>,
+        namespace: &str,
+    ) -> Vec<(Self::Item, NomadAlloc)>;
 }
 
 /// A description of a Bitte cluster and its nodes
@@ -43,9 +74,53 @@ pub struct BitteCluster {
     pub nodes: BitteNodes,
     pub domain: String,
     pub provider: BitteProvider,
+    pub endpoints: Endpoints,
+    /// Instance counts per region, to make incomplete discovery visible
+    pub regions: Vec<RegionDiscovery>,
+    /// Regions and APIs that could not be queried
+    pub failures: Vec<SourceFailure>,
     #[serde(skip)]
     pub nomad_api_client: Option<Arc<Client>>,
+    /// Every Nomad client, including those without an instance; `None`
+    /// without a token or if they couldn't be queried
+    #[serde(skip)]
+    pub nomad_clients: Option<NomadClients>,
+    /// Timeouts and retries for talking to the cluster after discovery
+    #[serde(skip)]
+    pub policy: RequestPolicy,
     pub ttl: SystemTime,
+    /// Keeps the forwards to the APIs open for as long as the cluster is in use
+    #[serde(skip)]
+    _tunnels: Vec<Tunnel>,
+}
+
+/// A region or API that couldn't be queried while discovering the cluster.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SourceFailure {
+    pub source: String,
+    pub error: String,
+}
+
+/// Unwrap the result of a discovery task. Unless `strict` is set, a failed
+/// source is recorded in `failures` and discovery carries on without it.
+pub(crate) fn partial<T>(
+    result: Result<Result<T>, JoinError>,
+    source: &str,
+    strict: bool,
+    failures: &mut Vec<SourceFailure>,
+) -> Result<Option<T>> {
+    match result.map_err(anyhow::Error::from).and_then(|r| r) {
+        Ok(value) => Ok(Some(value)),
+        Err(error) if strict => Err(error),
+        Err(error) => {
+            warn!("{}: {:#}", source, error);
+            failures.push(SourceFailure {
+                source: source.to_owned(),
+                error: format!("{:#}", error),
+            });
+            Ok(None)
+        }
+    }
 }
 
 impl BitteCluster {
@@ -56,64 +131,92 @@ impl BitteCluster {
             .get_one::<BitteProvider>("provider")
             .unwrap()
             .to_owned();
-
-        let nomad_api_client = match token {
-            Some(token) => {
-                let mut token = HeaderValue::from_str(&token.to_string())?;
-                token.set_sensitive(true);
-                let mut headers = HeaderMap::new();
-                headers.insert("X-Nomad-Token", token);
-                Some(Arc::new(
-                    Client::builder()
-                        .default_headers(headers)
-                        .gzip(true)
-                        .build()?,
-                ))
+        let policy = RequestPolicy::from_args(args);
+        let strict = args.is_present("strict");
+        // like the contexts, a broken config only stops a command that
+        // explicitly asked for a context out of it
+        let config = match Config::load() {
+            Ok(config) => config.cluster(&name),
+            Err(error) if args.value_source("context").is_none() => {
+                warn!("ignoring the config: {:#}", error);
+                ClusterConfig::default()
             }
-            None => None,
+            Err(error) => return Err(error),
         };
+        let mut endpoints = Endpoints::resolve(&domain, &config);
 
-        let nodes = if let Some(client) = &nomad_api_client {
-            let allocs = tokio::spawn(NomadAlloc::find_allocs(
-                Arc::clone(client),
-                domain.to_owned(),
-            ));
-
-            let client_nodes = tokio::spawn(NomadClient::find_nomad_nodes(
-                Arc::clone(client),
-                domain.to_owned(),
-            ));
-
-            tokio::spawn(BitteNode::find_nodes(
-                provider,
-                name.to_owned(),
-                Some(allocs),
-                Some(client_nodes),
-                args.clone(),
-            ))
-            .await??
+        let nodes: JoinHandle<Result<Discovery>> = tokio::spawn(BitteNode::find_nodes(
+            provider,
+            name.to_owned(),
+            args.clone(),
+            policy.clone(),
+        ));
+
+        // without public APIs, the nodes have to be known before the APIs
+        // can be reached through one of them
+        let (nodes, tunnels) = if args.is_present("tunnel") || config.tunnel {
+            let discovery = nodes.await??;
+            let tunnels = Tunnel::open_all(&name, &discovery.nodes, &mut endpoints).await?;
+            (tokio::spawn(async { Ok(discovery) }), tunnels)
         } else {
-            tokio::spawn(BitteNode::find_nodes(
-                provider,
-                name.to_owned(),
-                None,
-                None,
-                args.clone(),
-            ))
-            .await??
+            (nodes, Vec::new())
         };
 
+        // the token given on the command line or by the context wins
+        endpoints.nomad.token = token.map(|token| token.to_string());
+        let nomad_api_client = match token {
+            Some(_) => Some(Arc::new(endpoints.nomad.client(&policy)?)),
+            None => None,
+        };
+
+        let nomad: Option<(AllocHandle, ClientHandle)> = nomad_api_client.as_ref().map(|client| {
+            (
+                tokio::spawn(NomadAlloc::find_allocs(
+                    Arc::clone(client),
+                    endpoints.nomad.addr.to_owned(),
+                    policy.clone(),
+                )),
+                tokio::spawn(NomadClient::find_nomad_nodes(
+                    Arc::clone(client),
+                    endpoints.nomad.addr.to_owned(),
+                    policy.clone(),
+                )),
+            )
+        });
+
+        let mut discovery = nodes.await??;
+        let mut nomad_clients = None;
+
+        if let Some((allocs, clients)) = nomad {
+            let failures = &mut discovery.failures;
+            let allocs = partial(allocs.await, "Nomad allocations", strict, failures)?;
+            nomad_clients = partial(clients.await, "Nomad nodes", strict, failures)?;
+            BitteNode::attach_nomad(
+                &mut discovery.nodes,
+                nomad_clients.as_ref().unwrap_or(&Vec::new()),
+                &allocs.unwrap_or_default(),
+            );
+        }
+
         let cluster = Self {
             name,
             domain,
             provider,
+            endpoints,
+            regions: discovery.regions,
+            failures: discovery.failures,
             nomad_api_client,
-            nodes,
+            nomad_clients,
+            policy,
+            nodes: discovery.nodes,
             ttl: SystemTime::now()
                 .checked_add(Duration::from_secs(300))
                 .unwrap(),
+            _tunnels: tunnels,
         };
 
+        cluster.warn_partial();
+
         Ok(cluster)
     }
 
@@ -121,4 +224,18 @@ impl BitteCluster {
     pub fn init(args: ArgMatches, token: Option<Uuid>) -> ClusterHandle {
         tokio::spawn(async move { BitteCluster::new(&args, token).await })
     }
+
+    /// Tell the user on stderr which parts of the cluster are missing from
+    /// the results, so stdout stays usable for `--json`.
+    fn warn_partial(&self) {
+        if self.failures.is_empty() {
+            return;
+        }
+
+        eprintln!("WARNING: results are incomplete, the following could not be queried:");
+        for failure in self.failures.iter() {
+            eprintln!("  - {}: {}", failure.source, failure.error);
+        }
+        eprintln!("Pass --strict to fail instead.\n");
+    }
 }
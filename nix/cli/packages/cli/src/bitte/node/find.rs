@@ -1,9 +1,9 @@
-use super::BitteFind;
-use crate::nomad::alloc::NomadAlloc;
-use anyhow::{Context, Result};
+use super::{BitteFind, BitteNode, BitteNodes};
+use crate::nomad::alloc::{IndexSelector, NomadAlloc};
+use anyhow::{anyhow, Context, Result};
 use std::net::IpAddr;
 
-impl BitteFind for super::BitteNodes {
+impl BitteFind for BitteNodes {
     fn find_with_job(
         self,
         name: &str,
@@ -11,51 +11,83 @@ impl BitteFind for super::BitteNodes {
         index: &str,
         namespace: &str,
     ) -> Result<(Self::Item, NomadAlloc)> {
-        let node = self
-            .into_iter()
-            .find(|node| {
-                let client = &node.nomad_client;
-                if client.is_none() {
-                    return false;
-                };
-
-                let allocs = &client.as_ref().unwrap().allocs;
-                if allocs.is_none() || allocs.as_ref().unwrap().is_empty() {
-                    return false;
-                };
-
-                allocs.as_ref().unwrap().iter().any(|alloc| {
-                    alloc.namespace == namespace
-                        && alloc.job_id == name
-                        && alloc.task_group == group
-                        && alloc.index.get() == index.parse().ok()
-                        && alloc.status == "running"
-                })
-            })
-            .with_context(|| {
-                format!(
-                    "{}, {}, {} does not match any running nomad allocations in namespace {}",
-                    name, group, index, namespace
-                )
-            })?;
-        let alloc = node
-            .nomad_client
-            .as_ref()
-            .unwrap()
-            .allocs
-            .as_ref()
-            .unwrap()
+        let mut found = self.find_with_jobs(name, group, index, namespace, &["running"])?;
+        if found.len() > 1 {
+            return Err(anyhow!(
+                "{}, {}, {} matches {} allocations, but only a single one can be used here",
+                name,
+                group,
+                index,
+                found.len()
+            ));
+        }
+        Ok(found.remove(0))
+    }
+
+    fn find_with_jobs(
+        self,
+        name: &str,
+        group: &str,
+        index: &str,
+        namespace: &str,
+        statuses: &[&str],
+    ) -> Result<Vec<(Self::Item, NomadAlloc)>> {
+        let selector: IndexSelector = index.parse()?;
+        let allocs = node_allocs(self);
+
+        let mut found: Vec<(Self::Item, NomadAlloc)> = allocs
             .iter()
-            .find(|alloc| {
+            .filter(|(_, alloc)| {
                 alloc.namespace == namespace
                     && alloc.job_id == name
                     && alloc.task_group == group
-                    && alloc.index.get() == index.parse().ok()
+                    && selector.matches(&alloc.index)
+                    && statuses.contains(&&*alloc.status)
+            })
+            .cloned()
+            .collect();
+
+        if found.is_empty() {
+            let closest = closest_allocs(&allocs, name, group, &selector, namespace, statuses);
+            let hint = if closest.is_empty() {
+                "no similar allocations were found".to_owned()
+            } else {
+                format!("closest matching allocations:\n{}", closest.join("\n"))
+            };
+
+            return Err(anyhow!(
+                "{}, {}, {} does not match any {} nomad allocations in namespace {}\n{}",
+                name,
+                group,
+                index,
+                statuses.join("/"),
+                namespace,
+                hint
+            ));
+        }
+
+        found.sort_by_key(|(_, alloc)| alloc.index.get());
+        Ok(found)
+    }
+
+    fn find_job_allocs(
+        self,
+        name: &str,
+        group: Option<&str>,
+        namespace: &str,
+    ) -> Vec<(Self::Item, NomadAlloc)> {
+        let mut result: Vec<(Self::Item, NomadAlloc)> = node_allocs(self)
+            .into_iter()
+            .filter(|(_, alloc)| {
+                alloc.namespace == namespace
+                    && alloc.job_id == name
+                    && (group.is_none() || group == Some(alloc.task_group.as_str()))
                     && alloc.status == "running"
             })
-            .unwrap()
-            .clone();
-        Ok((node, alloc))
+            .collect();
+
+        result.sort_by_key(|(_, alloc)| (alloc.task_group.clone(), alloc.index.get()));
+        result
     }
 
     fn find_needle(self, needle: &str) -> Result<Self::Item> {
@@ -117,3 +149,86 @@ impl BitteFind for super::BitteNodes {
             .collect()
     }
 }
+
+/// Every allocation in the cluster, paired with the node it was placed on.
+fn node_allocs(nodes: BitteNodes) -> Vec<(BitteNode, NomadAlloc)> {
+    nodes
+        .into_iter()
+        .flat_map(|node| {
+            let allocs = node
+                .nomad_client
+                .as_ref()
+                .and_then(|client| client.allocs.clone())
+                .unwrap_or_default();
+
+            allocs
+                .into_iter()
+                .map(|alloc| (node.clone(), alloc))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Describe the allocations that come closest to a failed job lookup, so
+/// typos and wrong statuses are easy to spot.
+fn closest_allocs(
+    allocs: &[(BitteNode, NomadAlloc)],
+    name: &str,
+    group: &str,
+    selector: &IndexSelector,
+    namespace: &str,
+    statuses: &[&str],
+) -> Vec<String> {
+    let mut scored: Vec<(usize, &BitteNode, &NomadAlloc)> = allocs
+        .iter()
+        .filter_map(|(node, alloc)| {
+            let similar_job = alloc.job_id.contains(name) || name.contains(&*alloc.job_id);
+            if !similar_job && alloc.task_group != group {
+                return None;
+            }
+
+            let score = [
+                (alloc.job_id == name, 4),
+                (similar_job, 2),
+                (alloc.task_group == group, 2),
+                (alloc.namespace == namespace, 1),
+                (selector.matches(&alloc.index), 1),
+                (statuses.contains(&&*alloc.status), 1),
+            ]
+            .iter()
+            .filter(|(matched, _)| *matched)
+            .map(|(_, weight)| weight)
+            .sum();
+
+            Some((score, node, alloc))
+        })
+        .collect();
+
+    scored.sort_by(|(a_score, _, a), (b_score, _, b)| {
+        b_score
+            .cmp(a_score)
+            .then_with(|| a.job_id.cmp(&b.job_id))
+            .then_with(|| a.task_group.cmp(&b.task_group))
+            .then_with(|| a.index.get().cmp(&b.index.get()))
+    });
+
+    scored
+        .into_iter()
+        .take(5)
+        .map(|(_, node, alloc)| {
+            format!(
+                "  {}, {}, {} ({}) in namespace {} on {}",
+                alloc.job_id,
+                alloc.task_group,
+                alloc.index.get().unwrap_or_default(),
+                alloc.status,
+                alloc.namespace,
+                if node.name.is_empty() {
+                    &node.id
+                } else {
+                    &node.name
+                }
+            )
+        })
+        .collect()
+}
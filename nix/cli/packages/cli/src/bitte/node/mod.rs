@@ -1,23 +1,34 @@
 mod find;
 mod instance;
 mod order;
+mod orphans;
 
+pub use orphans::Orphans;
+
+use super::partial;
+use super::policy::RequestPolicy;
 use super::BitteFind;
 use super::BitteProvider;
-use crate::nomad::alloc::{AllocHandle, NomadAllocs};
-use crate::nomad::client::{ClientHandle, NomadClient};
+use super::SourceFailure;
+use crate::nomad::alloc::NomadAllocs;
+use crate::nomad::client::{NomadClient, NomadClients};
 use anyhow::{Context, Result};
-use aws_sdk_ec2::{model::Filter, Client as Ec2Client, Region};
+use aws_sdk_ec2::{
+    model::{Filter, Instance},
+    Client as Ec2Client,
+};
 use clap::ArgMatches;
+use log::info;
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::net::IpAddr;
+use tokio_stream::StreamExt;
 
 /// A Collection of Bitte Nodes
 pub type BitteNodes = Vec<BitteNode>;
 
 /// Descrition of an individual node in the cluster
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BitteNode {
     pub id: String,
     pub name: String,
@@ -34,14 +45,37 @@ pub struct BitteNode {
     pub asg: Option<String>,
 }
 
+/// How many instances were discovered in a region, and over how many pages
+/// of `DescribeInstances` results.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RegionDiscovery {
+    pub region: String,
+    pub instances: usize,
+    pub pages: usize,
+}
+
+/// Everything discovery learned about the cluster, along with the sources
+/// that could not be queried.
+#[derive(Debug)]
+pub struct Discovery {
+    pub nodes: BitteNodes,
+    pub regions: Vec<RegionDiscovery>,
+    pub failures: Vec<SourceFailure>,
+}
+
 impl BitteNode {
+    /// Core nodes run the Consul, Nomad and Vault servers; they aren't part
+    /// of an auto-scaling group.
+    pub fn is_core(&self) -> bool {
+        self.asg.is_none() && self.name.starts_with("core")
+    }
+
     pub async fn find_nodes(
         provider: BitteProvider,
         name: String,
-        allocs: Option<AllocHandle>,
-        clients: Option<ClientHandle>,
         args: ArgMatches,
-    ) -> Result<BitteNodes> {
+        policy: RequestPolicy,
+    ) -> Result<Discovery> {
         match provider {
             BitteProvider::AWS => {
                 let regions = {
@@ -57,86 +91,157 @@ impl BitteNode {
                     result
                 };
 
+                let strict = args.is_present("strict");
+                let mut failures: Vec<SourceFailure> = Vec::new();
                 let mut handles = Vec::with_capacity(regions.len());
 
-                for region_str in regions {
-                    let region = Region::new(region_str.clone());
-                    let config = aws_config::from_env().region(region).load().await;
-                    let client = Ec2Client::new(&config);
-                    let request = client.describe_instances().set_filters(Some(vec![
-                        Filter::builder()
-                            .set_name(Some("tag:Cluster".to_owned()))
-                            .set_values(Some(vec![name.to_owned()]))
-                            .build(),
-                        Filter::builder()
-                            .set_name(Some("instance-state-name".to_owned()))
-                            .set_values(Some(vec!["running".to_owned()]))
-                            .build(),
-                    ]));
-                    let response = tokio::spawn(async move {
-                        request.send().await.with_context(|| {
-                            format!(
-                                "failed to connect to ec2.{}.amazonaws.com",
-                                region_str.to_owned()
-                            )
-                        })
-                    });
-                    handles.push(response);
+                for region in regions {
+                    let handle = tokio::spawn(Self::describe_region(
+                        name.to_owned(),
+                        region.clone(),
+                        policy.clone(),
+                    ));
+                    handles.push((region, handle));
                 }
 
                 let mut result: BitteNodes = Vec::new();
+                let mut discovered: Vec<RegionDiscovery> = Vec::with_capacity(handles.len());
 
-                let allocs = if let Some(allocs) = allocs {
-                    allocs.await??
-                } else {
-                    Vec::new()
-                };
-                let clients = if let Some(clients) = clients {
-                    clients.await??
-                } else {
-                    Vec::new()
-                };
-
-                for response in handles.into_iter() {
-                    let response = response.await??;
-                    let iter = response.reservations.into_iter();
-                    let mut nodes: BitteNodes = iter
-                        .flat_map(|reservations| {
-                            reservations
-                                .into_iter()
-                                .flat_map(|reservation| reservation.instances.unwrap_or_default())
-                        })
-                        .map(|instance| {
-                            let mut node = BitteNode::from(instance);
-                            node.nomad_client = match clients
-                                .iter()
-                                .find(|client| client.address == Some(node.priv_ip))
-                            {
-                                Some(client) => {
-                                    let mut client = client.to_owned();
-                                    client.allocs = {
-                                        Some(
-                                            allocs
-                                                .iter()
-                                                .filter(|alloc| alloc.node_id == client.id)
-                                                .map(|alloc| alloc.to_owned())
-                                                .collect::<NomadAllocs>(),
-                                        )
-                                    };
-                                    Some(client)
-                                }
-                                None => None,
-                            };
-
-                            node
-                        })
-                        .collect();
+                for (region, response) in handles.into_iter() {
+                    let source = format!("EC2 in {}", region);
+                    let (instances, discovery) =
+                        match partial(response.await, &source, strict, &mut failures)? {
+                            Some(found) => found,
+                            None => continue,
+                        };
+                    info!(
+                        "discovered {} instances in {} over {} page(s)",
+                        discovery.instances, discovery.region, discovery.pages
+                    );
+                    discovered.push(discovery);
 
+                    let mut nodes: BitteNodes =
+                        instances.into_iter().map(BitteNode::from).collect();
                     result.append(&mut nodes);
                 }
 
-                Ok(result)
+                discovered.sort_by(|a, b| a.region.cmp(&b.region));
+                Ok(Discovery {
+                    nodes: result,
+                    regions: discovered,
+                    failures,
+                })
             }
         }
     }
+
+    /// Attach each node's Nomad client, and the client's allocations, by
+    /// matching the client's address against the node's private IP.
+    pub fn attach_nomad(nodes: &mut BitteNodes, clients: &NomadClients, allocs: &NomadAllocs) {
+        for node in nodes.iter_mut() {
+            // a live client wins over a dead one that had the address before
+            node.nomad_client = clients
+                .iter()
+                .filter(|client| client.address == Some(node.priv_ip))
+                .min_by_key(|client| client.is_down())
+                .map(|client| {
+                    let mut client = client.to_owned();
+                    client.allocs = Some(
+                        allocs
+                            .iter()
+                            .filter(|alloc| alloc.node_id == client.id)
+                            .map(|alloc| alloc.to_owned())
+                            .collect::<NomadAllocs>(),
+                    );
+                    client
+                });
+        }
+    }
+
+    /// The region of the node's availability zone, e.g. `eu-central-1` for
+    /// `eu-central-1a`.
+    pub fn region(&self) -> Option<String> {
+        self.zone
+            .as_deref()
+            .map(|zone| zone.trim_end_matches(|c: char| c.is_ascii_lowercase()))
+            .filter(|region| !region.is_empty())
+            .map(str::to_owned)
+    }
+
+    /// Everything EC2 says about the node's instance.
+    pub async fn describe_instance(
+        &self,
+        region: &str,
+        policy: &RequestPolicy,
+    ) -> Result<Instance> {
+        let client = Self::ec2_client(region, policy).await?;
+        let output = client
+            .describe_instances()
+            .instance_ids(&self.id)
+            .send()
+            .await
+            .with_context(|| format!("failed to connect to {}", policy.aws_host("ec2", region)))?;
+
+        output
+            .reservations
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|reservation| reservation.instances.unwrap_or_default())
+            .find(|instance| instance.instance_id.as_deref() == Some(self.id.as_str()))
+            .with_context(|| format!("EC2 doesn't know instance {} in {}", self.id, region))
+    }
+
+    pub(crate) async fn ec2_client(region: &str, policy: &RequestPolicy) -> Result<Ec2Client> {
+        Ok(Ec2Client::new(&policy.aws_config(region)?.load().await))
+    }
+
+    /// Fetch every page of running cluster instances in one region.
+    async fn describe_region(
+        name: String,
+        region_str: String,
+        policy: RequestPolicy,
+    ) -> Result<(Vec<Instance>, RegionDiscovery)> {
+        let client = Self::ec2_client(&region_str, &policy).await?;
+        let mut pages = client
+            .describe_instances()
+            .set_filters(Some(vec![
+                Filter::builder()
+                    .set_name(Some("tag:Cluster".to_owned()))
+                    .set_values(Some(vec![name]))
+                    .build(),
+                Filter::builder()
+                    .set_name(Some("instance-state-name".to_owned()))
+                    .set_values(Some(vec!["running".to_owned()]))
+                    .build(),
+            ]))
+            .into_paginator()
+            .page_size(1000)
+            .send();
+
+        let mut instances: Vec<Instance> = Vec::new();
+        let mut discovery = RegionDiscovery {
+            region: region_str,
+            instances: 0,
+            pages: 0,
+        };
+
+        while let Some(page) = pages.next().await {
+            let page = page.with_context(|| {
+                format!(
+                    "failed to connect to {}",
+                    policy.aws_host("ec2", &discovery.region)
+                )
+            })?;
+            discovery.pages += 1;
+            instances.extend(
+                page.reservations
+                    .unwrap_or_default()
+                    .into_iter()
+                    .flat_map(|reservation| reservation.instances.unwrap_or_default()),
+            );
+        }
+
+        discovery.instances = instances.len();
+        Ok((instances, discovery))
+    }
 }
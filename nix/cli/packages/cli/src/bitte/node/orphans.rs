@@ -0,0 +1,163 @@
+use super::BitteNode;
+use crate::nomad::client::{NomadClient, NomadClients};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+/// Instances and Nomad clients that don't add up, since they are only
+/// joined by private IP.
+#[derive(Debug, Serialize, Default)]
+pub struct Orphans {
+    /// Nomad clients no instance was joined with, like down ones whose
+    /// address a new instance took over
+    pub clients: NomadClients,
+    /// Instances of an auto-scaling group whose Nomad client never registered
+    pub instances: Vec<BitteNode>,
+    /// Private IPs claimed by more than one instance or live client
+    pub duplicates: Vec<DuplicateIp>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicateIp {
+    pub ip: IpAddr,
+    /// IDs of the instances with this IP
+    pub instances: Vec<String>,
+    /// Names of the live Nomad clients with this IP
+    pub clients: Vec<String>,
+}
+
+impl Orphans {
+    pub fn find(nodes: &[BitteNode], clients: &[NomadClient]) -> Self {
+        let mut orphans = Self {
+            clients: clients
+                .iter()
+                .filter(|client| {
+                    !nodes.iter().any(
+                        |node| matches!(&node.nomad_client, Some(joined) if joined.id == client.id),
+                    )
+                })
+                .cloned()
+                .collect(),
+            instances: nodes
+                .iter()
+                .filter(|node| node.asg.is_some() && node.nomad_client.is_none())
+                .cloned()
+                .collect(),
+            duplicates: Vec::new(),
+        };
+
+        // a down client may well have left its address to a new instance
+        let mut by_ip: BTreeMap<IpAddr, (Vec<String>, Vec<String>)> = BTreeMap::new();
+        for node in nodes.iter().filter(|node| !node.priv_ip.is_unspecified()) {
+            by_ip
+                .entry(node.priv_ip)
+                .or_default()
+                .0
+                .push(node.id.clone());
+        }
+        for client in clients.iter().filter(|client| !client.is_down()) {
+            if let Some(ip) = client.address {
+                by_ip
+                    .entry(ip)
+                    .or_default()
+                    .1
+                    .push(client.name.clone().unwrap_or_else(|| client.id.to_string()));
+            }
+        }
+        orphans.duplicates = by_ip
+            .into_iter()
+            .filter(|(_, (instances, clients))| instances.len() > 1 || clients.len() > 1)
+            .map(|(ip, (instances, clients))| DuplicateIp {
+                ip,
+                instances,
+                clients,
+            })
+            .collect();
+
+        orphans
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty() && self.instances.is_empty() && self.duplicates.is_empty()
+    }
+
+    /// The orphaned clients that are down, and so safe to purge.
+    pub fn dead(&self) -> impl Iterator<Item = &NomadClient> {
+        self.clients.iter().filter(|client| client.is_down())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn client(id: u128, ip: &str, status: &str) -> NomadClient {
+        NomadClient {
+            id: Uuid::from_u128(id),
+            address: Some(ip.parse().unwrap()),
+            name: Some(format!("client-{}", id)),
+            status: Some(status.to_owned()),
+            eligibility: Some("eligible".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn node(id: &str, ip: &str, nomad_client: Option<NomadClient>) -> BitteNode {
+        BitteNode {
+            id: id.to_owned(),
+            name: id.to_owned(),
+            priv_ip: ip.parse().unwrap(),
+            pub_ip: "0.0.0.0".parse().unwrap(),
+            nixos: String::new(),
+            nomad_client,
+            node_type: None,
+            zone: None,
+            asg: Some("client-asg".to_owned()),
+        }
+    }
+
+    #[test]
+    fn find_joined_clients() {
+        let live = client(1, "10.0.0.1", "ready");
+        let nodes = [node("i-1", "10.0.0.1", Some(live.clone()))];
+        let orphans = Orphans::find(&nodes, &[live]);
+        assert!(orphans.is_empty());
+    }
+
+    #[test]
+    fn find_dead_client_at_reused_ip() {
+        let dead = client(1, "10.0.0.1", "down");
+        let live = client(2, "10.0.0.1", "ready");
+        let nodes = [node("i-2", "10.0.0.1", Some(live.clone()))];
+        let orphans = Orphans::find(&nodes, &[dead, live]);
+
+        let ids: Vec<Uuid> = orphans.clients.iter().map(|client| client.id).collect();
+        assert_eq!(ids, vec![Uuid::from_u128(1)]);
+        assert_eq!(orphans.dead().count(), 1);
+        assert!(orphans.instances.is_empty());
+        // only the live client claims the address
+        assert!(orphans.duplicates.is_empty());
+    }
+
+    #[test]
+    fn find_instance_without_client() {
+        let dead = client(1, "10.0.0.1", "down");
+        let nodes = [node("i-2", "10.0.0.1", None)];
+        let orphans = Orphans::find(&nodes, &[dead]);
+        assert_eq!(orphans.clients.len(), 1);
+        assert_eq!(orphans.instances.len(), 1);
+        assert_eq!(orphans.instances[0].id, "i-2");
+    }
+
+    #[test]
+    fn find_duplicate_ips() {
+        let a = client(1, "10.0.0.1", "ready");
+        let b = client(2, "10.0.0.1", "ready");
+        let nodes = [node("i-1", "10.0.0.1", Some(a.clone()))];
+        let orphans = Orphans::find(&nodes, &[a, b]);
+        assert_eq!(orphans.clients.len(), 1);
+        assert_eq!(orphans.duplicates.len(), 1);
+        assert_eq!(orphans.duplicates[0].clients.len(), 2);
+    }
+}
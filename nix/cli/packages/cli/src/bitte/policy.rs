@@ -0,0 +1,231 @@
+use anyhow::{Context, Result};
+use aws_config::{retry::RetryConfig, timeout::TimeoutConfig, ConfigLoader};
+#[cfg(test)]
+use aws_sdk_autoscaling::Credentials;
+use aws_sdk_autoscaling::{Endpoint, Region};
+use clap::ArgMatches;
+use log::info;
+use reqwest::{Client, ClientBuilder, RequestBuilder, Response, StatusCode};
+use std::cmp::min;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The longest we back off between two attempts, however many failed.
+const MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// Timeouts and retries shared by the remote calls used to discover a cluster.
+#[derive(Debug, Clone)]
+pub struct RequestPolicy {
+    /// Limit for establishing a connection
+    pub connect_timeout: Duration,
+    /// Limit for a single attempt, from connecting until the body is read
+    pub timeout: Duration,
+    /// Attempts in total, including the first one
+    pub max_attempts: u32,
+    /// Upper bound of the first backoff, doubled on every further attempt
+    pub base_delay: Duration,
+    /// Where AWS requests go instead of the regional endpoints, e.g. a local
+    /// stand-in
+    pub aws_endpoint: Option<Arc<str>>,
+}
+
+impl Default for RequestPolicy {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(30),
+            max_attempts: 4,
+            base_delay: Duration::from_millis(250),
+            aws_endpoint: None,
+        }
+    }
+}
+
+impl RequestPolicy {
+    pub fn from_args(args: &ArgMatches) -> Self {
+        let mut policy = Self::default();
+        if let Some(timeout) = args.get_one::<Duration>("timeout") {
+            policy.timeout = *timeout;
+            policy.connect_timeout = min(policy.connect_timeout, *timeout);
+        }
+        policy.aws_endpoint = args
+            .get_one::<String>("aws-endpoint")
+            .map(|endpoint| Arc::from(endpoint.as_str()));
+        policy
+    }
+
+    /// Only the connect timeout is set on the client itself: followed logs
+    /// and large downloads legitimately outlive any per-request timeout.
+    pub fn client_builder(&self) -> ClientBuilder {
+        Client::builder().connect_timeout(self.connect_timeout)
+    }
+
+    /// The AWS SDK already retries throttling and server errors with jittered
+    /// exponential backoff, it only needs to know our limits.
+    pub fn aws_retry_config(&self) -> RetryConfig {
+        RetryConfig::standard()
+            .with_max_attempts(self.max_attempts)
+            .with_initial_backoff(self.base_delay)
+    }
+
+    pub fn aws_timeout_config(&self) -> TimeoutConfig {
+        TimeoutConfig::builder()
+            .connect_timeout(self.connect_timeout)
+            .read_timeout(self.timeout)
+            .operation_attempt_timeout(self.timeout)
+            .build()
+    }
+
+    /// What every AWS client in `region` is configured with.
+    pub fn aws_config(&self, region: &str) -> Result<ConfigLoader> {
+        let loader = aws_config::from_env()
+            .region(Region::new(region.to_owned()))
+            .retry_config(self.aws_retry_config())
+            .timeout_config(self.aws_timeout_config());
+        // tests never get to sign with the credentials of whoever runs them
+        #[cfg(test)]
+        let loader =
+            loader.credentials_provider(Credentials::new("AKIDTEST", "test", None, None, "test"));
+        match &self.aws_endpoint {
+            Some(endpoint) => {
+                let uri = endpoint
+                    .parse()
+                    .with_context(|| format!("invalid AWS endpoint: {}", endpoint))?;
+                Ok(loader.endpoint_resolver(Endpoint::immutable(uri)))
+            }
+            None => Ok(loader),
+        }
+    }
+
+    /// The host `service` is reached at in `region`, for error messages.
+    pub fn aws_host(&self, service: &str, region: &str) -> String {
+        match &self.aws_endpoint {
+            Some(endpoint) => endpoint.to_string(),
+            None => format!("{}.{}.amazonaws.com", service, region),
+        }
+    }
+
+    /// Send a request with the policy's timeout, retrying connection errors,
+    /// timeouts, throttling and server errors with jittered exponential
+    /// backoff. Once the attempts are used up, the last response or error is
+    /// returned as is.
+    pub async fn send(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        let mut attempt = 1;
+
+        loop {
+            let current = match request.try_clone() {
+                Some(current) => current.timeout(self.timeout),
+                // streaming bodies can't be replayed, so there is only one try
+                None => return request.timeout(self.timeout).send().await,
+            };
+
+            let outcome = current.send().await;
+            let reason = match &outcome {
+                Ok(response) if retry_status(response.status()) => response.status().to_string(),
+                Ok(_) => return outcome,
+                Err(error) if error.is_timeout() || error.is_connect() => error.to_string(),
+                Err(_) => return outcome,
+            };
+
+            if attempt >= self.max_attempts {
+                return outcome;
+            }
+
+            let delay = self.backoff(attempt);
+            info!(
+                "attempt {}/{} failed ({}), retrying in {:?}",
+                attempt, self.max_attempts, reason, delay
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// A random delay between half and all of the exponential backoff, so
+    /// concurrent callers don't retry in lockstep.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .base_delay
+            .checked_mul(1 << min(attempt - 1, 16))
+            .map_or(MAX_DELAY, |delay| min(delay, MAX_DELAY))
+            .as_millis() as u64;
+        Duration::from_millis(fastrand::u64(ceiling / 2..=ceiling))
+    }
+}
+
+fn retry_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Requests};
+
+    fn policy(max_attempts: u32) -> RequestPolicy {
+        RequestPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    /// An HTTP server that answers the first `failures` requests with
+    /// `status` and every later one with 200.
+    async fn flaky(failures: usize, status: &'static str) -> (String, Requests) {
+        testing::serve(move |before, _| {
+            let status = if before < failures { status } else { "200 OK" };
+            (status, String::new())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn send_retries_server_errors() {
+        let (url, requests) = flaky(2, "503 Service Unavailable").await;
+        let client = policy(4).client_builder().build().unwrap();
+        let response = policy(4).send(client.get(&url)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn send_gives_up_after_max_attempts() {
+        let (url, requests) = flaky(10, "502 Bad Gateway").await;
+        let client = policy(3).client_builder().build().unwrap();
+        let response = policy(3).send(client.get(&url)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn send_returns_client_errors() {
+        let (url, requests) = flaky(10, "404 Not Found").await;
+        let client = policy(4).client_builder().build().unwrap();
+        let response = policy(4).send(client.get(&url)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn backoff_bounds() {
+        let policy = RequestPolicy::default();
+        for attempt in 1..=40 {
+            let ceiling = min(
+                policy.base_delay * 2u32.pow(min(attempt - 1, 16)),
+                MAX_DELAY,
+            );
+            for _ in 0..100 {
+                let delay = policy.backoff(attempt);
+                assert!(
+                    delay >= ceiling / 2 && delay <= ceiling,
+                    "attempt {}: {:?} is outside of {:?}..={:?}",
+                    attempt,
+                    delay,
+                    ceiling / 2,
+                    ceiling
+                );
+            }
+        }
+    }
+}
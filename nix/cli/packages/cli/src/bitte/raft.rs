@@ -0,0 +1,341 @@
+use super::endpoint::{Endpoint, Endpoints, Service};
+use super::policy::RequestPolicy;
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info};
+use reqwest::{Client, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+/// How often the raft state is polled while waiting for quorum.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How many polls in a row have to find the cluster healthy, so a leader
+/// that's about to step down again isn't mistaken for a settled cluster.
+const STABLE_POLLS: u32 = 2;
+
+/// The raft peers of one service and who leads them.
+#[derive(Debug, Clone)]
+pub struct RaftState {
+    pub service: Service,
+    /// Hosts of the servers in the raft configuration; `None` for a Vault
+    /// that doesn't use raft storage
+    pub servers: Option<Vec<String>>,
+    /// Host of the current leader, if one is elected
+    pub leader: Option<String>,
+    /// Autopilot's verdict on the servers, where the service has one
+    pub healthy: Option<bool>,
+}
+
+impl RaftState {
+    pub fn leads(&self, host: &str) -> bool {
+        self.leader.as_deref() == Some(host)
+    }
+
+    /// Whether a leader is elected, autopilot is content and every server of
+    /// `before` is a peer again.
+    pub fn problems(&self, before: &RaftState) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.leader.is_none() {
+            problems.push(format!("{} has no leader", self.service));
+        }
+        if self.healthy == Some(false) {
+            problems.push(format!(
+                "{} autopilot reports unhealthy servers",
+                self.service
+            ));
+        }
+        if let (Some(now), Some(before)) = (&self.servers, &before.servers) {
+            for server in before.iter().filter(|server| !now.contains(server)) {
+                problems.push(format!("{} is missing raft peer {}", self.service, server));
+            }
+        }
+
+        problems
+    }
+}
+
+impl Display for RaftState {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: leader {}, {} peers",
+            self.service,
+            self.leader.as_deref().unwrap_or("none"),
+            self.servers
+                .as_ref()
+                .map(|servers| servers.len().to_string())
+                .unwrap_or_else(|| "no raft".to_owned())
+        )
+    }
+}
+
+/// The raft state of Consul, Nomad and Vault, as seen through their APIs.
+pub struct Quorum {
+    apis: Vec<(Endpoint, Client)>,
+    policy: RequestPolicy,
+}
+
+#[derive(Debug, Deserialize)]
+struct RaftConfiguration {
+    #[serde(rename = "Servers")]
+    servers: Vec<RaftServer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RaftServer {
+    #[serde(rename = "Address")]
+    address: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AutopilotHealth {
+    #[serde(rename = "Healthy")]
+    healthy: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct VaultRaftConfiguration {
+    data: VaultRaftData,
+}
+
+#[derive(Debug, Deserialize)]
+struct VaultRaftData {
+    config: VaultRaftConfig,
+}
+
+#[derive(Debug, Deserialize)]
+struct VaultRaftConfig {
+    servers: Vec<VaultRaftServer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VaultRaftServer {
+    address: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct VaultLeader {
+    ha_enabled: bool,
+    #[serde(default)]
+    leader_cluster_address: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct VaultAutopilotState {
+    healthy: bool,
+}
+
+impl Quorum {
+    pub fn new(endpoints: &Endpoints, policy: &RequestPolicy) -> Result<Self> {
+        let mut apis = Vec::with_capacity(3);
+        for endpoint in [&endpoints.consul, &endpoints.nomad, &endpoints.vault] {
+            apis.push((endpoint.clone(), endpoint.client(policy)?));
+        }
+        Ok(Self {
+            apis,
+            policy: policy.clone(),
+        })
+    }
+
+    pub async fn states(&self) -> Result<Vec<RaftState>> {
+        self.each().await.into_iter().collect()
+    }
+
+    /// The state of every service, even if some of them can't be read.
+    pub async fn each(&self) -> Vec<Result<RaftState>> {
+        let mut states = Vec::with_capacity(self.apis.len());
+        for (endpoint, client) in self.apis.iter() {
+            let state = match endpoint.service {
+                Service::Vault => self.vault_state(endpoint, client).await,
+                _ => self.state(endpoint, client).await,
+            }
+            .with_context(|| format!("failed to read the raft state of {}", endpoint.service));
+            if let Ok(state) = &state {
+                debug!("{}", state);
+            }
+            states.push(state);
+        }
+        states
+    }
+
+    /// Poll until every service is healthy compared to `before`, taken
+    /// before the node was touched, or fail after `timeout`.
+    pub async fn wait_healthy(&self, before: &[RaftState], timeout: Duration) -> Result<()> {
+        let started = Instant::now();
+        let mut stable = 0;
+
+        loop {
+            let problems: Vec<String> = match self.states().await {
+                Ok(states) => states
+                    .iter()
+                    .zip(before.iter())
+                    .flat_map(|(now, before)| now.problems(before))
+                    .collect(),
+                // the API itself may be down while its server restarts
+                Err(error) => vec![format!("{:#}", error)],
+            };
+
+            if problems.is_empty() {
+                stable += 1;
+                if stable >= STABLE_POLLS {
+                    return Ok(());
+                }
+            } else {
+                stable = 0;
+                info!("waiting for quorum: {}", problems.join("; "));
+                if started.elapsed() > timeout {
+                    return Err(anyhow!(
+                        "quorum wasn't restored within {:?}: {}",
+                        timeout,
+                        problems.join("; ")
+                    ));
+                }
+            }
+
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Consul and Nomad share the same operator and status APIs.
+    async fn state(&self, endpoint: &Endpoint, client: &Client) -> Result<RaftState> {
+        let config: RaftConfiguration = self
+            .get(
+                client,
+                &format!("{}/v1/operator/raft/configuration", endpoint.addr),
+            )
+            .await?;
+        let leader: String = self
+            .get(client, &format!("{}/v1/status/leader", endpoint.addr))
+            .await?;
+        let health = self
+            .autopilot_health(client, &endpoint.addr)
+            .await
+            .map_err(|error| debug!("{} autopilot: {:#}", endpoint.service, error))
+            .ok();
+
+        Ok(RaftState {
+            service: endpoint.service,
+            servers: Some(config.servers.iter().map(|s| host(&s.address)).collect()),
+            leader: Some(host(&leader)).filter(|leader| !leader.is_empty()),
+            healthy: health,
+        })
+    }
+
+    async fn vault_state(&self, endpoint: &Endpoint, client: &Client) -> Result<RaftState> {
+        let leader: VaultLeader = self
+            .get(client, &format!("{}/v1/sys/leader", endpoint.addr))
+            .await?;
+
+        let url = format!("{}/v1/sys/storage/raft/configuration", endpoint.addr);
+        let response = self
+            .policy
+            .send(client.get(&url))
+            .await
+            .with_context(|| format!("failed to query: {}", &url))?;
+        let servers = match response.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                return Err(anyhow!(
+                    "{} returned {}, set VAULT_TOKEN or run `vault login`",
+                    &url,
+                    response.status()
+                ))
+            }
+            status if status.is_success() => {
+                let config: VaultRaftConfiguration = response
+                    .json()
+                    .await
+                    .with_context(|| format!("failed to decode response from: {}", &url))?;
+                Some(
+                    config
+                        .data
+                        .config
+                        .servers
+                        .iter()
+                        .map(|s| host(&s.address))
+                        .collect(),
+                )
+            }
+            // other storage backends don't have a raft configuration
+            status => {
+                debug!("{} returned {}, assuming no raft storage", &url, status);
+                None
+            }
+        };
+
+        let healthy = match servers {
+            Some(_) => self
+                .get::<VaultAutopilotState>(
+                    client,
+                    &format!("{}/v1/sys/storage/raft/autopilot/state", endpoint.addr),
+                )
+                .await
+                .map_err(|error| debug!("vault autopilot: {:#}", error))
+                .ok()
+                .map(|state| state.healthy),
+            None => None,
+        };
+
+        Ok(RaftState {
+            service: endpoint.service,
+            servers,
+            leader: if leader.ha_enabled {
+                Some(host(&leader.leader_cluster_address)).filter(|leader| !leader.is_empty())
+            } else {
+                Some(host(&endpoint.addr))
+            },
+            healthy,
+        })
+    }
+
+    /// Autopilot answers 429 when it considers a server unhealthy, which must
+    /// neither be retried nor be taken as a failure to ask.
+    async fn autopilot_health(&self, client: &Client, addr: &str) -> Result<bool> {
+        let url = format!("{}/v1/operator/autopilot/health", addr);
+        let response = client
+            .get(&url)
+            .timeout(self.policy.timeout)
+            .send()
+            .await
+            .with_context(|| format!("failed to query: {}", &url))?;
+        if response.status() != StatusCode::TOO_MANY_REQUESTS {
+            response
+                .error_for_status_ref()
+                .with_context(|| format!("failed to query: {}", &url))?;
+        }
+
+        let health: AutopilotHealth = response
+            .json()
+            .await
+            .with_context(|| format!("failed to decode response from: {}", &url))?;
+        Ok(health.healthy)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, client: &Client, url: &str) -> Result<T> {
+        self.policy
+            .send(client.get(url))
+            .await
+            .with_context(|| format!("failed to query: {}", url))?
+            .error_for_status()
+            .with_context(|| format!("failed to query: {}", url))?
+            .json::<T>()
+            .await
+            .with_context(|| format!("failed to decode response from: {}", url))
+    }
+}
+
+/// The host part of a raft address like `10.0.0.1:8300` or of a URL like
+/// `https://10.0.0.1:8201`, to compare with the nodes' private IPs.
+pub(crate) fn host(address: &str) -> String {
+    let address = address.split("://").last().unwrap_or(address);
+    let address = address.split('/').next().unwrap_or(address);
+    match address.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_owned(),
+        _ => address.to_owned(),
+    }
+}
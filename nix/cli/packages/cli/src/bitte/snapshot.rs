@@ -0,0 +1,188 @@
+use super::endpoint::{Endpoint, Service};
+use super::policy::RequestPolicy;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use log::info;
+use reqwest::Body;
+use serde::Serialize;
+use std::env;
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+
+/// The same timestamp format as the snapshots `modules/hashi-snapshots.nix`
+/// takes on the servers.
+pub const TIME_FORMAT: &str = "%Y-%m-%d_%H%M%SZ";
+
+/// A snapshot of one service's state, stored locally as
+/// `<cluster>-<service>-<timestamp>.snap`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Snapshot {
+    pub cluster: String,
+    pub service: Service,
+    pub taken: DateTime<Utc>,
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// `$BITTE_SNAPSHOT_DIR`, or `bitte/snapshots` in the user's data directory.
+pub fn default_dir() -> PathBuf {
+    match env::var_os("BITTE_SNAPSHOT_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("bitte")
+            .join("snapshots"),
+    }
+}
+
+/// Where the snapshot API of a service lives.
+fn api_path(service: Service) -> &'static str {
+    match service {
+        Service::Consul => "/v1/snapshot",
+        Service::Nomad => "/v1/operator/snapshot",
+        Service::Vault => "/v1/sys/storage/raft/snapshot",
+    }
+}
+
+impl Snapshot {
+    pub fn file_name(cluster: &str, service: Service, taken: &DateTime<Utc>) -> String {
+        format!("{}-{}-{}.snap", cluster, service, taken.format(TIME_FORMAT))
+    }
+
+    /// Recognize a file named by `file_name` for `cluster`.
+    pub fn parse(cluster: &str, path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let rest = name
+            .strip_prefix(cluster)?
+            .strip_prefix('-')?
+            .strip_suffix(".snap")?;
+        let (service, taken) = rest.split_once('-')?;
+        let service = Service::ALL
+            .into_iter()
+            .find(|s| s.to_string() == service)?;
+        let taken = NaiveDateTime::parse_from_str(taken, TIME_FORMAT).ok()?;
+
+        Some(Self {
+            cluster: cluster.to_owned(),
+            service,
+            taken: Utc.from_utc_datetime(&taken),
+            path: path.to_path_buf(),
+            size: path.metadata().map(|m| m.len()).unwrap_or_default(),
+        })
+    }
+
+    /// The snapshots of `cluster` in `dir`, oldest first.
+    pub fn list(dir: &Path, cluster: &str) -> Result<Vec<Self>> {
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut result: Vec<Self> = std::fs::read_dir(dir)
+            .with_context(|| format!("failed to read {}", dir.display()))?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| Self::parse(cluster, &entry.path()))
+            .collect();
+        result.sort_by(|a, b| a.taken.cmp(&b.taken).then(a.path.cmp(&b.path)));
+
+        Ok(result)
+    }
+
+    /// Download a snapshot of the service behind `endpoint` into `dir`. The
+    /// file only gets its final name once it is complete.
+    pub async fn take(
+        endpoint: &Endpoint,
+        policy: &RequestPolicy,
+        cluster: &str,
+        dir: &Path,
+    ) -> Result<Self> {
+        let taken = Utc::now();
+        let path = dir.join(Self::file_name(cluster, endpoint.service, &taken));
+        let partial = path.with_extension("snap.partial");
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+
+        // no per-request timeout, large snapshots take a while to stream
+        let url = format!("{}{}", endpoint.addr, api_path(endpoint.service));
+        info!("saving {} to {}", url, path.display());
+        let mut response = endpoint
+            .client(policy)?
+            .get(&url)
+            .send()
+            .await
+            .with_context(|| format!("failed to query: {}", &url))?
+            .error_for_status()
+            .with_context(|| format!("failed to query: {}", &url))?;
+
+        let mut file = File::create(&partial)
+            .await
+            .with_context(|| format!("failed to create {}", partial.display()))?;
+        let mut size = 0;
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .with_context(|| format!("failed to download: {}", &url))?
+        {
+            size += chunk.len() as u64;
+            file.write_all(&chunk)
+                .await
+                .with_context(|| format!("failed to write {}", partial.display()))?;
+        }
+        file.flush().await?;
+
+        if size == 0 {
+            tokio::fs::remove_file(&partial).await.ok();
+            return Err(anyhow!("{} returned an empty snapshot", &url));
+        }
+        tokio::fs::rename(&partial, &path)
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+
+        Ok(Self {
+            cluster: cluster.to_owned(),
+            service: endpoint.service,
+            taken,
+            path,
+            size,
+        })
+    }
+
+    /// Replace the state of the service behind `endpoint` with this
+    /// snapshot. `force` lets Vault accept a snapshot of another cluster,
+    /// e.g. one whose unseal keys have changed since.
+    pub async fn restore(
+        &self,
+        endpoint: &Endpoint,
+        policy: &RequestPolicy,
+        force: bool,
+    ) -> Result<()> {
+        // snapshots can be large, so they're streamed rather than read into memory
+        let file = File::open(&self.path)
+            .await
+            .with_context(|| format!("failed to read {}", self.path.display()))?;
+
+        let client = endpoint.client(policy)?;
+        let url = format!("{}{}", endpoint.addr, api_path(endpoint.service));
+        let request = match endpoint.service {
+            Service::Vault if force => client.post(format!("{}-force", url)),
+            Service::Vault => client.post(&url),
+            Service::Consul | Service::Nomad => client.put(&url),
+        };
+
+        info!("restoring {} to {}", self.path.display(), url);
+        let response = request
+            .body(Body::wrap_stream(ReaderStream::new(file)))
+            .send()
+            .await
+            .with_context(|| format!("failed to query: {}", &url))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(anyhow!("{} returned {}: {}", &url, status, text.trim()));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+use super::endpoint::{Endpoint, Endpoints, Service};
+use super::node::BitteNode;
+use crate::cli::subs::ssh::ssh_opts;
+use anyhow::{anyhow, Context, Result};
+use clap::ArgEnum;
+use log::info;
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, TcpListener};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+
+/// How long ssh gets to authenticate and start listening.
+const OPEN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The cluster's own CA, as generated next to the ssh key by
+/// `profiles/auxiliaries/secrets.nix`; core certificates are valid for
+/// 127.0.0.1, so a tunnel can keep verifying TLS.
+const CLUSTER_CA: &str = "secrets/ca.pem";
+
+/// A service that can be reached through a tunnel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum)]
+pub enum TunnelTarget {
+    Nomad,
+    Consul,
+    Vault,
+    Grafana,
+}
+
+impl Display for TunnelTarget {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let target = match *self {
+            TunnelTarget::Nomad => "nomad",
+            TunnelTarget::Consul => "consul",
+            TunnelTarget::Vault => "vault",
+            TunnelTarget::Grafana => "grafana",
+        };
+        write!(f, "{}", target)
+    }
+}
+
+impl TunnelTarget {
+    /// The port the service listens on, on the node the tunnel goes through.
+    fn port(&self) -> u16 {
+        match *self {
+            TunnelTarget::Nomad => 4646,
+            TunnelTarget::Consul => 8500,
+            TunnelTarget::Vault => 8200,
+            TunnelTarget::Grafana => 3000,
+        }
+    }
+
+    fn scheme(&self) -> &'static str {
+        match *self {
+            TunnelTarget::Nomad | TunnelTarget::Vault => "https",
+            TunnelTarget::Consul | TunnelTarget::Grafana => "http",
+        }
+    }
+
+    fn service(service: Service) -> Self {
+        match service {
+            Service::Nomad => TunnelTarget::Nomad,
+            Service::Consul => TunnelTarget::Consul,
+            Service::Vault => TunnelTarget::Vault,
+        }
+    }
+
+    /// Grafana runs on the monitoring node, the rest on the core nodes.
+    fn node<'a>(&self, nodes: &'a [BitteNode]) -> Result<&'a BitteNode> {
+        let mut candidates: Vec<&BitteNode> = nodes
+            .iter()
+            .filter(|node| match *self {
+                TunnelTarget::Grafana => node.asg.is_none() && node.name == "monitoring",
+                _ => node.is_core(),
+            })
+            .collect();
+        candidates.sort_by(|a, b| a.name.cmp(&b.name));
+
+        candidates.into_iter().next().ok_or_else(|| match *self {
+            TunnelTarget::Grafana => anyhow!("no monitoring node found to tunnel to"),
+            _ => anyhow!("no core node found to tunnel to"),
+        })
+    }
+}
+
+/// An ssh port-forward from a local port to a service on a cluster node. The
+/// forward is closed when the tunnel is dropped.
+#[derive(Debug)]
+pub struct Tunnel {
+    pub target: TunnelTarget,
+    pub node: String,
+    pub local_port: u16,
+    ssh: Child,
+}
+
+impl Tunnel {
+    /// Forward `local_port`, or any free port, to `target` on the node that
+    /// serves it, and wait until the forward accepts connections.
+    pub async fn open(
+        cluster: &str,
+        target: TunnelTarget,
+        nodes: &[BitteNode],
+        local_port: Option<u16>,
+    ) -> Result<Self> {
+        Self::open_on(cluster, target, target.node(nodes)?, local_port).await
+    }
+
+    /// Forward every API of the cluster and point `endpoints` at the local
+    /// ends, for clusters that don't expose them publicly.
+    pub async fn open_all(
+        cluster: &str,
+        nodes: &[BitteNode],
+        endpoints: &mut Endpoints,
+    ) -> Result<Vec<Self>> {
+        let open = |service| Self::open(cluster, TunnelTarget::service(service), nodes, None);
+        let (nomad, consul, vault) = tokio::try_join!(
+            open(Service::Nomad),
+            open(Service::Consul),
+            open(Service::Vault)
+        )?;
+
+        endpoints.nomad = nomad.endpoint(&endpoints.nomad);
+        endpoints.consul = consul.endpoint(&endpoints.consul);
+        endpoints.vault = vault.endpoint(&endpoints.vault);
+        Ok(vec![nomad, consul, vault])
+    }
+
+    /// Like `open`, but to `target` on a particular node, e.g. to ask each
+    /// Vault server about itself.
+    pub async fn open_on(
+        cluster: &str,
+        target: TunnelTarget,
+        node: &BitteNode,
+        local_port: Option<u16>,
+    ) -> Result<Self> {
+        let local_port = match local_port {
+            Some(port) => port,
+            None => free_port()?,
+        };
+
+        let ssh = spawn_ssh(cluster, node.pub_ip, local_port, target.port())?;
+        let mut tunnel = Self {
+            target,
+            node: node.name.clone(),
+            local_port,
+            ssh,
+        };
+        tunnel.wait_ready().await?;
+
+        info!(
+            "forwarding {} to {} on {}",
+            tunnel.url(),
+            target,
+            tunnel.node
+        );
+        Ok(tunnel)
+    }
+
+    /// The local end of the tunnel.
+    pub fn url(&self) -> String {
+        format!("{}://127.0.0.1:{}", self.target.scheme(), self.local_port)
+    }
+
+    /// Point `endpoint` at the local end, trusting the cluster's CA unless
+    /// another one is configured.
+    pub fn endpoint(&self, endpoint: &Endpoint) -> Endpoint {
+        let cacert = endpoint.cacert.clone().or_else(|| {
+            let ca = Path::new(CLUSTER_CA);
+            ca.is_file().then(|| ca.to_path_buf())
+        });
+
+        Endpoint {
+            addr: self.url(),
+            cacert,
+            ..endpoint.clone()
+        }
+    }
+
+    /// Keep the forward open until ssh exits, e.g. on Ctrl-C.
+    pub async fn wait(mut self) -> Result<()> {
+        let status = self.ssh.wait().await?;
+        if !status.success() {
+            return Err(anyhow!("ssh to {} exited with {}", self.node, status));
+        }
+        Ok(())
+    }
+
+    async fn wait_ready(&mut self) -> Result<()> {
+        let started = Instant::now();
+
+        loop {
+            if let Some(status) = self.ssh.try_wait()? {
+                return Err(anyhow!(
+                    "ssh to {} exited with {} before the tunnel was open",
+                    self.node,
+                    status
+                ));
+            }
+            if TcpStream::connect(("127.0.0.1", self.local_port))
+                .await
+                .is_ok()
+            {
+                return Ok(());
+            }
+            if started.elapsed() > OPEN_TIMEOUT {
+                return Err(anyhow!(
+                    "the tunnel to {} on {} didn't open within {:?}",
+                    self.target,
+                    self.node,
+                    OPEN_TIMEOUT
+                ));
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+fn spawn_ssh(cluster: &str, ip: IpAddr, local_port: u16, remote_port: u16) -> Result<Child> {
+    let mut cmd = Command::new("ssh");
+    cmd.args(["-N", "-x", "-p", "22"])
+        .args(ssh_opts(cluster))
+        .args(["-o", "ExitOnForwardFailure=yes"])
+        .arg("-L")
+        .arg(format!(
+            "127.0.0.1:{}:127.0.0.1:{}",
+            local_port, remote_port
+        ))
+        .arg(format!("root@{}", ip))
+        .kill_on_drop(true);
+    info!("cmd: {:?}", cmd);
+
+    cmd.spawn().with_context(|| "ssh command failed")
+}
+
+/// Ask the OS for a port nobody listens on; ssh binds it right after.
+fn free_port() -> Result<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    Ok(listener.local_addr()?.port())
+}
@@ -0,0 +1,285 @@
+use super::endpoint::Endpoint;
+use super::policy::RequestPolicy;
+use super::raft::host;
+use anyhow::{anyhow, Context, Result};
+use reqwest::{Client, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt::{Display, Formatter};
+
+/// Without these, `/v1/sys/health` answers with an error status for every
+/// server that isn't the active one.
+const HEALTH_QUERY: &str =
+    "standbyok=true&perfstandbyok=true&sealedcode=200&uninitcode=200&drsecondarycode=200";
+
+/// What a server reports about its seal, which needs no token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealStatus {
+    #[serde(rename = "type")]
+    pub seal_type: String,
+    pub initialized: bool,
+    pub sealed: bool,
+    /// Key shares needed to unseal
+    pub t: u32,
+    /// Key shares in total
+    pub n: u32,
+    /// Key shares submitted so far
+    pub progress: u32,
+    #[serde(default)]
+    pub version: String,
+    /// Whether the keys are recovery keys of an auto-unseal, which can't
+    /// unseal a server
+    #[serde(default)]
+    pub recovery_seal: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Health {
+    pub initialized: bool,
+    pub sealed: bool,
+    pub standby: bool,
+    #[serde(default)]
+    pub performance_standby: bool,
+    #[serde(default)]
+    pub version: String,
+}
+
+/// The server's part in high availability.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    Active,
+    Standby,
+    PerfStandby,
+    Sealed,
+    Uninitialized,
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let role = match *self {
+            Role::Active => "active",
+            Role::Standby => "standby",
+            Role::PerfStandby => "perf-standby",
+            Role::Sealed => "sealed",
+            Role::Uninitialized => "uninitialized",
+        };
+        write!(f, "{}", role)
+    }
+}
+
+impl Health {
+    pub fn role(&self) -> Role {
+        if !self.initialized {
+            Role::Uninitialized
+        } else if self.sealed {
+            Role::Sealed
+        } else if self.performance_standby {
+            Role::PerfStandby
+        } else if self.standby {
+            Role::Standby
+        } else {
+            Role::Active
+        }
+    }
+}
+
+/// A server in the raft configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RaftPeer {
+    pub node_id: String,
+    pub address: String,
+    pub leader: bool,
+    pub voter: bool,
+}
+
+impl RaftPeer {
+    /// The host of its cluster address, to compare with private IPs.
+    pub fn host(&self) -> String {
+        host(&self.address)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RaftConfiguration {
+    data: RaftData,
+}
+
+#[derive(Debug, Deserialize)]
+struct RaftData {
+    config: RaftConfig,
+}
+
+#[derive(Debug, Deserialize)]
+struct RaftConfig {
+    servers: Vec<RaftPeer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct InitStatus {
+    initialized: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct InitResponse {
+    #[serde(default)]
+    keys: Vec<String>,
+    #[serde(default)]
+    keys_base64: Vec<String>,
+    #[serde(default)]
+    recovery_keys: Vec<String>,
+    #[serde(default)]
+    recovery_keys_base64: Vec<String>,
+    root_token: String,
+}
+
+/// The outcome of initializing, in the shape `vault operator init
+/// -format=json` prints it, which is what the bootstrap scripts store.
+#[derive(Debug, Serialize)]
+pub struct InitResult {
+    pub unseal_keys_b64: Vec<String>,
+    pub unseal_keys_hex: Vec<String>,
+    pub unseal_shares: u32,
+    pub unseal_threshold: u32,
+    pub recovery_keys_b64: Vec<String>,
+    pub recovery_keys_hex: Vec<String>,
+    pub recovery_keys_shares: u32,
+    pub recovery_keys_threshold: u32,
+    pub root_token: String,
+}
+
+/// The API of a single Vault server, rather than whichever is active.
+pub struct VaultServer {
+    addr: String,
+    client: Client,
+    policy: RequestPolicy,
+}
+
+impl VaultServer {
+    pub fn new(endpoint: &Endpoint, policy: &RequestPolicy) -> Result<Self> {
+        Ok(Self {
+            addr: endpoint.addr.clone(),
+            client: endpoint.client(policy)?,
+            policy: policy.clone(),
+        })
+    }
+
+    pub async fn seal_status(&self) -> Result<SealStatus> {
+        self.get(&format!("{}/v1/sys/seal-status", self.addr)).await
+    }
+
+    pub async fn health(&self) -> Result<Health> {
+        self.get(&format!("{}/v1/sys/health?{}", self.addr, HEALTH_QUERY))
+            .await
+    }
+
+    /// Standbys forward this to the active server, so any unsealed server
+    /// can answer it.
+    pub async fn raft_peers(&self) -> Result<Vec<RaftPeer>> {
+        let url = format!("{}/v1/sys/storage/raft/configuration", self.addr);
+        let response = self
+            .policy
+            .send(self.client.get(&url))
+            .await
+            .with_context(|| format!("failed to query: {}", &url))?;
+        match response.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(anyhow!(
+                "{} returned {}, set VAULT_TOKEN or run `vault login`",
+                &url,
+                response.status()
+            )),
+            _ => {
+                let config: RaftConfiguration = response
+                    .error_for_status()
+                    .with_context(|| format!("failed to query: {}", &url))?
+                    .json()
+                    .await
+                    .with_context(|| format!("failed to decode response from: {}", &url))?;
+                Ok(config.data.config.servers)
+            }
+        }
+    }
+
+    pub async fn initialized(&self) -> Result<bool> {
+        let init: InitStatus = self.get(&format!("{}/v1/sys/init", self.addr)).await?;
+        Ok(init.initialized)
+    }
+
+    /// Initialize Vault, once for the whole cluster. With an auto-unseal,
+    /// the shares are recovery keys rather than unseal keys.
+    pub async fn init(&self, shares: u32, threshold: u32, recovery: bool) -> Result<InitResult> {
+        let url = format!("{}/v1/sys/init", self.addr);
+        let body = if recovery {
+            json!({ "recovery_shares": shares, "recovery_threshold": threshold })
+        } else {
+            json!({ "secret_shares": shares, "secret_threshold": threshold })
+        };
+
+        // not retried, a second attempt would only find it initialized
+        let response = self
+            .client
+            .put(&url)
+            .timeout(self.policy.timeout)
+            .json(&body)
+            .send()
+            .await
+            .with_context(|| format!("failed to query: {}", &url))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(anyhow!("{} returned {}: {}", &url, status, text.trim()));
+        }
+
+        let result: InitResponse = response
+            .json()
+            .await
+            .with_context(|| format!("failed to decode response from: {}", &url))?;
+        Ok(InitResult {
+            unseal_shares: if recovery { 0 } else { shares },
+            unseal_threshold: if recovery { 0 } else { threshold },
+            recovery_keys_shares: if recovery { shares } else { 0 },
+            recovery_keys_threshold: if recovery { threshold } else { 0 },
+            unseal_keys_b64: result.keys_base64,
+            unseal_keys_hex: result.keys,
+            recovery_keys_b64: result.recovery_keys_base64,
+            recovery_keys_hex: result.recovery_keys,
+            root_token: result.root_token,
+        })
+    }
+
+    /// Submit one key share; the server is unsealed once enough were.
+    pub async fn unseal(&self, key: &str) -> Result<SealStatus> {
+        let url = format!("{}/v1/sys/unseal", self.addr);
+        let response = self
+            .client
+            .put(&url)
+            .timeout(self.policy.timeout)
+            .json(&json!({ "key": key }))
+            .send()
+            .await
+            .with_context(|| format!("failed to query: {}", &url))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(anyhow!("{} returned {}: {}", &url, status, text.trim()));
+        }
+
+        response
+            .json()
+            .await
+            .with_context(|| format!("failed to decode response from: {}", &url))
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        self.policy
+            .send(self.client.get(url))
+            .await
+            .with_context(|| format!("failed to query: {}", url))?
+            .error_for_status()
+            .with_context(|| format!("failed to query: {}", url))?
+            .json::<T>()
+            .await
+            .with_context(|| format!("failed to decode response from: {}", url))
+    }
+}
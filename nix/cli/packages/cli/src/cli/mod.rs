@@ -1,9 +1,12 @@
 pub mod opts;
 pub mod subs;
 
+use anyhow::Result;
 use clap::App;
 use clap_complete::{generate, Generator};
 use std::env;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
 
 pub fn init_log(level: u64) {
     let level = match level {
@@ -20,3 +23,56 @@ pub(crate) async fn completions<G: Generator>(gen: G, mut app: App<'_>) {
     let cli = &mut app;
     generate(gen, cli, cli.get_name().to_string(), &mut std::io::stdout())
 }
+
+/// Ask a yes/no question on the terminal; anything but 'y' or 'yes' is a no.
+pub(crate) fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Read a line from the terminal without echoing it, e.g. a key share.
+pub(crate) fn read_secret(prompt: &str) -> Result<String> {
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+
+    // stty works on the terminal it gets as stdin, which is inherited
+    let hidden = Command::new("stty")
+        .arg("-echo")
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false);
+    let mut secret = String::new();
+    let read = io::stdin().read_line(&mut secret);
+    if hidden {
+        Command::new("stty").arg("echo").status().ok();
+        eprintln!();
+    }
+    read?;
+
+    Ok(secret.trim().to_owned())
+}
+
+/// The value of a long option if it's on the command line, for the few
+/// decisions that have to be made before clap parses the arguments.
+pub(crate) fn arg_value(args: &[String], name: &str) -> Option<String> {
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            break;
+        } else if arg == name {
+            return iter.next().cloned();
+        } else if let Some(value) = arg
+            .strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            return Some(value.to_owned());
+        }
+    }
+    None
+}
@@ -1,6 +1,8 @@
 use super::subs::SubCommands;
 use crate::bitte::BitteProvider;
 use clap::{ArgSettings, Parser};
+use reqwest::Url;
+use std::time::Duration;
 use uuid::Uuid;
 
 #[derive(Parser)]
@@ -9,6 +11,12 @@ pub struct Bitte {
     #[clap(short, long, parse(from_occurrences), global = true, env = "RUST_LOG")]
     /// set log level: 'unset' is 'warn', '-v' is 'info', '-vv' is 'debug', ...
     verbose: i32,
+    #[clap(long, global = true, env = "BITTE_CONTEXT", value_name = "NAME")]
+    /// use this context from the config file instead of the current one
+    context: Option<String>,
+    #[clap(long, global = true, env = "BITTE_FLAKE", value_name = "REF")]
+    /// take the cluster's name, domain and regions from this flake, e.g. '.'
+    flake: Option<String>,
     #[clap(subcommand)]
     commands: SubCommands,
 }
@@ -42,6 +50,24 @@ pub struct Globals {
     )]
     /// Regions containing Nomad clients
     aws_asg_regions: Option<Vec<String>>,
+    #[clap(long, env = "BITTE_AWS_ENDPOINT", value_name = "URL", value_parser = parse_endpoint)]
+    /// Send AWS requests here instead of to the regional endpoints, e.g. to a local stand-in
+    aws_endpoint: Option<String>,
+    #[clap(long, env = "BITTE_STRICT")]
+    /// Fail if any region or API can't be queried, instead of showing partial results
+    strict: bool,
+    #[clap(
+        long,
+        env = "BITTE_TIMEOUT",
+        value_name = "DURATION",
+        default_value = "30s",
+        value_parser = humantime::parse_duration
+    )]
+    /// Give up on a remote call after this long, per attempt, e.g. '1m'
+    timeout: Duration,
+    #[clap(long, env = "BITTE_TUNNEL")]
+    /// Reach Nomad, Consul and Vault through ssh tunnels to a core node, for clusters without public APIs
+    tunnel: bool,
 }
 
 #[derive(Parser, Default)]
@@ -56,3 +82,11 @@ pub struct Nomad {
     /// The Nomad token used to query node information
     nomad: Option<Uuid>,
 }
+
+fn parse_endpoint(endpoint: &str) -> Result<String, String> {
+    let url = Url::parse(endpoint).map_err(|error| error.to_string())?;
+    if !matches!(url.scheme(), "http" | "https") || !url.has_host() {
+        return Err("expected an http or https URL".to_owned());
+    }
+    Ok(endpoint.trim_end_matches('/').to_owned())
+}
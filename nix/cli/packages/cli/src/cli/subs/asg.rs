@@ -0,0 +1,162 @@
+use crate::bitte::asg::BitteAsg;
+use crate::bitte::{BitteCluster, ClusterHandle};
+use crate::cli;
+use anyhow::{anyhow, Result};
+use clap::ArgMatches;
+use prettytable::{cell, format, row, Table};
+use std::collections::BTreeMap;
+use std::io;
+
+/// The cluster's auto-scaling groups in every region it was discovered in.
+pub(crate) async fn find(cluster: &BitteCluster) -> Result<Vec<BitteAsg>> {
+    let regions: Vec<String> = cluster
+        .regions
+        .iter()
+        .map(|region| region.region.clone())
+        .collect();
+    BitteAsg::find_asgs(&cluster.name, &regions, &cluster.policy).await
+}
+
+/// A line like `2 desired (min 1, max 3): 1 InService, 1 Pending`.
+pub(crate) fn summary(asg: &BitteAsg) -> String {
+    let mut states: BTreeMap<&str, usize> = BTreeMap::new();
+    for instance in asg.instances.iter() {
+        *states.entry(instance.lifecycle.as_str()).or_default() += 1;
+    }
+    let states: Vec<String> = states
+        .iter()
+        .map(|(state, count)| format!("{} {}", count, state))
+        .collect();
+    let states = if states.is_empty() {
+        "no instances".to_owned()
+    } else {
+        states.join(", ")
+    };
+    format!(
+        "{} desired (min {}, max {}): {}",
+        asg.desired, asg.min, asg.max, states
+    )
+}
+
+pub async fn list(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let json = sub.is_present("json");
+    let cluster = cluster.await??;
+    let asgs = find(&cluster).await?;
+
+    if json {
+        let stdout = io::stdout();
+        serde_json::to_writer_pretty(stdout.lock(), &asgs)?;
+        println!();
+        return Ok(());
+    }
+
+    if asgs.is_empty() {
+        println!("No auto-scaling groups tagged Cluster={}.", cluster.name);
+        return Ok(());
+    }
+
+    for asg in asgs.iter() {
+        println!("{} ({}): {}", asg.name, asg.region, summary(asg));
+        println!(
+            "  launches {}{}",
+            asg.launch.as_deref().unwrap_or("-"),
+            if asg.protect_new {
+                ", protected from scale-in"
+            } else {
+                ""
+            }
+        );
+
+        if !asg.instances.is_empty() {
+            let mut table = Table::new();
+            table.set_format(*format::consts::FORMAT_CLEAN);
+            table.add_row(row![b =>
+                "INSTANCE",
+                "NAME",
+                "ZONE",
+                "LIFECYCLE",
+                "HEALTH",
+                "LAUNCH",
+                "PROTECTED",
+            ]);
+            for instance in asg.instances.iter() {
+                let name = cluster
+                    .nodes
+                    .iter()
+                    .find(|node| node.id == instance.id)
+                    .map(|node| node.name.as_str())
+                    .unwrap_or("-");
+                // instances started before the group's last change
+                let outdated = instance.launch.is_some() && instance.launch != asg.launch;
+                table.add_row(row![
+                    instance.id,
+                    name,
+                    instance.zone,
+                    instance.lifecycle,
+                    instance.health,
+                    format!(
+                        "{}{}",
+                        instance.launch.as_deref().unwrap_or("-"),
+                        if outdated { " (outdated)" } else { "" }
+                    ),
+                    if instance.protected { "yes" } else { "no" },
+                ]);
+            }
+            table.printstd();
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+pub async fn scale(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let name = sub.get_one::<String>("group").unwrap();
+    let desired = *sub.get_one::<u32>("desired").unwrap() as i32;
+    let yes = sub.is_present("yes");
+    let cluster = cluster.await??;
+
+    let asg = find(&cluster)
+        .await?
+        .into_iter()
+        .find(|asg| &asg.name == name)
+        .ok_or_else(|| {
+            anyhow!(
+                "no auto-scaling group {} tagged Cluster={}",
+                name,
+                cluster.name
+            )
+        })?;
+
+    if desired < asg.min || desired > asg.max {
+        return Err(anyhow!(
+            "{} can only be scaled between {} and {} instances",
+            asg.name,
+            asg.min,
+            asg.max
+        ));
+    }
+    if desired == asg.desired {
+        eprintln!("{} already wants {} instances.", asg.name, desired);
+        return Ok(());
+    }
+
+    println!("{} ({}): {}", asg.name, asg.region, summary(&asg));
+    if desired < asg.desired {
+        eprintln!(
+            "Scaling in terminates instances of AWS's choosing without draining their Nomad clients."
+        );
+    }
+    let question = format!(
+        "Scale {} in {} from {} to {} instances?",
+        asg.name, asg.region, asg.desired, desired
+    );
+    if !yes && !cli::confirm(&question)? {
+        return Err(anyhow!("aborted"));
+    }
+
+    asg.scale(desired, &cluster.policy).await?;
+    eprintln!("==> {} now wants {} instances", asg.name, desired);
+
+    Ok(())
+}
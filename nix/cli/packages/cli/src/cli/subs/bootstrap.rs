@@ -0,0 +1,369 @@
+use super::ssh::ssh_opts;
+use super::vault::{core_nodes, unseal_nodes};
+use crate::bitte::endpoint::Service;
+use crate::bitte::node::BitteNode;
+use crate::bitte::vault::VaultServer;
+use crate::bitte::{acl, age, BitteCluster, ClusterHandle};
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use clap::ArgMatches;
+use log::{info, warn};
+use serde_json::json;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// How the results are encrypted: with age for prem clusters, or with sops
+/// and a KMS key for AWS ones.
+enum Store {
+    Age(PathBuf),
+    Sops(String),
+}
+
+impl Store {
+    /// Where each service's secret goes in the encrypted directory; the
+    /// layouts the terraform scripts read them from. Consul's management
+    /// token is generated along with the cluster's secrets and pre-seeded
+    /// into its servers, so it's only ever read from there.
+    fn path(&self, service: Service) -> &'static str {
+        match (self, service) {
+            (Store::Age(_), Service::Vault) => "vault/vault.enc.json",
+            (Store::Age(_), Service::Consul) => "consul/token-initial-management.age",
+            (Store::Age(_), Service::Nomad) => "nomad/nomad.bootstrap.enc.json",
+            (Store::Sops(_), Service::Vault) => "vault.enc.json",
+            (Store::Sops(_), Service::Consul) => "consul-core.json",
+            (Store::Sops(_), Service::Nomad) => "nomad.bootstrap.enc.json",
+        }
+    }
+
+    /// Encrypt `content` next to its final path first, so an existing file
+    /// is only replaced, and kept with a timestamp, once that worked.
+    fn write(&self, dir: &Path, service: Service, content: &[u8]) -> Result<PathBuf> {
+        self.replace(dir, service, |partial| match self {
+            Store::Age(identity) => age::encrypt(identity, content, partial),
+            Store::Sops(kms) => sops_encrypt(kms, content, partial),
+        })
+    }
+
+    /// Like `write`, for content that is encrypted already.
+    fn copy(&self, dir: &Path, service: Service, encrypted: &[u8]) -> Result<PathBuf> {
+        self.replace(dir, service, |partial| {
+            std::fs::write(partial, encrypted)
+                .with_context(|| format!("failed to write {}", partial.display()))
+        })
+    }
+
+    fn replace(
+        &self,
+        dir: &Path,
+        service: Service,
+        write: impl FnOnce(&Path) -> Result<()>,
+    ) -> Result<PathBuf> {
+        let path = dir.join(self.path(service));
+        let partial = PathBuf::from(format!("{}.tmp", path.display()));
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+
+        write(&partial)?;
+        let written = partial.metadata().map(|m| m.len()).unwrap_or_default();
+        if written == 0 {
+            std::fs::remove_file(&partial).ok();
+            return Err(anyhow!("encrypting {} wrote nothing", path.display()));
+        }
+
+        if path.exists() {
+            let backup = format!("{}-{}", path.display(), Utc::now().format("%F-%T"));
+            std::fs::rename(&path, &backup)
+                .with_context(|| format!("failed to move {} aside", path.display()))?;
+            warn!("kept the previous {} as {}", path.display(), backup);
+        }
+        std::fs::rename(&partial, &path)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+
+        Ok(path)
+    }
+
+    /// What the on-node unit of `profiles/bootstrap` leaves behind for
+    /// `service` on AWS clusters, which sops encrypt, or on prem ones, which
+    /// encrypt to the node's host key. The command prints nothing until the
+    /// unit is done.
+    fn unit_output(&self, service: Service) -> (&'static str, &'static str) {
+        match (self, service) {
+            (Store::Sops(_), Service::Vault) => (
+                "vault-bootstrap.service",
+                "if [ -s /var/lib/vault/vault.enc.json ]; then cat /var/lib/vault/vault.enc.json; fi",
+            ),
+            (Store::Age(_), Service::Vault) => (
+                "vault-bootstrap.service",
+                "if [ -s /var/lib/vault/vault-bootstrap.json.age ]; then rage -i /etc/ssh/ssh_host_ed25519_key -d /var/lib/vault/vault-bootstrap.json.age; fi",
+            ),
+            (_, _) => (
+                "nomad-bootstrap.service",
+                "if [ -s /var/lib/nomad/bootstrap.token ]; then cat /var/lib/nomad/bootstrap.token; fi",
+            ),
+        }
+    }
+}
+
+fn token_content(token: &str) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec_pretty(&json!({ "token": token }))?)
+}
+
+fn sops_encrypt(kms: &str, content: &[u8], file: &Path) -> Result<()> {
+    let output =
+        File::create(file).with_context(|| format!("failed to create {}", file.display()))?;
+    let mut cmd = Command::new("sops");
+    cmd.args([
+        "--input-type",
+        "json",
+        "--output-type",
+        "json",
+        "--kms",
+        kms,
+    ])
+    .args(["--encrypt", "/dev/stdin"])
+    .stdin(Stdio::piped())
+    .stdout(output);
+    info!("cmd: {:?}", cmd);
+
+    let mut child = cmd.spawn().with_context(|| "sops command failed")?;
+    std::io::Write::write_all(&mut child.stdin.take().expect("stdin is piped"), content)?;
+    let status = child
+        .wait()
+        .with_context(|| "sops command didn't finish?")?;
+    if !status.success() {
+        return Err(anyhow!(
+            "encrypting {} failed with {}",
+            file.display(),
+            status
+        ));
+    }
+    Ok(())
+}
+
+/// Run `command` on `node` and return what it printed.
+fn remote(cluster: &str, node: &BitteNode, command: &str) -> Result<String> {
+    let mut cmd = Command::new("ssh");
+    cmd.args(["-x", "-p", "22"])
+        .args(ssh_opts(cluster))
+        .arg(format!("root@{}", node.pub_ip))
+        .arg(command);
+    info!("cmd: {:?}", cmd);
+
+    let output = cmd.output().with_context(|| "ssh command failed")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "`{}` on {} failed with {}: {}",
+            command,
+            node.name,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8(output.stdout)
+        .with_context(|| format!("`{}` on {} didn't print text", command, node.name))
+}
+
+/// Whether `unit` bootstraps the service on `node` by itself; bootstrapping
+/// it from here too would race it.
+fn unit_enabled(cluster: &str, node: &BitteNode, unit: &str) -> Result<bool> {
+    let command = format!(
+        "systemctl is-enabled --quiet {} && echo enabled || true",
+        unit
+    );
+    Ok(remote(cluster, node, &command)?.trim() == "enabled")
+}
+
+/// Store what the on-node unit bootstrapped `service` with, like the
+/// terraform provisioner in `modules/terraform.nix` does. Returns false
+/// while the unit isn't done yet.
+fn reuse(
+    cluster: &BitteCluster,
+    node: &BitteNode,
+    dir: &Path,
+    store: &Store,
+    service: Service,
+) -> Result<bool> {
+    let path = dir.join(store.path(service));
+    let (unit, command) = store.unit_output(service);
+    if path.exists() {
+        println!(
+            "{}: bootstrapped by {} on {}, already stored in {}",
+            service,
+            unit,
+            node.name,
+            path.display()
+        );
+        return Ok(true);
+    }
+
+    let output = remote(&cluster.name, node, command)?;
+    if output.trim().is_empty() {
+        println!(
+            "{}: {} on {} isn't done yet, run this again once it is",
+            service, unit, node.name
+        );
+        return Ok(false);
+    }
+    let path = match (store, service) {
+        (Store::Sops(_), Service::Vault) => store.copy(dir, service, output.as_bytes())?,
+        (_, Service::Vault) => store.write(dir, service, output.as_bytes())?,
+        (_, _) => {
+            let content = token_content(output.trim())?;
+            store.write(dir, service, &content)?
+        }
+    };
+    println!(
+        "{}: bootstrapped by {} on {}, stored in {}",
+        service,
+        unit,
+        node.name,
+        path.display()
+    );
+    Ok(true)
+}
+
+/// Initialize Vault and bootstrap the ACLs of Nomad, skipping whatever was
+/// done before, and store what they hand out exactly once. Where the core
+/// nodes bootstrap them by themselves, their results are stored instead.
+pub async fn bootstrap(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let dir = sub.get_one::<PathBuf>("dir").unwrap();
+    let shares = *sub.get_one::<u32>("key-shares").unwrap();
+    let threshold = *sub.get_one::<u32>("key-threshold").unwrap();
+    let store = match sub.get_one::<String>("kms") {
+        Some(kms) => Store::Sops(kms.to_owned()),
+        None => Store::Age(sub.get_one::<PathBuf>("identity").unwrap().to_owned()),
+    };
+
+    if threshold == 0 || threshold > shares {
+        return Err(anyhow!(
+            "--key-threshold has to be between 1 and --key-shares ({})",
+            shares
+        ));
+    }
+    if let Store::Age(identity) = &store {
+        if !identity.is_file() {
+            return Err(anyhow!(
+                "the age identity {} doesn't exist, pass --identity or --kms",
+                identity.display()
+            ));
+        }
+    }
+
+    let cluster = cluster.await??;
+    // where the on-node units do their work, like the terraform provisioner
+    let node = core_nodes(&cluster, Vec::new())?.remove(0);
+
+    eprintln!("==> vault");
+    let (unit, _) = store.unit_output(Service::Vault);
+    if unit_enabled(&cluster.name, &node, unit)? {
+        reuse(&cluster, &node, dir, &store, Service::Vault)?;
+    } else {
+        init_vault(&cluster, dir, &store, shares, threshold).await?;
+    }
+
+    eprintln!("==> consul");
+    let path = dir.join(store.path(Service::Consul));
+    if path.exists() {
+        println!(
+            "consul: ACLs use the pre-seeded management token in {}",
+            path.display()
+        );
+    } else {
+        warn!(
+            "the Consul management token is pre-seeded from {}, which doesn't exist",
+            path.display()
+        );
+    }
+
+    eprintln!("==> nomad");
+    let (unit, _) = store.unit_output(Service::Nomad);
+    if unit_enabled(&cluster.name, &node, unit)? {
+        reuse(&cluster, &node, dir, &store, Service::Nomad)?;
+    } else {
+        match acl::bootstrap(&cluster.endpoints.nomad, &cluster.policy).await? {
+            Some(token) => {
+                let content = token_content(&token)?;
+                let path = store
+                    .write(dir, Service::Nomad, &content)
+                    .map_err(|error| lost(&content, error))?;
+                println!(
+                    "nomad: ACLs bootstrapped, management token stored in {}",
+                    path.display()
+                );
+            }
+            None => {
+                println!("nomad: ACLs already bootstrapped");
+                missing(dir, &store, Service::Nomad);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn init_vault(
+    cluster: &BitteCluster,
+    dir: &Path,
+    store: &Store,
+    shares: u32,
+    threshold: u32,
+) -> Result<()> {
+    let server = VaultServer::new(&cluster.endpoints.vault, &cluster.policy)?;
+    if server.initialized().await? {
+        println!("vault: already initialized");
+        missing(dir, store, Service::Vault);
+        return Ok(());
+    }
+
+    let seal = server.seal_status().await?;
+    let recovery = seal.seal_type != "shamir";
+    let result = server.init(shares, threshold, recovery).await?;
+    let content = serde_json::to_vec_pretty(&result)?;
+    let path = store
+        .write(dir, Service::Vault, &content)
+        .map_err(|error| lost(&content, error))?;
+    println!(
+        "vault: initialized, keys and root token stored in {}",
+        path.display()
+    );
+
+    if !recovery {
+        let mut keys = result.unseal_keys_b64.clone();
+        let unsealed = match core_nodes(cluster, Vec::new()) {
+            Ok(nodes) => unseal_nodes(cluster, &nodes, &mut keys, false).await,
+            Err(error) => Err(error),
+        };
+        if let Err(error) = unsealed {
+            warn!("{:#}", error);
+            eprintln!(
+                "Vault is still sealed, unseal it with `bitte vault unseal -k {}`",
+                path.display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Point out a secret that was handed out before, but isn't stored here.
+fn missing(dir: &Path, store: &Store, service: Service) {
+    let path = dir.join(store.path(service));
+    if !path.exists() {
+        warn!(
+            "{} was bootstrapped before, but {} doesn't exist",
+            service,
+            path.display()
+        );
+    }
+}
+
+/// The secret can't be asked for again, so if it couldn't be stored, it
+/// has to be shown rather than lost.
+fn lost(content: &[u8], error: anyhow::Error) -> anyhow::Error {
+    eprintln!("Storing the secret failed, keep it safe by hand:");
+    eprintln!("{}", String::from_utf8_lossy(content));
+    error
+}
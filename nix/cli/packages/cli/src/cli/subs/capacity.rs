@@ -0,0 +1,409 @@
+use crate::bitte::{partial, BitteCluster, ClusterHandle, SourceFailure};
+use crate::nomad::alloc::NomadAlloc;
+use crate::nomad::node::{NodeResources, NomadNode};
+use anyhow::{anyhow, Result};
+use clap::ArgMatches;
+use prettytable::{cell, format, row, Table};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Arc;
+
+/// CPU in MHz, memory and disk in MB.
+#[derive(Debug, Serialize, Clone, Copy, Default)]
+pub(crate) struct Resources {
+    pub cpu: u64,
+    pub memory: u64,
+    pub disk: u64,
+}
+
+impl Resources {
+    pub fn add(&mut self, other: Resources) {
+        self.cpu += other.cpu;
+        self.memory += other.memory;
+        self.disk += other.disk;
+    }
+
+    pub fn saturating_sub(&self, other: Resources) -> Resources {
+        Resources {
+            cpu: self.cpu.saturating_sub(other.cpu),
+            memory: self.memory.saturating_sub(other.memory),
+            disk: self.disk.saturating_sub(other.disk),
+        }
+    }
+
+    /// The first dimension that's short of what's needed.
+    pub fn exhausted(&self, need: Resources) -> Option<&'static str> {
+        if need.memory > self.memory {
+            Some("memory")
+        } else if need.cpu > self.cpu {
+            Some("cpu")
+        } else if need.disk > self.disk {
+            Some("disk")
+        } else {
+            None
+        }
+    }
+
+    /// What the scheduler may hand out: the node's resources minus those
+    /// reserved for the system.
+    pub fn allocatable(node: &NomadNode) -> Resources {
+        let total = Resources::from(&node.resources.clone().unwrap_or_default());
+        let reserved = Resources::from(&node.reserved.clone().unwrap_or_default());
+        total.saturating_sub(reserved)
+    }
+
+    /// What the allocation has set aside, while it holds on to it.
+    pub fn used_by(alloc: &NomadAlloc) -> Resources {
+        match (&alloc.resources, alloc.status.as_str()) {
+            (Some(resources), "pending" | "running") => Resources {
+                cpu: resources.cpu(),
+                memory: resources.memory(),
+                disk: resources.disk(),
+            },
+            _ => Resources::default(),
+        }
+    }
+}
+
+impl From<&NodeResources> for Resources {
+    fn from(resources: &NodeResources) -> Self {
+        Resources {
+            cpu: resources.cpu.cpu_shares,
+            memory: resources.memory.memory_mb,
+            disk: resources.disk.disk_mb,
+        }
+    }
+}
+
+/// A Nomad client with what it can take and what it holds.
+#[derive(Debug, Clone)]
+pub(crate) struct ClientCapacity {
+    pub node: NomadNode,
+    pub asg: Option<String>,
+    pub allocatable: Resources,
+    pub used: Resources,
+    /// The allocations that hold on to resources
+    pub allocs: Vec<NomadAlloc>,
+}
+
+impl ClientCapacity {
+    /// Only ready, eligible clients that aren't draining take new work.
+    pub fn eligible(&self) -> bool {
+        self.node.status == "ready" && self.node.eligibility == "eligible" && !self.node.drain
+    }
+
+    pub fn free(&self) -> Resources {
+        self.allocatable.saturating_sub(self.used)
+    }
+}
+
+/// Fetch the resources of every Nomad client of the cluster's instances.
+/// Unless `strict` is set, a client that can't be queried is recorded in
+/// `failures` and left out.
+pub(crate) async fn clients(
+    cluster: &BitteCluster,
+    strict: bool,
+    failures: &mut Vec<SourceFailure>,
+) -> Result<Vec<ClientCapacity>> {
+    let api = cluster
+        .nomad_api_client
+        .clone()
+        .ok_or_else(|| anyhow!("a Nomad token is required to read the clients' resources"))?;
+
+    let mut handles = Vec::new();
+    for node in cluster.nodes.iter() {
+        let client = match &node.nomad_client {
+            Some(client) => client.clone(),
+            None => continue,
+        };
+        let handle = tokio::spawn(NomadNode::get(
+            Arc::clone(&api),
+            cluster.endpoints.nomad.addr.clone(),
+            client.id,
+            cluster.policy.clone(),
+        ));
+        handles.push((node.asg.clone(), client, handle));
+    }
+
+    let mut result = Vec::with_capacity(handles.len());
+    for (asg, client, handle) in handles {
+        let source = format!("Nomad node {}", client.id);
+        let node = match partial(handle.await, &source, strict, failures)? {
+            Some(node) => node,
+            None => continue,
+        };
+        let allocs: Vec<NomadAlloc> = client
+            .allocs
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|alloc| alloc.status == "pending" || alloc.status == "running")
+            .collect();
+        let mut used = Resources::default();
+        for alloc in allocs.iter() {
+            used.add(Resources::used_by(alloc));
+        }
+        result.push(ClientCapacity {
+            allocatable: Resources::allocatable(&node),
+            node,
+            asg,
+            used,
+            allocs,
+        });
+    }
+
+    Ok(result)
+}
+
+#[derive(Debug, Serialize)]
+struct Fragmentation {
+    cpu: f64,
+    memory: f64,
+}
+
+/// The capacity of the eligible clients of one node class in one group.
+#[derive(Debug, Serialize)]
+struct GroupCapacity {
+    class: String,
+    asg: String,
+    nodes: usize,
+    eligible: usize,
+    allocatable: Resources,
+    used: Resources,
+    free: Resources,
+    /// What's free on the client with the most free memory: the largest
+    /// allocation that can still be placed
+    largest: Resources,
+    /// The share of free CPU and memory that isn't on the client with the
+    /// most of it free; high values mean the headroom is spread too thin
+    fragmentation: Fragmentation,
+}
+
+pub async fn capacity(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let json = sub.is_present("json");
+    let strict = sub.is_present("strict");
+    let cluster = cluster.await??;
+    let mut failures = Vec::new();
+    let clients = clients(&cluster, strict, &mut failures).await?;
+
+    let mut groups: BTreeMap<(String, String), Vec<&ClientCapacity>> = BTreeMap::new();
+    for client in clients.iter() {
+        let class = Some(client.node.node_class.clone())
+            .filter(|class| !class.is_empty())
+            .unwrap_or_else(|| "-".to_owned());
+        let asg = client.asg.clone().unwrap_or_else(|| "-".to_owned());
+        groups.entry((class, asg)).or_default().push(client);
+    }
+
+    let result: Vec<GroupCapacity> = groups
+        .into_iter()
+        .map(|((class, asg), clients)| group(class, asg, &clients))
+        .collect();
+
+    if json {
+        let stdout = io::stdout();
+        serde_json::to_writer_pretty(stdout.lock(), &result)?;
+        println!();
+        incomplete(&failures);
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    table.add_row(row![b =>
+        "CLASS",
+        "ASG",
+        "NODES",
+        "CPU FREE (MHz)",
+        "MEMORY FREE (MB)",
+        "DISK FREE (MB)",
+        "LARGEST (MHz/MB)",
+        "FRAGMENTATION (CPU/MEMORY)",
+    ]);
+    for group in result.iter() {
+        table.add_row(row![
+            group.class,
+            group.asg,
+            format!("{}/{}", group.eligible, group.nodes),
+            r -> headroom(group.free.cpu, group.allocatable.cpu),
+            r -> headroom(group.free.memory, group.allocatable.memory),
+            r -> headroom(group.free.disk, group.allocatable.disk),
+            r -> format!("{}/{}", group.largest.cpu, group.largest.memory),
+            r -> format!(
+                "{:.0}% / {:.0}%",
+                group.fragmentation.cpu * 100.0,
+                group.fragmentation.memory * 100.0
+            ),
+        ]);
+    }
+    table.printstd();
+    incomplete(&failures);
+
+    Ok(())
+}
+
+/// Point out that the headroom leaves out the clients that couldn't be
+/// queried.
+pub(crate) fn incomplete(failures: &[SourceFailure]) {
+    if failures.is_empty() {
+        return;
+    }
+    eprintln!(
+        "WARNING: {} Nomad clients couldn't be queried and aren't counted; pass --strict to fail instead.",
+        failures.len()
+    );
+}
+
+fn group(class: String, asg: String, clients: &[&ClientCapacity]) -> GroupCapacity {
+    let eligible: Vec<&&ClientCapacity> = clients.iter().filter(|c| c.eligible()).collect();
+
+    let mut allocatable = Resources::default();
+    let mut used = Resources::default();
+    let mut most_cpu = 0;
+    let mut largest = Resources::default();
+    for client in eligible.iter() {
+        allocatable.add(client.allocatable);
+        used.add(client.used);
+        let free = client.free();
+        most_cpu = most_cpu.max(free.cpu);
+        // memory is what usually runs out first
+        if (free.memory, free.cpu) > (largest.memory, largest.cpu) {
+            largest = free;
+        }
+    }
+    let free = allocatable.saturating_sub(used);
+
+    GroupCapacity {
+        class,
+        asg,
+        nodes: clients.len(),
+        eligible: eligible.len(),
+        allocatable,
+        used,
+        free,
+        largest,
+        fragmentation: Fragmentation {
+            cpu: fragmentation(most_cpu, free.cpu),
+            memory: fragmentation(largest.memory, free.memory),
+        },
+    }
+}
+
+fn fragmentation(largest: u64, free: u64) -> f64 {
+    if free == 0 {
+        0.0
+    } else {
+        1.0 - largest as f64 / free as f64
+    }
+}
+
+fn headroom(free: u64, total: u64) -> String {
+    let percent = if total == 0 {
+        0.0
+    } else {
+        free as f64 * 100.0 / total as f64
+    };
+    format!("{}/{} ({:.0}%)", free, total, percent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    fn client(
+        eligibility: &str,
+        drain: bool,
+        cpu: u64,
+        memory: u64,
+        used: (u64, u64),
+    ) -> ClientCapacity {
+        let node: NomadNode = serde_json::from_value(json!({
+            "ID": Uuid::nil(),
+            "Name": "client",
+            "Datacenter": "dc1",
+            "Status": "ready",
+            "SchedulingEligibility": eligibility,
+            "Drain": drain,
+        }))
+        .unwrap();
+        ClientCapacity {
+            node,
+            asg: None,
+            allocatable: Resources {
+                cpu,
+                memory,
+                disk: 0,
+            },
+            used: Resources {
+                cpu: used.0,
+                memory: used.1,
+                disk: 0,
+            },
+            allocs: Vec::new(),
+        }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn group_counts_eligible_clients_only() {
+        let clients = [
+            client("eligible", false, 4000, 8000, (1000, 2000)),
+            client("eligible", false, 2000, 4000, (0, 0)),
+            client("ineligible", false, 9000, 9000, (0, 0)),
+            client("eligible", true, 9000, 9000, (0, 0)),
+        ];
+        let clients: Vec<&ClientCapacity> = clients.iter().collect();
+        let group = group("web".to_owned(), "asg".to_owned(), &clients);
+
+        assert_eq!((group.nodes, group.eligible), (4, 2));
+        assert_eq!(
+            (group.allocatable.cpu, group.allocatable.memory),
+            (6000, 12000)
+        );
+        assert_eq!((group.used.cpu, group.used.memory), (1000, 2000));
+        assert_eq!((group.free.cpu, group.free.memory), (5000, 10000));
+        assert_eq!((group.largest.cpu, group.largest.memory), (3000, 6000));
+        assert!(close(group.fragmentation.cpu, 0.4));
+        assert!(close(group.fragmentation.memory, 0.4));
+    }
+
+    #[test]
+    fn group_picks_largest_by_memory() {
+        let clients = [
+            client("eligible", false, 1000, 8000, (0, 0)),
+            client("eligible", false, 3000, 2000, (0, 0)),
+        ];
+        let clients: Vec<&ClientCapacity> = clients.iter().collect();
+        let group = group("web".to_owned(), "asg".to_owned(), &clients);
+
+        assert_eq!((group.largest.cpu, group.largest.memory), (1000, 8000));
+        // CPU fragmentation goes by the client with the most CPU free
+        assert!(close(group.fragmentation.cpu, 0.25));
+        assert!(close(group.fragmentation.memory, 0.2));
+    }
+
+    #[test]
+    fn group_without_eligible_clients() {
+        let clients = [client("ineligible", false, 4000, 8000, (0, 0))];
+        let clients: Vec<&ClientCapacity> = clients.iter().collect();
+        let group = group("-".to_owned(), "-".to_owned(), &clients);
+
+        assert_eq!((group.nodes, group.eligible), (1, 0));
+        assert_eq!((group.free.cpu, group.free.memory), (0, 0));
+        assert!(close(group.fragmentation.cpu, 0.0));
+        assert!(close(group.fragmentation.memory, 0.0));
+    }
+
+    #[test]
+    fn fragmentation_share() {
+        assert!(close(fragmentation(0, 0), 0.0));
+        assert!(close(fragmentation(10, 10), 0.0));
+        assert!(close(fragmentation(5, 10), 0.5));
+        assert!(close(fragmentation(1, 4), 0.75));
+    }
+}
@@ -0,0 +1,147 @@
+use crate::cli;
+use crate::config::Config;
+use anyhow::{anyhow, Context, Result};
+use clap::{ArgMatches, ValueSource};
+use log::warn;
+use prettytable::{cell, format, row, Table};
+use std::env;
+use uuid::Uuid;
+
+/// Fill in the environment from the context in use, before the arguments are
+/// parsed. Variables that are already set win over the current context, but
+/// not over a context selected with `--context` or `BITTE_CONTEXT`.
+pub fn apply(args: &[String]) -> Result<()> {
+    // clap can't be asked yet, the context decides the defaults of the other options
+    let explicit = cli::arg_value(args, "--context").or_else(|| env::var("BITTE_CONTEXT").ok());
+    // logging isn't set up before the arguments are parsed
+    let ignore = |error: anyhow::Error| {
+        eprintln!("WARNING: ignoring the current context: {:#}", error);
+        Ok(())
+    };
+
+    // a broken config must not get in the way of `--help` or `completions`
+    let config = match (Config::load(), &explicit) {
+        (Ok(config), _) => config,
+        (Err(error), Some(_)) => return Err(error),
+        (Err(error), None) => return ignore(error),
+    };
+
+    let name = match explicit.clone().or_else(|| config.current_context.clone()) {
+        Some(name) if !name.is_empty() => name,
+        _ => return Ok(()),
+    };
+    let context = match (config.context(&name), &explicit) {
+        (Ok(context), _) => context,
+        (Err(error), Some(_)) => return Err(error),
+        (Err(error), None) => return ignore(error),
+    };
+
+    if explicit.is_some() {
+        env::set_var("BITTE_CONTEXT", &name);
+    }
+    for (var, value) in context.env() {
+        if explicit.is_some() || env::var_os(var).is_none() {
+            env::set_var(var, value);
+        }
+    }
+
+    Ok(())
+}
+
+/// The Nomad token to use: one given on the command line, else the active
+/// context's, unless `NOMAD_TOKEN` is set and the context wasn't explicitly
+/// selected.
+pub fn token(sub: &ArgMatches) -> Option<Uuid> {
+    let given = sub.get_one::<Uuid>("nomad").copied();
+    let explicit = sub.value_source("context").is_some();
+    if sub.value_source("nomad") == Some(ValueSource::CommandLine) || (given.is_some() && !explicit)
+    {
+        return given;
+    }
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(error) => {
+            warn!("{:#}", error);
+            return given;
+        }
+    };
+
+    let source = match config.active().and_then(|context| context.token.as_ref()) {
+        Some(source) => source,
+        None => return given,
+    };
+
+    match source.read() {
+        Ok(token) => Some(token),
+        Err(error) => {
+            warn!(
+                "failed to read the Nomad token of context \"{}\": {:#}",
+                config.active_name().unwrap_or_default(),
+                error
+            );
+            given
+        }
+    }
+}
+
+pub async fn list() -> Result<()> {
+    let config = Config::load()?;
+    if config.contexts.is_empty() {
+        println!(
+            "No contexts are defined in {}",
+            Config::path()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default()
+        );
+        return Ok(());
+    }
+
+    let active = config.active_name();
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    table.add_row(row![b => "CURRENT", "NAME", "CLUSTER", "DOMAIN"]);
+    for (name, context) in config.contexts.iter() {
+        table.add_row(row![
+            if active.as_ref() == Some(name) {
+                "*"
+            } else {
+                ""
+            },
+            name,
+            context.cluster,
+            context.domain.clone().unwrap_or_default(),
+        ]);
+    }
+    table.printstd();
+
+    Ok(())
+}
+
+pub async fn switch(sub: &ArgMatches) -> Result<()> {
+    let name = sub.get_one::<String>("name").unwrap();
+    let config = Config::load()?;
+    config.set_current(name)?;
+    println!("Switched to context \"{}\".", name);
+    Ok(())
+}
+
+pub async fn show(sub: &ArgMatches) -> Result<()> {
+    let config = Config::load()?;
+    let name = match sub.get_one::<String>("name") {
+        Some(name) => name.to_owned(),
+        None => config
+            .active_name()
+            .ok_or_else(|| anyhow!("no context is in use, see `bitte context use`"))?,
+    };
+    let context = config.context(&name)?;
+
+    println!("# context \"{}\"", name);
+    print!(
+        "{}",
+        toml::to_string(context).with_context(|| format!("failed to render context {}", name))?
+    );
+
+    Ok(())
+}
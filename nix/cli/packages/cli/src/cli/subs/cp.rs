@@ -0,0 +1,197 @@
+use super::ssh::{ssh_opts, ALLOC_DIR};
+use crate::bitte::policy::RequestPolicy;
+use crate::bitte::{BitteFind, ClusterHandle};
+use crate::nomad::fs;
+use anyhow::{anyhow, Context, Result};
+use clap::ArgMatches;
+use log::info;
+use reqwest::Client;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use tokio::fs::{create_dir_all, File};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+/// A `job:group:index:/path` location inside an allocation directory.
+struct AllocPath {
+    job: String,
+    group: String,
+    index: String,
+    path: String,
+}
+
+impl AllocPath {
+    fn parse(spec: &str) -> Option<Self> {
+        let parts: Vec<&str> = spec.splitn(4, ':').collect();
+        match parts[..] {
+            [job, group, index, path] if !job.is_empty() && !path.is_empty() => Some(Self {
+                job: job.to_owned(),
+                group: group.to_owned(),
+                index: index.to_owned(),
+                path: path.to_owned(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+pub async fn cp(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let source = sub.get_one::<String>("source").unwrap();
+    let destination = sub.get_one::<String>("destination").unwrap();
+    let recursive = sub.is_present("recursive");
+
+    let namespace = sub
+        .get_one::<String>("namespace")
+        .unwrap_or(&"default".to_string())
+        .to_owned();
+
+    let (remote, local, upload) = match (AllocPath::parse(source), AllocPath::parse(destination)) {
+        (Some(remote), None) => (remote, destination, false),
+        (None, Some(remote)) => (remote, source, true),
+        (Some(_), Some(_)) => {
+            return Err(anyhow!("copying between two allocations is not supported"))
+        }
+        (None, None) => {
+            return Err(anyhow!(
+                "either source or destination must be of the form job:group:index:/path"
+            ))
+        }
+    };
+
+    let cluster = cluster.await??;
+    let client = cluster
+        .nomad_api_client
+        .clone()
+        .context("a Nomad token is required to locate allocations")?;
+
+    let (node, alloc) =
+        cluster
+            .nodes
+            .find_with_job(&remote.job, &remote.group, &remote.index, &namespace)?;
+
+    if upload {
+        let target = format!(
+            "root@{}:{}/{}/{}",
+            node.pub_ip,
+            ALLOC_DIR,
+            alloc.id,
+            remote.path.trim_start_matches('/')
+        );
+
+        let mut cmd = Command::new("scp");
+        cmd.args(["-P", "22"]).args(ssh_opts(&cluster.name));
+        if recursive {
+            cmd.arg("-r");
+        }
+        let cmd_with_args = cmd.arg(local).arg(target);
+        info!("cmd: {:?}", cmd_with_args);
+
+        let status = cmd
+            .spawn()
+            .with_context(|| "scp command failed")?
+            .wait()
+            .with_context(|| "scp command didn't finish?")?;
+
+        if !status.success() {
+            return Err(anyhow!("scp exited with {}", status));
+        }
+
+        return Ok(());
+    }
+
+    let file = fs::stat(
+        Arc::clone(&client),
+        cluster.endpoints.nomad.addr.clone(),
+        alloc.id,
+        &remote.path,
+        &cluster.policy,
+    )
+    .await?;
+    if file.is_dir && !recursive {
+        return Err(anyhow!(
+            "{} is a directory (not copied, use --recursive)",
+            remote.path
+        ));
+    }
+
+    // like cp, copy into an existing directory rather than replacing it
+    let mut target = PathBuf::from(local);
+    if target.is_dir() {
+        target.push(&file.name);
+    }
+
+    if file.is_dir {
+        download_dir(
+            client,
+            cluster.endpoints.nomad.addr,
+            alloc.id,
+            remote.path,
+            target,
+            &cluster.policy,
+        )
+        .await
+    } else {
+        download_file(
+            client,
+            cluster.endpoints.nomad.addr,
+            alloc.id,
+            &remote.path,
+            &target,
+        )
+        .await
+    }
+}
+
+async fn download_dir(
+    client: Arc<Client>,
+    addr: String,
+    alloc: Uuid,
+    path: String,
+    target: PathBuf,
+    policy: &RequestPolicy,
+) -> Result<()> {
+    let mut pending = vec![(path, target)];
+
+    while let Some((path, target)) = pending.pop() {
+        create_dir_all(&target)
+            .await
+            .with_context(|| format!("failed to create {}", target.display()))?;
+
+        for file in fs::ls(Arc::clone(&client), addr.clone(), alloc, &path, policy).await? {
+            let remote = format!("{}/{}", path.trim_end_matches('/'), file.name);
+            let local = target.join(&file.name);
+            if file.is_dir {
+                pending.push((remote, local));
+            } else {
+                download_file(Arc::clone(&client), addr.clone(), alloc, &remote, &local).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn download_file(
+    client: Arc<Client>,
+    addr: String,
+    alloc: Uuid,
+    path: &str,
+    target: &Path,
+) -> Result<()> {
+    info!("copy {}:{} to {}", alloc, path, target.display());
+
+    let mut response = fs::cat(client, addr, alloc, path).await?;
+    let mut file = File::create(target)
+        .await
+        .with_context(|| format!("failed to create {}", target.display()))?;
+
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk)
+            .await
+            .with_context(|| format!("failed to write {}", target.display()))?;
+    }
+
+    file.flush().await?;
+    Ok(())
+}
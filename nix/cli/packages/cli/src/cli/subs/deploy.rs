@@ -1,14 +1,33 @@
-use crate::bitte::{BitteFind, ClusterHandle};
+use crate::bitte::endpoint::Service;
+use crate::bitte::node::BitteNodes;
+use crate::bitte::raft::Quorum;
+use crate::bitte::snapshot;
+use crate::bitte::{BitteCluster, BitteFind, ClusterHandle};
 use crate::deploy_rs::cli as deployCli;
 use crate::deploy_rs::cli::Opts as ExtDeployOpts;
-use anyhow::Result;
+use crate::flake;
+use anyhow::{anyhow, Context, Result};
 use clap::{ArgMatches, FromArgMatches};
 use log::{error, info};
+use std::collections::HashSet;
 use std::process::{Command, Stdio};
+use std::time::Duration;
 
 pub async fn deploy(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
-    let opts = <super::Deploy as FromArgMatches>::from_arg_matches(sub).unwrap_or_default();
+    let mut opts = <super::Deploy as FromArgMatches>::from_arg_matches(sub).unwrap_or_default();
     let cluster = cluster.await??;
+    if let Some(node) = opts.core_node.take() {
+        // one step of `deploy_core`, which took the snapshots already
+        opts.nodes = vec![node];
+        opts.fail_fast = true;
+    } else {
+        if opts.snapshot_first {
+            super::snapshot::take_all(&cluster, &Service::ALL, &snapshot::default_dir()).await?;
+        }
+        if opts.core {
+            return deploy_core(sub, cluster, opts.quorum_timeout).await;
+        }
+    }
     let node_class = sub.get_one::<String>("class").cloned();
 
     info!("node needles: {:?}", opts.nodes);
@@ -21,10 +40,34 @@ pub async fn deploy(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
             .find_needles(opts.nodes.iter().map(AsRef::as_ref).collect())
     };
 
-    let nixos_configurations: Vec<String> = instances
+    // nodes outside of EC2 are only known to the flake, by their name
+    let mut names: Vec<String> = instances.iter().map(|i| i.nixos.clone()).collect();
+    names.extend(opts.nodes.iter().cloned());
+    let deploy_nodes = flake::deploy_nodes(sub, &names);
+
+    let mut targets: Vec<(String, String)> = instances
         .iter()
-        .map(|i| i.nixos.clone())
-        .collect::<Vec<String>>();
+        .map(|i| {
+            let host = deploy_nodes
+                .get(&i.nixos)
+                .and_then(|node| node.hostname.clone())
+                .unwrap_or_else(|| i.pub_ip.to_string());
+            (i.nixos.clone(), host)
+        })
+        .collect();
+    if !opts.clients {
+        for needle in opts.nodes.iter() {
+            if targets.iter().any(|(nixos, _)| nixos == needle) {
+                continue;
+            }
+            if let Some(hostname) = deploy_nodes.get(needle).and_then(|n| n.hostname.clone()) {
+                targets.push((needle.clone(), hostname));
+            }
+        }
+    }
+
+    let nixos_configurations: Vec<String> =
+        targets.iter().map(|(nixos, _)| nixos.clone()).collect();
     info!("regenerate secrets for: {:?}", nixos_configurations);
 
     for nixos_configuration in nixos_configurations {
@@ -47,12 +90,13 @@ pub async fn deploy(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
         }
     }
 
-    let targets: Vec<String> = instances
+    let targets: Vec<String> = targets
         .iter()
-        .map(|i| format!(".#{}@{}:22", i.nixos, i.pub_ip))
+        .map(|(nixos, host)| format!(".#{}@{}:22", nixos, host))
         .collect();
 
     info!("redeploy: {:?}", targets);
+    let fail_fast = opts.fail_fast;
     // TODO: disable these options for the general public (target & targets)
     let opts = ExtDeployOpts {
         hostname: None,
@@ -64,6 +108,9 @@ pub async fn deploy(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
     // wait_for_ssh(&instance.pub_ip).await?;
     if let Err(err) = deployCli::run(Some(opts)).await {
         error!("{}", err);
+        if fail_fast {
+            return Err(anyhow!("deployment failed: {}", err));
+        }
         // NB: if your up for a mass rebuild you are expected to:
         //   - Randomly check on a representative single node before
         //   - Eventually use the dry-run fearure
@@ -74,3 +121,123 @@ pub async fn deploy(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
     }
     Ok(())
 }
+
+/// Deploy the Consul, Nomad and Vault servers one at a time, so that raft
+/// never loses more than one peer. After each node, wait until every service
+/// has a leader and all of its previous peers back.
+///
+/// deploy-rs sets up its logger on every run, which only works once per
+/// process, so each node is deployed by this same command line in a process
+/// of its own, told which node it is about through `BITTE_DEPLOY_CORE_NODE`.
+async fn deploy_core(sub: &ArgMatches, cluster: BitteCluster, timeout: Duration) -> Result<()> {
+    let needles: Vec<String> = sub.get_many("nodes").unwrap_or_default().cloned().collect();
+    let quorum = Quorum::new(&cluster.endpoints, &cluster.policy)?;
+
+    let before = quorum.states().await?;
+    let problems: Vec<String> = before
+        .iter()
+        .flat_map(|state| state.problems(state))
+        .collect();
+    if !problems.is_empty() {
+        return Err(anyhow!(
+            "refusing to deploy core nodes, the cluster isn't healthy: {}",
+            problems.join("; ")
+        ));
+    }
+
+    let servers: HashSet<String> = before
+        .iter()
+        .flat_map(|state| state.servers.clone().unwrap_or_default())
+        .collect();
+    let mut nodes: BitteNodes = cluster
+        .nodes
+        .iter()
+        .filter(|node| servers.contains(&node.priv_ip.to_string()))
+        .cloned()
+        .collect();
+
+    if !needles.is_empty() {
+        let wanted = cluster
+            .nodes
+            .clone()
+            .find_needles(needles.iter().map(AsRef::as_ref).collect());
+        if let Some(node) = wanted
+            .iter()
+            .find(|wanted| !nodes.iter().any(|node| node.id == wanted.id))
+        {
+            return Err(anyhow!(
+                "{} is not a Consul, Nomad or Vault server",
+                node.name
+            ));
+        }
+        nodes.retain(|node| wanted.iter().any(|wanted| wanted.id == node.id));
+    }
+    if nodes.is_empty() {
+        return Err(anyhow!("no Consul, Nomad or Vault servers to deploy"));
+    }
+    nodes.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut states = before.clone();
+    while !nodes.is_empty() {
+        // leadership can move while followers restart, so the next node is
+        // picked from the current state every time
+        let next = nodes
+            .iter()
+            .position(|node| {
+                let ip = node.priv_ip.to_string();
+                !states.iter().any(|state| state.leads(&ip))
+            })
+            .unwrap_or(0);
+        let node = nodes.remove(next);
+
+        let ip = node.priv_ip.to_string();
+        let leads: Vec<String> = states
+            .iter()
+            .filter(|state| state.leads(&ip))
+            .map(|state| state.service.to_string())
+            .collect();
+        let role = if leads.is_empty() {
+            "follower".to_owned()
+        } else {
+            format!("{} leader", leads.join(", "))
+        };
+
+        eprintln!("==> deploying {} ({}, {})", node.name, node.id, role);
+        let status = Command::new(std::env::current_exe()?)
+            .args(std::env::args_os().skip(1))
+            .env("BITTE_DEPLOY_CORE_NODE", &node.id)
+            .status()
+            .with_context(|| format!("failed to deploy {}", node.name))?;
+        if !status.success() {
+            return Err(anyhow!(
+                "deploying {} failed with {}, not deploying: {}",
+                node.name,
+                status,
+                remaining(&nodes)
+            ));
+        }
+
+        eprintln!("==> waiting for quorum after deploying {}", node.name);
+        quorum
+            .wait_healthy(&before, timeout)
+            .await
+            .with_context(|| format!("not deploying: {}", remaining(&nodes)))?;
+        states = quorum.states().await?;
+        for state in states.iter() {
+            eprintln!("    {}", state);
+        }
+    }
+
+    Ok(())
+}
+
+fn remaining(nodes: &BitteNodes) -> String {
+    if nodes.is_empty() {
+        return "none left".to_owned();
+    }
+    nodes
+        .iter()
+        .map(|node| node.name.clone())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,374 @@
+use crate::bitte::consul::{Consul, ConsulCheck};
+use crate::bitte::node::BitteNode;
+use crate::bitte::{BitteCluster, BitteFind, ClusterHandle, SourceFailure};
+use crate::nomad::alloc::{AllocIndex, NomadAlloc};
+use crate::nomad::node::{NodeResources, NomadNode};
+use anyhow::{anyhow, Result};
+use aws_sdk_ec2::model::Instance;
+use chrono::{DateTime, TimeZone, Utc};
+use clap::ArgMatches;
+use log::warn;
+use prettytable::{cell, format, row, Table};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io;
+use std::sync::Arc;
+
+/// The parts of the EC2 instance worth knowing when looking at a node.
+#[derive(Debug, Serialize)]
+struct Ec2Details {
+    instance_type: Option<String>,
+    image_id: Option<String>,
+    launch_time: Option<DateTime<Utc>>,
+    state: Option<String>,
+    key_name: Option<String>,
+    vpc_id: Option<String>,
+    subnet_id: Option<String>,
+    tags: BTreeMap<String, String>,
+    security_groups: Vec<SecurityGroup>,
+    volumes: Vec<Volume>,
+}
+
+#[derive(Debug, Serialize)]
+struct SecurityGroup {
+    id: String,
+    name: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Volume {
+    device: String,
+    volume_id: String,
+    status: String,
+    delete_on_termination: bool,
+}
+
+impl From<Instance> for Ec2Details {
+    fn from(instance: Instance) -> Self {
+        Self {
+            instance_type: instance.instance_type.map(|t| t.as_str().to_owned()),
+            image_id: instance.image_id,
+            launch_time: instance
+                .launch_time
+                .and_then(|time| Utc.timestamp_opt(time.secs(), 0).single()),
+            state: instance
+                .state
+                .and_then(|state| state.name)
+                .map(|name| name.as_str().to_owned()),
+            key_name: instance.key_name,
+            vpc_id: instance.vpc_id,
+            subnet_id: instance.subnet_id,
+            tags: instance
+                .tags
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|tag| Some((tag.key?, tag.value.unwrap_or_default())))
+                .collect(),
+            security_groups: instance
+                .security_groups
+                .unwrap_or_default()
+                .into_iter()
+                .map(|group| SecurityGroup {
+                    id: group.group_id.unwrap_or_default(),
+                    name: group.group_name.unwrap_or_default(),
+                })
+                .collect(),
+            volumes: instance
+                .block_device_mappings
+                .unwrap_or_default()
+                .into_iter()
+                .map(|mapping| {
+                    let ebs = mapping.ebs.unwrap_or_default();
+                    Volume {
+                        device: mapping.device_name.unwrap_or_default(),
+                        volume_id: ebs.volume_id.unwrap_or_default(),
+                        status: ebs
+                            .status
+                            .map(|status| status.as_str().to_owned())
+                            .unwrap_or_default(),
+                        delete_on_termination: ebs.delete_on_termination.unwrap_or_default(),
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Everything about one node, from wherever it could be found.
+#[derive(Debug, Serialize)]
+struct Description {
+    node: BitteNode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ec2: Option<Ec2Details>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nomad: Option<NomadNode>,
+    allocs: Vec<NomadAlloc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checks: Option<Vec<ConsulCheck>>,
+    /// Sources that couldn't be queried
+    failures: Vec<SourceFailure>,
+}
+
+impl Description {
+    fn failed(&mut self, source: &str, error: anyhow::Error) {
+        warn!("{}: {:#}", source, error);
+        self.failures.push(SourceFailure {
+            source: source.to_owned(),
+            error: format!("{:#}", error),
+        });
+    }
+}
+
+pub async fn describe(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let needle = sub.get_one::<String>("needle").unwrap();
+    let json = sub.is_present("json");
+    let cluster = cluster.await??;
+    let mut node = cluster.nodes.clone().find_needle(needle)?;
+
+    let mut allocs: Vec<NomadAlloc> = node
+        .nomad_client
+        .as_mut()
+        .and_then(|client| client.allocs.take())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|alloc| alloc.status == "running")
+        .collect();
+    allocs.sort_by_key(alloc_name);
+
+    let mut description = Description {
+        node,
+        ec2: None,
+        nomad: None,
+        allocs,
+        checks: None,
+        failures: Vec::new(),
+    };
+
+    let region = description
+        .node
+        .region()
+        .or_else(|| sub.get_one::<String>("aws-region").cloned());
+    match region {
+        Some(region) => match description
+            .node
+            .describe_instance(&region, &cluster.policy)
+            .await
+        {
+            Ok(instance) => description.ec2 = Some(Ec2Details::from(instance)),
+            Err(error) => description.failed("EC2", error),
+        },
+        None => description.failed("EC2", anyhow!("the instance's region is unknown")),
+    }
+
+    if let (Some(client), Some(api)) = (
+        description.node.nomad_client.as_ref(),
+        cluster.nomad_api_client.as_ref(),
+    ) {
+        let addr = cluster.endpoints.nomad.addr.clone();
+        match NomadNode::get(Arc::clone(api), addr, client.id, cluster.policy.clone()).await {
+            Ok(nomad) => description.nomad = Some(nomad),
+            Err(error) => description.failed("Nomad", error),
+        }
+    }
+
+    match consul_checks(&cluster, &description.node).await {
+        Ok(checks) => description.checks = Some(checks),
+        Err(error) => description.failed("Consul", error),
+    }
+
+    if json {
+        let stdout = io::stdout();
+        serde_json::to_writer_pretty(stdout.lock(), &description)?;
+        println!();
+    } else {
+        print(&description);
+    }
+
+    Ok(())
+}
+
+async fn consul_checks(cluster: &BitteCluster, node: &BitteNode) -> Result<Vec<ConsulCheck>> {
+    Consul::new(&cluster.endpoints.consul, &cluster.policy)?
+        .checks(node.priv_ip)
+        .await
+}
+
+/// The name Nomad gives an allocation, like `job.group[0]`.
+pub(crate) fn alloc_name(alloc: &NomadAlloc) -> String {
+    let index = match &alloc.index {
+        AllocIndex::Int(index) => index.to_string(),
+        AllocIndex::String(index) => index.clone(),
+    };
+    format!("{}.{}[{}]", alloc.job_id, alloc.task_group, index)
+}
+
+fn section(title: &str) {
+    println!("\n{}", title);
+}
+
+fn pairs<K: Display, V: Display>(pairs: impl IntoIterator<Item = (K, V)>) {
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    for (key, value) in pairs {
+        table.add_row(row![b -> key, value]);
+    }
+    if !table.is_empty() {
+        table.printstd();
+    }
+}
+
+fn print(description: &Description) {
+    let node = &description.node;
+    pairs([
+        ("Name", node.name.clone()),
+        ("Instance", node.id.clone()),
+        ("Private IP", node.priv_ip.to_string()),
+        ("Public IP", node.pub_ip.to_string()),
+        ("Zone", node.zone.clone().unwrap_or_default()),
+        ("ASG", node.asg.clone().unwrap_or_else(|| "-".to_owned())),
+        ("NixOS", node.nixos.clone()),
+    ]);
+
+    if let Some(ec2) = &description.ec2 {
+        section("EC2");
+        let none = || "-".to_owned();
+        pairs([
+            ("Type", ec2.instance_type.clone().unwrap_or_else(none)),
+            ("AMI", ec2.image_id.clone().unwrap_or_else(none)),
+            (
+                "Launched",
+                ec2.launch_time
+                    .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+                    .unwrap_or_else(none),
+            ),
+            ("State", ec2.state.clone().unwrap_or_else(none)),
+            ("Key", ec2.key_name.clone().unwrap_or_else(none)),
+            ("VPC", ec2.vpc_id.clone().unwrap_or_else(none)),
+            ("Subnet", ec2.subnet_id.clone().unwrap_or_else(none)),
+        ]);
+
+        section("Tags");
+        pairs(ec2.tags.iter());
+
+        section("Security groups");
+        pairs(ec2.security_groups.iter().map(|g| (&g.id, &g.name)));
+
+        section("Volumes");
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_CLEAN);
+        table.add_row(row![b => "DEVICE", "VOLUME", "STATUS", "DELETE ON TERMINATION"]);
+        for volume in ec2.volumes.iter() {
+            table.add_row(row![
+                volume.device,
+                volume.volume_id,
+                volume.status,
+                volume.delete_on_termination,
+            ]);
+        }
+        table.printstd();
+    }
+
+    if let Some(nomad) = &description.nomad {
+        section("Nomad");
+        pairs([
+            ("ID", nomad.id.to_string()),
+            ("Name", nomad.name.clone()),
+            ("Datacenter", nomad.datacenter.clone()),
+            ("Class", nomad.node_class.clone()),
+            ("Status", nomad.status.clone()),
+            ("Eligibility", nomad.eligibility.clone()),
+            ("Drain", nomad.drain.to_string()),
+        ]);
+
+        if let Some(total) = &nomad.resources {
+            section("Resources");
+            let reserved = nomad.reserved.clone().unwrap_or_default();
+            let mut table = Table::new();
+            table.set_format(*format::consts::FORMAT_CLEAN);
+            table.add_row(row![b => "", "TOTAL", "RESERVED", "ALLOCATABLE"]);
+            resource_row(&mut table, "CPU (MHz)", total, &reserved, |r| {
+                r.cpu.cpu_shares
+            });
+            resource_row(&mut table, "Memory (MB)", total, &reserved, |r| {
+                r.memory.memory_mb
+            });
+            resource_row(&mut table, "Disk (MB)", total, &reserved, |r| {
+                r.disk.disk_mb
+            });
+            table.printstd();
+        }
+
+        section("Drivers");
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_CLEAN);
+        table.add_row(row![b => "DRIVER", "DETECTED", "HEALTHY", "DESCRIPTION"]);
+        for (name, driver) in nomad.drivers.iter().flatten() {
+            table.add_row(row![
+                name,
+                driver.detected,
+                driver.healthy,
+                driver.description
+            ]);
+        }
+        table.printstd();
+
+        section("Host volumes");
+        pairs(nomad.host_volumes.iter().flatten().map(|(name, volume)| {
+            let mode = if volume.read_only { " (read-only)" } else { "" };
+            (name, format!("{}{}", volume.path, mode))
+        }));
+
+        section("Meta");
+        pairs(nomad.meta.iter().flatten());
+
+        section("Attributes");
+        pairs(nomad.attributes.iter());
+    }
+
+    if node.nomad_client.is_some() {
+        section("Allocations");
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_CLEAN);
+        table.add_row(row![b => "ID", "NAME", "NAMESPACE"]);
+        for alloc in description.allocs.iter() {
+            table.add_row(row![alloc.id, alloc_name(alloc), alloc.namespace]);
+        }
+        table.printstd();
+    }
+
+    if let Some(checks) = &description.checks {
+        section("Consul checks");
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_CLEAN);
+        table.add_row(row![b => "STATUS", "CHECK", "SERVICE", "OUTPUT"]);
+        for check in checks.iter() {
+            table.add_row(row![
+                check.status,
+                check.name,
+                check.service,
+                check.output.lines().next().unwrap_or_default(),
+            ]);
+        }
+        table.printstd();
+    }
+
+    for failure in description.failures.iter() {
+        eprintln!("{}: {}", failure.source, failure.error);
+    }
+}
+
+fn resource_row(
+    table: &mut Table,
+    name: &str,
+    total: &NodeResources,
+    reserved: &NodeResources,
+    get: impl Fn(&NodeResources) -> u64,
+) {
+    table.add_row(row![
+        b -> name,
+        r -> get(total),
+        r -> get(reserved),
+        r -> get(total).saturating_sub(get(reserved)),
+    ]);
+}
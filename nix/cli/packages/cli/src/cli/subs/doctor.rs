@@ -0,0 +1,454 @@
+use super::vault::core_nodes;
+use crate::bitte::endpoint::{Endpoint, Service};
+use crate::bitte::node::{BitteNode, Orphans};
+use crate::bitte::raft::Quorum;
+use crate::bitte::tunnel::{Tunnel, TunnelTarget};
+use crate::bitte::{BitteCluster, ClusterHandle};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use clap::ArgMatches;
+use openssl::asn1::Asn1Time;
+use prettytable::{cell, format, row, Table};
+use reqwest::header::DATE;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let status = match *self {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+/// The outcome of one check, or of one thing a check found wrong.
+#[derive(Debug, Serialize)]
+struct Check {
+    check: &'static str,
+    status: Status,
+    detail: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    checks: Vec<Check>,
+    passed: usize,
+    warnings: usize,
+    failed: usize,
+}
+
+impl Report {
+    fn add(&mut self, check: &'static str, status: Status, detail: impl Into<String>) {
+        match status {
+            Status::Pass => self.passed += 1,
+            Status::Warn => self.warnings += 1,
+            Status::Fail => self.failed += 1,
+        }
+        self.checks.push(Check {
+            check,
+            status,
+            detail: detail.into(),
+        });
+    }
+}
+
+/// Run every check over the cluster; it fails if any of them does, so CI
+/// can gate on it.
+pub async fn doctor(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let json = sub.is_present("json");
+    let cert_days = *sub.get_one::<u32>("cert-days").unwrap();
+    let max_skew = *sub.get_one::<Duration>("max-skew").unwrap();
+    let cluster = cluster.await??;
+
+    let mut report = Report::default();
+    discovery(&cluster, &mut report);
+    addresses(&cluster, &mut report);
+    nomad_clients(&cluster, &mut report);
+    leaders(&cluster, &mut report).await;
+    certificates(&cluster, cert_days, &mut report).await;
+    clocks(&cluster, max_skew, &mut report).await;
+    asgs(&cluster, &mut report).await;
+
+    if json {
+        let stdout = io::stdout();
+        serde_json::to_writer_pretty(stdout.lock(), &report)?;
+        println!();
+    } else {
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_CLEAN);
+        table.add_row(row![b => "STATUS", "CHECK", "DETAIL"]);
+        for check in report.checks.iter() {
+            table.add_row(row![check.status, check.check, check.detail]);
+        }
+        table.printstd();
+        println!(
+            "\n{} passed, {} warnings, {} failed",
+            report.passed, report.warnings, report.failed
+        );
+    }
+
+    if report.failed > 0 {
+        return Err(anyhow!("{} checks failed", report.failed));
+    }
+    Ok(())
+}
+
+/// Everything else is judged on what discovery found, so gaps in it come first.
+fn discovery(cluster: &BitteCluster, report: &mut Report) {
+    for failure in cluster.failures.iter() {
+        report.add(
+            "discovery",
+            Status::Fail,
+            format!("{}: {}", failure.source, failure.error),
+        );
+    }
+    if cluster.failures.is_empty() {
+        report.add(
+            "discovery",
+            Status::Pass,
+            format!(
+                "{} instances in {} region{}",
+                cluster.nodes.len(),
+                cluster.regions.len(),
+                if cluster.regions.len() == 1 { "" } else { "s" }
+            ),
+        );
+    }
+}
+
+/// Instances without an address are stored with 0.0.0.0 rather than left out.
+fn addresses(cluster: &BitteCluster, report: &mut Report) {
+    let mut found = false;
+    for node in cluster.nodes.iter() {
+        if node.priv_ip.is_unspecified() {
+            found = true;
+            report.add(
+                "addresses",
+                Status::Fail,
+                format!("{} ({}) has no private IP", node.name, node.id),
+            );
+        }
+        if node.pub_ip.is_unspecified() {
+            found = true;
+            report.add(
+                "addresses",
+                Status::Warn,
+                format!(
+                    "{} ({}) has no public IP, ssh won't reach it",
+                    node.name, node.id
+                ),
+            );
+        }
+    }
+    if !found {
+        report.add(
+            "addresses",
+            Status::Pass,
+            format!("all {} instances have both addresses", cluster.nodes.len()),
+        );
+    }
+}
+
+/// Every instance of an auto-scaling group should run a Nomad client, and
+/// every live Nomad client should be one of the instances.
+fn nomad_clients(cluster: &BitteCluster, report: &mut Report) {
+    let clients = match &cluster.nomad_clients {
+        Some(clients) => clients,
+        None if cluster.nomad_api_client.is_none() => {
+            report.add(
+                "nomad-clients",
+                Status::Warn,
+                "skipped, pass a Nomad token to compare clients and instances",
+            );
+            return;
+        }
+        // already reported as a discovery failure
+        None => return,
+    };
+
+    let orphans = Orphans::find(&cluster.nodes, clients);
+    for node in orphans.instances.iter() {
+        report.add(
+            "nomad-clients",
+            Status::Fail,
+            format!(
+                "{} ({}) of {} has no Nomad client",
+                node.id,
+                node.priv_ip,
+                node.asg.as_deref().unwrap_or_default()
+            ),
+        );
+    }
+    for client in orphans.clients.iter() {
+        report.add(
+            "nomad-clients",
+            if client.is_down() {
+                Status::Warn
+            } else {
+                Status::Fail
+            },
+            format!(
+                "Nomad client {} ({}, {}) has no instance",
+                client.name.as_deref().unwrap_or_default(),
+                client
+                    .address
+                    .map(|ip| ip.to_string())
+                    .unwrap_or_else(|| "no address".to_owned()),
+                client.status.as_deref().unwrap_or("unknown")
+            ),
+        );
+    }
+    for duplicate in orphans.duplicates.iter() {
+        report.add(
+            "nomad-clients",
+            Status::Fail,
+            format!(
+                "{} is used by {}",
+                duplicate.ip,
+                duplicate
+                    .instances
+                    .iter()
+                    .chain(duplicate.clients.iter())
+                    .cloned()
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        );
+    }
+    if orphans.is_empty() {
+        report.add(
+            "nomad-clients",
+            Status::Pass,
+            format!("{} Nomad clients match the instances", clients.len()),
+        );
+    }
+}
+
+async fn leaders(cluster: &BitteCluster, report: &mut Report) {
+    let quorum = match Quorum::new(&cluster.endpoints, &cluster.policy) {
+        Ok(quorum) => quorum,
+        Err(error) => {
+            report.add("leaders", Status::Fail, format!("{:#}", error));
+            return;
+        }
+    };
+
+    for state in quorum.each().await {
+        match state {
+            Ok(state) if state.leader.is_none() => report.add(
+                "leaders",
+                Status::Fail,
+                format!("{} has no leader", state.service),
+            ),
+            Ok(state) if state.healthy == Some(false) => report.add(
+                "leaders",
+                Status::Warn,
+                format!("{}, but autopilot reports unhealthy servers", state),
+            ),
+            Ok(state) => report.add("leaders", Status::Pass, state.to_string()),
+            Err(error) => report.add("leaders", Status::Fail, format!("{:#}", error)),
+        }
+    }
+}
+
+/// Certificates expiring within `days` warn, expired ones fail.
+async fn certificates(cluster: &BitteCluster, days: u32, report: &mut Report) {
+    for service in Service::ALL {
+        let endpoint = cluster.endpoints.get(service).clone();
+        if !endpoint.addr.starts_with("https://") {
+            report.add(
+                "certificates",
+                Status::Warn,
+                format!("{} isn't served over TLS", endpoint.addr),
+            );
+            continue;
+        }
+
+        let timeout = cluster.policy.timeout;
+        let cert = tokio::task::spawn_blocking(move || endpoint.peer_certificate(timeout))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|cert| cert);
+        let days_left = cert.and_then(|cert| {
+            let diff = Asn1Time::days_from_now(0)?.diff(cert.not_after())?;
+            Ok((cert.not_after().to_string(), diff.days))
+        });
+
+        let addr = &cluster.endpoints.get(service).addr;
+        match days_left {
+            Ok((expiry, left)) if left < 0 => report.add(
+                "certificates",
+                Status::Fail,
+                format!("{}: expired on {}", addr, expiry),
+            ),
+            Ok((expiry, left)) if left < days as i32 => report.add(
+                "certificates",
+                Status::Warn,
+                format!("{}: expires on {}, in {} days", addr, expiry, left),
+            ),
+            Ok((expiry, left)) => report.add(
+                "certificates",
+                Status::Pass,
+                format!("{}: expires on {}, in {} days", addr, expiry, left),
+            ),
+            Err(error) => report.add(
+                "certificates",
+                Status::Fail,
+                format!("{}: {:#}", addr, error),
+            ),
+        }
+    }
+}
+
+/// Compare the clocks of the core nodes, going by the `Date` their Consul
+/// servers answer with, with each other and with our own. The header only
+/// has a resolution of seconds, and our own clock may be the one that's off,
+/// so too much skew warns rather than fails.
+async fn clocks(cluster: &BitteCluster, max_skew: Duration, report: &mut Report) {
+    let nodes = match core_nodes(cluster, Vec::new()) {
+        Ok(nodes) => nodes,
+        Err(error) => {
+            report.add("clock-skew", Status::Warn, format!("{:#}", error));
+            return;
+        }
+    };
+    let too_far = |skew: ChronoDuration| {
+        if skew.num_milliseconds().unsigned_abs() as u128 > max_skew.as_millis() {
+            Status::Warn
+        } else {
+            Status::Pass
+        }
+    };
+
+    let mut skews: Vec<(&str, ChronoDuration)> = Vec::new();
+    for node in nodes.iter() {
+        match node_skew(cluster, node).await {
+            Ok(skew) => {
+                report.add(
+                    "clock-skew",
+                    too_far(skew),
+                    format!("{}: {:+.1}s from the local clock", node.name, seconds(skew)),
+                );
+                skews.push((&node.name, skew));
+            }
+            Err(error) => report.add(
+                "clock-skew",
+                Status::Warn,
+                format!("{}: {:#}", node.name, error),
+            ),
+        }
+    }
+
+    let ahead = skews.iter().max_by_key(|(_, skew)| *skew);
+    let behind = skews.iter().min_by_key(|(_, skew)| *skew);
+    if let (Some((ahead, max)), Some((behind, min))) = (ahead, behind) {
+        if skews.len() > 1 {
+            report.add(
+                "clock-skew",
+                too_far(*max - *min),
+                format!(
+                    "{} is {:.1}s ahead of {}",
+                    ahead,
+                    seconds(*max - *min),
+                    behind
+                ),
+            );
+        }
+    }
+}
+
+fn seconds(skew: ChronoDuration) -> f64 {
+    skew.num_milliseconds() as f64 / 1000.0
+}
+
+/// The API servers only serve their own node and its peers, so each node is
+/// asked through a tunnel of its own.
+async fn node_skew(cluster: &BitteCluster, node: &BitteNode) -> Result<ChronoDuration> {
+    let tunnel = Tunnel::open_on(&cluster.name, TunnelTarget::Consul, node, None).await?;
+    skew(&tunnel.endpoint(&cluster.endpoints.consul), cluster).await
+}
+
+async fn skew(endpoint: &Endpoint, cluster: &BitteCluster) -> Result<ChronoDuration> {
+    // needs no token
+    let url = format!("{}/v1/status/leader", endpoint.addr);
+    let client = endpoint.client(&cluster.policy)?;
+
+    let sent = Utc::now();
+    let response = cluster
+        .policy
+        .send(client.get(&url))
+        .await
+        .with_context(|| format!("failed to query: {}", &url))?;
+    let received = Utc::now();
+
+    let date = response
+        .headers()
+        .get(DATE)
+        .and_then(|date| date.to_str().ok())
+        .with_context(|| format!("{} sent no Date header", &url))?;
+    let date = DateTime::parse_from_rfc2822(date)
+        .with_context(|| format!("{} sent an invalid Date header: {}", &url, date))?;
+
+    // the header is truncated to seconds, so its middle is the best guess
+    let remote = date.with_timezone(&Utc) + ChronoDuration::milliseconds(500);
+    let local = sent + (received - sent) / 2;
+    Ok(remote - local)
+}
+
+/// Each auto-scaling group should have as many instances in service and
+/// running as it desires; while instances come and go that only warns.
+async fn asgs(cluster: &BitteCluster, report: &mut Report) {
+    let asgs = match super::asg::find(cluster).await {
+        Ok(asgs) => asgs,
+        Err(error) => {
+            report.add("asgs", Status::Fail, format!("{:#}", error));
+            return;
+        }
+    };
+    if asgs.is_empty() {
+        report.add(
+            "asgs",
+            Status::Warn,
+            format!("no auto-scaling groups tagged Cluster={}", cluster.name),
+        );
+    }
+
+    for asg in asgs.iter() {
+        let in_service = asg.instances.iter().filter(|i| i.in_service()).count();
+        let running = cluster
+            .nodes
+            .iter()
+            .filter(|node| node.asg.as_deref() == Some(asg.name.as_str()))
+            .count();
+        let desired = asg.desired.max(0) as usize;
+
+        let detail = format!(
+            "{} in {}: {} desired, {} in service, {} running",
+            asg.name, asg.region, desired, in_service, running
+        );
+        if desired == in_service && in_service == running {
+            report.add("asgs", Status::Pass, detail);
+        } else if asg.in_flux() > 0 {
+            report.add(
+                "asgs",
+                Status::Warn,
+                format!("{}, {} coming or going", detail, asg.in_flux()),
+            );
+        } else {
+            report.add("asgs", Status::Fail, detail);
+        }
+    }
+}
@@ -0,0 +1,441 @@
+use super::capacity::{self, ClientCapacity, Resources};
+use super::describe::alloc_name;
+use crate::bitte::{BitteCluster, BitteFind, ClusterHandle};
+use crate::nomad::alloc::NomadAlloc;
+use crate::nomad::constraint::Constraint;
+use crate::nomad::job::{JobDefinition, TaskGroupDefinition};
+use crate::nomad::node::NomadNode;
+use anyhow::{anyhow, Result};
+use clap::ArgMatches;
+use prettytable::{cell, format, row, Table};
+use regex::Regex;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Placed,
+    Failed,
+    Skipped,
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let outcome = match *self {
+            Outcome::Placed => "PLACED",
+            Outcome::Failed => "FAILED",
+            Outcome::Skipped => "SKIPPED",
+        };
+        write!(f, "{}", outcome)
+    }
+}
+
+/// Where one allocation of the drained node would go.
+#[derive(Debug, Serialize)]
+struct Placement {
+    alloc: Uuid,
+    name: String,
+    namespace: String,
+    resources: Resources,
+    outcome: Outcome,
+    /// The node it would be placed on, or why it wouldn't be
+    detail: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Simulation {
+    node: String,
+    node_id: Uuid,
+    class: String,
+    /// Eligible nodes of the same class the allocations could move to
+    candidates: usize,
+    placements: Vec<Placement>,
+    placed: usize,
+    failed: usize,
+    skipped: usize,
+}
+
+type Definitions = HashMap<(String, String), Result<JobDefinition, String>>;
+
+/// Simulate draining a node: bin-pack its allocations onto the other
+/// eligible nodes of its class, honouring the jobs' datacenters,
+/// constraints, drivers and host volumes, without touching the cluster.
+pub async fn drain(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let needle = sub.get_one::<String>("needle").unwrap();
+    let json = sub.is_present("json");
+    let strict = sub.is_present("strict");
+    let cluster = cluster.await??;
+
+    let node = cluster.nodes.clone().find_needle(needle)?;
+    let client = node
+        .nomad_client
+        .ok_or_else(|| anyhow!("{} ({}) has no Nomad client", node.name, node.id))?;
+
+    let mut failures = Vec::new();
+    let (draining, others): (Vec<ClientCapacity>, Vec<ClientCapacity>) =
+        capacity::clients(&cluster, strict, &mut failures)
+            .await?
+            .into_iter()
+            .partition(|c| c.node.id == client.id);
+    let draining = draining
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("Nomad node {} couldn't be queried", client.id))?;
+    let mut candidates: Vec<ClientCapacity> = others
+        .into_iter()
+        .filter(|c| c.eligible() && c.node.node_class == draining.node.node_class)
+        .collect();
+
+    let jobs = definitions(&cluster, &draining.allocs).await?;
+
+    // largest first, so the big ones get the pick of the nodes
+    let mut allocs = draining.allocs.clone();
+    allocs.sort_by_key(|alloc| {
+        let need = Resources::used_by(alloc);
+        Reverse((need.memory, need.cpu))
+    });
+
+    let mut simulation = Simulation {
+        node: draining.node.name.clone(),
+        node_id: draining.node.id,
+        class: draining.node.node_class.clone(),
+        candidates: candidates.len(),
+        placements: Vec::with_capacity(allocs.len()),
+        placed: 0,
+        failed: 0,
+        skipped: 0,
+    };
+
+    for alloc in allocs.iter() {
+        let key = (alloc.namespace.clone(), alloc.job_id.clone());
+        let (outcome, detail) = match jobs.get(&key) {
+            Some(Ok(job)) => place(alloc, job, &draining, &mut candidates),
+            Some(Err(error)) => (
+                Outcome::Failed,
+                format!("job definition unavailable: {}", error),
+            ),
+            None => (Outcome::Failed, "job definition unavailable".to_owned()),
+        };
+        match outcome {
+            Outcome::Placed => simulation.placed += 1,
+            Outcome::Failed => simulation.failed += 1,
+            Outcome::Skipped => simulation.skipped += 1,
+        }
+        simulation.placements.push(Placement {
+            alloc: alloc.id,
+            name: alloc_name(alloc),
+            namespace: alloc.namespace.clone(),
+            resources: Resources::used_by(alloc),
+            outcome,
+            detail,
+        });
+    }
+    simulation.placements.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if json {
+        let stdout = io::stdout();
+        serde_json::to_writer_pretty(stdout.lock(), &simulation)?;
+        println!();
+    } else {
+        print(&simulation);
+    }
+    capacity::incomplete(&failures);
+
+    if simulation.failed > 0 {
+        return Err(anyhow!(
+            "{} allocations would fail to place",
+            simulation.failed
+        ));
+    }
+    Ok(())
+}
+
+/// Drain a Nomad client and wait until Nomad is done with it, picking up a
+/// drain that's already under way. Nomad stops what's left at `deadline`,
+/// so waiting much longer than that means something is off.
+pub(crate) async fn drain_client(
+    cluster: &BitteCluster,
+    id: Uuid,
+    deadline: Duration,
+) -> Result<()> {
+    let api = cluster
+        .nomad_api_client
+        .clone()
+        .ok_or_else(|| anyhow!("a Nomad token is required to drain clients"))?;
+    let addr = cluster.endpoints.nomad.addr.clone();
+    let get = || NomadNode::get(Arc::clone(&api), addr.clone(), id, cluster.policy.clone());
+
+    let node = get().await?;
+    if node.drain {
+        eprintln!("==> {} is already draining", node.name);
+    } else {
+        NomadNode::drain(
+            Arc::clone(&api),
+            addr.clone(),
+            id,
+            deadline,
+            &cluster.policy,
+        )
+        .await?;
+        eprintln!("==> draining {} ({})", node.name, id);
+    }
+
+    let started = Instant::now();
+    let timeout = deadline + Duration::from_secs(300);
+    loop {
+        let node = get().await?;
+        if !node.drain {
+            eprintln!("==> {} is drained", node.name);
+            return Ok(());
+        }
+        if started.elapsed() > timeout {
+            return Err(anyhow!(
+                "{} is still draining after {}",
+                node.name,
+                humantime::format_duration(Duration::from_secs(started.elapsed().as_secs()))
+            ));
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Fetch the definition of every job with an allocation on the node.
+async fn definitions(cluster: &BitteCluster, allocs: &[NomadAlloc]) -> Result<Definitions> {
+    let api = cluster
+        .nomad_api_client
+        .clone()
+        .ok_or_else(|| anyhow!("a Nomad token is required to read the jobs"))?;
+
+    let mut handles = BTreeMap::new();
+    for alloc in allocs.iter() {
+        let key = (alloc.namespace.clone(), alloc.job_id.clone());
+        if handles.contains_key(&key) {
+            continue;
+        }
+        let handle = tokio::spawn(JobDefinition::get(
+            Arc::clone(&api),
+            cluster.endpoints.nomad.addr.clone(),
+            key.0.clone(),
+            key.1.clone(),
+            cluster.policy.clone(),
+        ));
+        handles.insert(key, handle);
+    }
+
+    let mut jobs = HashMap::with_capacity(handles.len());
+    for (key, handle) in handles {
+        let job = handle.await?.map_err(|error| format!("{:#}", error));
+        jobs.insert(key, job);
+    }
+    Ok(jobs)
+}
+
+/// Place the allocation on the candidate it fills up the most, like
+/// Nomad's binpack scoring, and account for it there. Otherwise explain
+/// why no candidate would take it, in the spirit of Nomad's placement
+/// metrics.
+fn place(
+    alloc: &NomadAlloc,
+    job: &JobDefinition,
+    draining: &ClientCapacity,
+    candidates: &mut [ClientCapacity],
+) -> (Outcome, String) {
+    if job.is_system() {
+        return (
+            Outcome::Skipped,
+            format!("{} jobs aren't migrated", job.job_type),
+        );
+    }
+
+    let group = job.task_group(&alloc.task_group);
+    let need = Resources::used_by(alloc);
+    let mut filtered: BTreeMap<String, usize> = BTreeMap::new();
+    let mut exhausted: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut best: Option<(usize, (u64, u64))> = None;
+
+    for (i, candidate) in candidates.iter().enumerate() {
+        if let Some(reason) = filter(alloc, job, group, candidate) {
+            *filtered.entry(reason).or_default() += 1;
+            continue;
+        }
+        let free = candidate.free();
+        if let Some(dimension) = free.exhausted(need) {
+            *exhausted.entry(dimension).or_default() += 1;
+            continue;
+        }
+        let left = free.saturating_sub(need);
+        let left = (left.memory, left.cpu);
+        if best.map(|(_, best)| left < best).unwrap_or(true) {
+            best = Some((i, left));
+        }
+    }
+
+    if let Some((i, _)) = best {
+        let candidate = &mut candidates[i];
+        candidate.used.add(need);
+        candidate.allocs.push(alloc.clone());
+        return (Outcome::Placed, candidate.node.name.clone());
+    }
+
+    if candidates.is_empty() {
+        let class = Some(draining.node.node_class.as_str())
+            .filter(|class| !class.is_empty())
+            .unwrap_or("-");
+        return (
+            Outcome::Failed,
+            format!("no other eligible nodes of class {}", class),
+        );
+    }
+
+    let mut reasons = Vec::new();
+    if !filtered.is_empty() {
+        reasons.push(format!("filtered: {}", counts(filtered)));
+    }
+    if !exhausted.is_empty() {
+        reasons.push(format!("exhausted: {}", counts(exhausted)));
+    }
+    (Outcome::Failed, reasons.join("; "))
+}
+
+fn counts<K: Display>(counts: BTreeMap<K, usize>) -> String {
+    counts
+        .into_iter()
+        .map(|(reason, count)| format!("{} ({})", reason, count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Why the candidate can't run the allocation regardless of its free
+/// resources, if it can't.
+fn filter(
+    alloc: &NomadAlloc,
+    job: &JobDefinition,
+    group: Option<&TaskGroupDefinition>,
+    candidate: &ClientCapacity,
+) -> Option<String> {
+    let node = &candidate.node;
+
+    // newer Nomads default to any datacenter
+    let in_datacenter = job.datacenters.is_empty()
+        || job
+            .datacenters
+            .iter()
+            .any(|dc| datacenter_matches(dc, &node.datacenter));
+    if !in_datacenter {
+        return Some("datacenter".to_owned());
+    }
+
+    let job_constraints = job.constraints.iter().flatten();
+    let group_constraints = group
+        .and_then(|group| group.constraints.as_ref())
+        .into_iter()
+        .flatten();
+    let task_constraints = group
+        .into_iter()
+        .flat_map(|group| group.tasks.iter())
+        .flat_map(|task| task.constraints.iter().flatten());
+    let constraints: Vec<&Constraint> = job_constraints
+        .chain(group_constraints)
+        .chain(task_constraints)
+        .collect();
+    if let Some(constraint) = constraints.iter().find(|c| !c.matches(node)) {
+        return Some(format!("constraint {}", constraint));
+    }
+
+    let job_distinct = job
+        .constraints
+        .iter()
+        .flatten()
+        .any(|c| c.is_distinct_hosts());
+    let group_distinct = group
+        .and_then(|group| group.constraints.as_ref())
+        .map(|constraints| constraints.iter().any(|c| c.is_distinct_hosts()))
+        .unwrap_or(false);
+    let collides = candidate.allocs.iter().any(|other| {
+        other.namespace == alloc.namespace
+            && other.job_id == alloc.job_id
+            && (job_distinct || (group_distinct && other.task_group == alloc.task_group))
+    });
+    if collides {
+        return Some("distinct_hosts".to_owned());
+    }
+
+    let group = group?;
+
+    for task in group.tasks.iter() {
+        let usable = node
+            .drivers
+            .as_ref()
+            .and_then(|drivers| drivers.get(&task.driver))
+            .map(|driver| driver.detected && driver.healthy)
+            .unwrap_or(false);
+        if !usable {
+            return Some(format!("missing driver {}", task.driver));
+        }
+    }
+
+    for volume in group.volumes.iter().flat_map(|volumes| volumes.values()) {
+        let present = node
+            .host_volumes
+            .as_ref()
+            .map(|volumes| volumes.contains_key(&volume.source))
+            .unwrap_or(false);
+        if volume.volume_type == "host" && !present {
+            return Some(format!("missing host volume {}", volume.source));
+        }
+    }
+
+    None
+}
+
+/// Job datacenters may be globs like `eu-*`.
+fn datacenter_matches(pattern: &str, datacenter: &str) -> bool {
+    if !pattern.contains('*') {
+        return pattern == datacenter;
+    }
+    let pattern = format!("^{}$", regex::escape(pattern).replace("\\*", ".*"));
+    Regex::new(&pattern)
+        .map(|re| re.is_match(datacenter))
+        .unwrap_or(false)
+}
+
+fn print(simulation: &Simulation) {
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    table.add_row(row![b =>
+        "ALLOC",
+        "NAME",
+        "CPU (MHz)",
+        "MEMORY (MB)",
+        "OUTCOME",
+        "DETAIL",
+    ]);
+    for placement in simulation.placements.iter() {
+        table.add_row(row![
+            placement.alloc,
+            placement.name,
+            r -> placement.resources.cpu,
+            r -> placement.resources.memory,
+            placement.outcome,
+            placement.detail,
+        ]);
+    }
+    table.printstd();
+    println!(
+        "\nDraining {} onto {} eligible nodes: {} placed, {} failed, {} skipped",
+        simulation.node,
+        simulation.candidates,
+        simulation.placed,
+        simulation.failed,
+        simulation.skipped
+    );
+}
@@ -1,8 +1,10 @@
+use super::asg;
 use crate::bitte::ClusterHandle;
 use anyhow::Result;
 use clap::ArgMatches;
+use log::warn;
 use prettytable::{cell, format, row, Table};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::io;
 
 pub async fn info(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
@@ -22,30 +24,34 @@ async fn info_print(cluster: ClusterHandle, json: bool) -> Result<()> {
         core_nodes_table
             .add_row(row![ bc => format!("{} Core Instance", cluster.provider), "Private IP", "Public IP", "Zone"]);
 
-        let mut client_nodes_table_map: HashMap<String, Table> = HashMap::new();
+        let asgs = match asg::find(&cluster).await {
+            Ok(asgs) => asgs,
+            Err(error) => {
+                warn!("{:#}", error);
+                Vec::new()
+            }
+        };
+
+        let mut client_nodes_table_map: BTreeMap<String, Table> = BTreeMap::new();
 
         let mut nodes = cluster.nodes;
         nodes.sort();
 
         for node in nodes.into_iter() {
             match node.asg {
-                Some(_) => {
-                    let group: String = {
-                        match node.nomad_client {
-                            Some(client) => match client.node_class {
-                                Some(class) => format!(" ({})", class),
-                                None => "".to_string(),
-                            },
-                            None => "".to_string(),
-                        }
-                    };
+                Some(group) => {
+                    let class = node
+                        .nomad_client
+                        .and_then(|client| client.node_class)
+                        .unwrap_or_default();
 
                     let client_nodes_table =
                         client_nodes_table_map.entry(group.clone()).or_insert({
                             let mut client_nodes_table = Table::new();
                             client_nodes_table.set_format(*format::consts::FORMAT_BOX_CHARS);
                             client_nodes_table.add_row(row![ bc =>
-                                format!("{} Instance ID{}", cluster.provider, group),
+                                format!("{} Instance ID ({})", cluster.provider, group),
+                                "Class",
                                 "Private IP",
                                 "Public IP",
                                 "Zone",
@@ -54,6 +60,7 @@ async fn info_print(cluster: ClusterHandle, json: bool) -> Result<()> {
                         });
                     client_nodes_table.add_row(row![
                         node.id,
+                        class,
                         node.priv_ip,
                         node.pub_ip,
                         node.zone.unwrap_or_default(),
@@ -70,8 +77,28 @@ async fn info_print(cluster: ClusterHandle, json: bool) -> Result<()> {
             }
         }
         core_nodes_table.printstd();
-        for val in client_nodes_table_map.values() {
-            val.printstd();
+        for (group, table) in client_nodes_table_map.iter() {
+            if let Some(asg) = asgs.iter().find(|asg| &asg.name == group) {
+                println!("{} ({}): {}", asg.name, asg.region, asg::summary(asg));
+            }
+            table.printstd();
+        }
+        // groups without running instances have no table
+        for asg in asgs
+            .iter()
+            .filter(|asg| !client_nodes_table_map.contains_key(&asg.name))
+        {
+            println!("{} ({}): {}", asg.name, asg.region, asg::summary(asg));
+        }
+
+        for region in cluster.regions.iter() {
+            println!(
+                "{}: {} running instances ({} page{})",
+                region.region,
+                region.instances,
+                region.pages,
+                if region.pages == 1 { "" } else { "s" }
+            );
         }
     }
 
@@ -0,0 +1,293 @@
+use crate::bitte::ClusterHandle;
+use crate::cli::confirm;
+use crate::nomad::job::{
+    AllocMetric, DesiredUpdates, DiffType, FieldDiff, JobPlan, NomadJob, ObjectDiff,
+};
+use crate::nomad::schema::{self, Severity};
+use anyhow::{anyhow, Context, Result};
+use clap::ArgMatches;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+pub async fn plan(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let job = NomadJob::from_file(Path::new(sub.get_one::<String>("file").unwrap()))?;
+
+    let cluster = cluster.await??;
+    let client = cluster
+        .nomad_api_client
+        .context("a Nomad token is required to plan jobs")?;
+
+    let plan = job
+        .plan(client, cluster.endpoints.nomad.addr, &cluster.policy)
+        .await?;
+
+    if sub.is_present("json") {
+        let stdout = io::stdout();
+        let handle = stdout.lock();
+        serde_json::to_writer_pretty(handle, &plan)?;
+    } else {
+        print_plan(&plan);
+    }
+
+    Ok(())
+}
+
+pub async fn run(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let job = NomadJob::from_file(Path::new(sub.get_one::<String>("file").unwrap()))?;
+
+    let cluster = cluster.await??;
+    let client = cluster
+        .nomad_api_client
+        .context("a Nomad token is required to run jobs")?;
+
+    let plan = job
+        .plan(
+            client.clone(),
+            cluster.endpoints.nomad.addr.clone(),
+            &cluster.policy,
+        )
+        .await?;
+    print_plan(&plan);
+    println!();
+
+    if !sub.is_present("yes")
+        && !confirm(&format!(
+            "Run job \"{}\" in namespace \"{}\" on {}?",
+            job.id, job.namespace, cluster.name
+        ))?
+    {
+        println!("Aborted, job {} was not submitted.", job.id);
+        return Ok(());
+    }
+
+    let registration = job
+        .register(
+            client,
+            cluster.endpoints.nomad.addr,
+            plan.job_modify_index,
+            &cluster.policy,
+        )
+        .await?;
+
+    println!(
+        "Job \"{}\" submitted, evaluation ID: {}",
+        job.id, registration.eval_id
+    );
+    if let Some(warnings) = registration.warnings.filter(|w| !w.is_empty()) {
+        println!("Job Warnings:\n{}", warnings);
+    }
+
+    Ok(())
+}
+
+pub async fn validate(sub: &ArgMatches) -> Result<()> {
+    let files: Vec<String> = sub.get_many("files").unwrap_or_default().cloned().collect();
+    let mut failed = 0;
+
+    for file in files.iter() {
+        let job = NomadJob::from_file(Path::new(file))?;
+        let issues = schema::validate(job.spec);
+
+        let errors = issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Error)
+            .count();
+        if errors > 0 {
+            failed += 1;
+        }
+
+        for issue in issues.iter() {
+            println!("{}: {}", file, issue);
+        }
+        if issues.is_empty() {
+            println!("{}: job \"{}\" is valid", file, job.id);
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} of {} jobs failed validation",
+            failed,
+            files.len()
+        ));
+    }
+
+    Ok(())
+}
+
+fn print_plan(plan: &JobPlan) {
+    if let Some(diff) = &plan.diff {
+        let updates = plan
+            .annotations
+            .as_ref()
+            .and_then(|a| a.desired_tg_updates.clone())
+            .unwrap_or_default();
+        print_job_diff(diff, &updates);
+    }
+
+    println!("\nScheduler dry-run:");
+    let failed = plan.failed_tg_allocs.clone().unwrap_or_default();
+    if failed.is_empty() {
+        println!("- All tasks successfully allocated.");
+    } else {
+        println!("- WARNING: Failed to place all allocations.");
+        for (group, metric) in failed.iter() {
+            print_placement_failure(group, metric);
+        }
+    }
+
+    if let Some(warnings) = plan.warnings.as_ref().filter(|w| !w.is_empty()) {
+        println!("\nJob Warnings:\n{}", warnings);
+    }
+}
+
+fn print_job_diff(diff: &ObjectDiff, updates: &BTreeMap<String, DesiredUpdates>) {
+    println!("{} Job: \"{}\"", diff.diff_type.marker(), diff.name);
+    print_fields_and_objects(diff, 1);
+
+    for group in diff.task_groups.iter().flatten() {
+        let summary = match updates.get(&group.name) {
+            Some(u) => [
+                (u.place, "create"),
+                (u.stop, "destroy"),
+                (u.migrate, "migrate"),
+                (u.in_place_update, "in-place update"),
+                (u.destructive_update, "create/destroy update"),
+                (u.canary, "canary"),
+                (u.ignore, "ignore"),
+            ]
+            .iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, what)| format!("{} {}", count, what))
+            .collect::<Vec<String>>()
+            .join(", "),
+            None => String::new(),
+        };
+
+        if group.diff_type == DiffType::None && summary.is_empty() {
+            continue;
+        }
+
+        if summary.is_empty() {
+            println!(
+                "{} Task Group: \"{}\"",
+                group.diff_type.marker(),
+                group.name
+            );
+        } else {
+            println!(
+                "{} Task Group: \"{}\" ({})",
+                group.diff_type.marker(),
+                group.name,
+                summary
+            );
+        }
+        print_fields_and_objects(group, 1);
+
+        for task in group.tasks.iter().flatten() {
+            if task.diff_type == DiffType::None {
+                continue;
+            }
+            let annotations = task.annotations.clone().unwrap_or_default();
+            if annotations.is_empty() {
+                println!("  {} Task: \"{}\"", task.diff_type.marker(), task.name);
+            } else {
+                println!(
+                    "  {} Task: \"{}\" ({})",
+                    task.diff_type.marker(),
+                    task.name,
+                    annotations.join(", ")
+                );
+            }
+            print_fields_and_objects(task, 2);
+        }
+    }
+}
+
+fn print_fields_and_objects(diff: &ObjectDiff, depth: usize) {
+    let indent = "  ".repeat(depth);
+
+    for field in diff.fields.iter().flatten() {
+        if field.diff_type != DiffType::None {
+            println!("{}{}", indent, format_field(field));
+        }
+    }
+
+    for object in diff.objects.iter().flatten() {
+        if object.diff_type == DiffType::None {
+            continue;
+        }
+        println!("{}{} {} {{", indent, object.diff_type.marker(), object.name);
+        print_fields_and_objects(object, depth + 1);
+        println!("{}}}", indent);
+    }
+}
+
+fn format_field(field: &FieldDiff) -> String {
+    let line = match field.diff_type {
+        DiffType::Added => format!("+ {}: \"{}\"", field.name, field.new),
+        DiffType::Deleted => format!("- {}: \"{}\"", field.name, field.old),
+        _ => format!(
+            "{} {}: \"{}\" => \"{}\"",
+            field.diff_type.marker(),
+            field.name,
+            field.old,
+            field.new
+        ),
+    };
+
+    match field.annotations.as_ref().filter(|a| !a.is_empty()) {
+        Some(annotations) => format!("{} ({})", line, annotations.join(", ")),
+        None => line,
+    }
+}
+
+fn print_placement_failure(group: &str, metric: &AllocMetric) {
+    println!(
+        "  Task Group \"{}\" (failed to place {} allocation{}):",
+        group,
+        metric.coalesced_failures + 1,
+        if metric.coalesced_failures > 0 {
+            "s"
+        } else {
+            ""
+        }
+    );
+
+    if metric.nodes_evaluated == 0 {
+        println!("    * No nodes were eligible for evaluation");
+    }
+    for (dc, available) in metric.nodes_available.iter().flatten() {
+        if *available == 0 {
+            println!("    * No nodes are available in datacenter \"{}\"", dc);
+        }
+    }
+    for (class, count) in metric.class_filtered.iter().flatten() {
+        println!(
+            "    * Class \"{}\": {} nodes excluded by filter",
+            class, count
+        );
+    }
+    for (constraint, count) in metric.constraint_filtered.iter().flatten() {
+        println!(
+            "    * Constraint \"{}\": {} nodes excluded by filter",
+            constraint, count
+        );
+    }
+    if metric.nodes_exhausted > 0 {
+        println!(
+            "    * Resources exhausted on {} nodes",
+            metric.nodes_exhausted
+        );
+    }
+    for (dimension, count) in metric.dimension_exhausted.iter().flatten() {
+        println!(
+            "    * Dimension \"{}\" exhausted on {} nodes",
+            dimension, count
+        );
+    }
+    for quota in metric.quota_exhausted.iter().flatten() {
+        println!("    * Quota limit hit \"{}\"", quota);
+    }
+}
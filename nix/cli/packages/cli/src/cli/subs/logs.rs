@@ -0,0 +1,280 @@
+use crate::bitte::policy::RequestPolicy;
+use crate::bitte::{BitteFind, ClusterHandle};
+use crate::nomad::alloc::NomadAlloc;
+use crate::nomad::fs::{self, LogOrigin, LogRequest, LogType};
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use clap::ArgMatches;
+use log::{error, info};
+use reqwest::{Client, Response};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio::time::{timeout_at, Instant};
+
+/// Nomad's own CLI assumes this many bytes per line when tailing.
+const TAIL_LINE_BYTES: u64 = 120;
+/// How long the initial backlog is collected before `--tail` trims it.
+const TAIL_WINDOW: Duration = Duration::from_millis(500);
+
+/// A single line read from one of the log streams.
+struct LogLine {
+    prefix: String,
+    log_type: LogType,
+    line: String,
+}
+
+/// What the log streams report back; failures are reported as they happen,
+/// since with `--follow` the other streams never end.
+enum LogEvent {
+    Line(LogLine),
+    Failed(String, anyhow::Error),
+}
+
+pub async fn logs(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let job = sub.get_one::<String>("job").unwrap();
+    let group = sub.get_one::<String>("group").map(AsRef::as_ref);
+    let task = sub.get_one::<String>("task").cloned();
+    let tail = sub.get_one::<usize>("tail").copied();
+    let since = sub.get_one::<Duration>("since").copied();
+    let follow = !sub.is_present("no-follow");
+
+    let namespace = sub
+        .get_one::<String>("namespace")
+        .unwrap_or(&"default".to_string())
+        .to_owned();
+
+    let cluster = cluster.await??;
+    let client = cluster
+        .nomad_api_client
+        .context("a Nomad token is required to read allocation logs")?;
+
+    let allocs: Vec<NomadAlloc> = cluster
+        .nodes
+        .find_job_allocs(job, group, &namespace)
+        .into_iter()
+        .map(|(_, alloc)| alloc)
+        .collect();
+
+    if allocs.is_empty() {
+        return Err(anyhow!(
+            "{} does not match any running nomad allocations in namespace {}",
+            job,
+            namespace
+        ));
+    }
+
+    let mut streams: Vec<(String, NomadAlloc, String)> = Vec::new();
+    for alloc in allocs.into_iter() {
+        let tasks = match &task {
+            Some(task) => vec![task.to_owned()],
+            None => {
+                alloc
+                    .find_tasks(
+                        Arc::clone(&client),
+                        cluster.endpoints.nomad.addr.clone(),
+                        &cluster.policy,
+                    )
+                    .await?
+            }
+        };
+        for task in tasks.into_iter() {
+            let prefix = format!(
+                "{}[{}] {}",
+                alloc.task_group,
+                alloc.index.get().unwrap_or_default(),
+                task
+            );
+            streams.push((prefix, alloc.clone(), task));
+        }
+    }
+
+    let width = streams
+        .iter()
+        .map(|(prefix, _, _)| prefix.len())
+        .max()
+        .unwrap_or_default();
+
+    let (tx, mut rx) = unbounded_channel::<LogEvent>();
+    let mut handles: Vec<JoinHandle<()>> = Vec::with_capacity(streams.len() * 2);
+
+    for (prefix, alloc, task) in streams.into_iter() {
+        for log_type in [LogType::Stdout, LogType::Stderr] {
+            let (origin, offset) = match (tail, since) {
+                (Some(lines), _) => (LogOrigin::End, lines as u64 * TAIL_LINE_BYTES),
+                (None, Some(since)) => (
+                    LogOrigin::Start,
+                    since_offset(
+                        Arc::clone(&client),
+                        cluster.endpoints.nomad.addr.clone(),
+                        &alloc,
+                        &task,
+                        log_type,
+                        since,
+                        &cluster.policy,
+                    )
+                    .await?,
+                ),
+                (None, None) => (LogOrigin::Start, 0),
+            };
+
+            let request = LogRequest {
+                task: task.clone(),
+                log_type,
+                origin,
+                offset,
+                follow,
+            };
+            info!("{}: {:?}", prefix, request);
+
+            let client = Arc::clone(&client);
+            let addr = cluster.endpoints.nomad.addr.clone();
+            let prefix = format!("{:width$}", prefix, width = width);
+            let tx = tx.clone();
+            let alloc_id = alloc.id;
+
+            handles.push(tokio::spawn(async move {
+                let result = match fs::logs(client, addr, alloc_id, &request).await {
+                    Ok(response) => stream_log(response, &prefix, log_type, tail, &tx).await,
+                    Err(error) => Err(error),
+                };
+                if let Err(error) = result {
+                    // the receiver only goes away once we're exiting anyway
+                    let _ = tx.send(LogEvent::Failed(prefix, error));
+                }
+            }));
+        }
+    }
+
+    drop(tx);
+
+    let mut failed = 0;
+    while let Some(event) = rx.recv().await {
+        match event {
+            LogEvent::Line(log_line) => match log_line.log_type {
+                LogType::Stdout => println!("{} | {}", log_line.prefix, log_line.line),
+                LogType::Stderr => eprintln!("{} | {}", log_line.prefix, log_line.line),
+            },
+            LogEvent::Failed(prefix, error) => {
+                failed += 1;
+                error!("{} {:#}", prefix.trim_end(), error);
+            }
+        }
+    }
+
+    let streams = handles.len();
+    for handle in handles.into_iter() {
+        handle.await?;
+    }
+
+    if failed > 0 {
+        return Err(anyhow!("{} of {} log streams failed", failed, streams));
+    }
+
+    Ok(())
+}
+
+/// Nomad has no notion of time in its log API, so `--since` skips every
+/// rotated log file that was last written before the cutoff.
+async fn since_offset(
+    client: Arc<Client>,
+    addr: String,
+    alloc: &NomadAlloc,
+    task: &str,
+    log_type: LogType,
+    since: Duration,
+    policy: &RequestPolicy,
+) -> Result<u64> {
+    let cutoff = Utc::now() - chrono::Duration::from_std(since)?;
+    let prefix = format!("{}.{}.", task, log_type);
+
+    let offset = fs::ls(client, addr, alloc.id, "alloc/logs", policy)
+        .await?
+        .into_iter()
+        .filter(|file| !file.is_dir && file.name.starts_with(&prefix) && file.mod_time < cutoff)
+        .map(|file| file.size)
+        .sum();
+
+    Ok(offset)
+}
+
+async fn stream_log(
+    mut response: Response,
+    prefix: &str,
+    log_type: LogType,
+    tail: Option<usize>,
+    tx: &UnboundedSender<LogEvent>,
+) -> Result<()> {
+    let mut buf: Vec<u8> = Vec::new();
+    let mut backlog: Option<VecDeque<String>> = tail.map(|_| VecDeque::new());
+    let deadline = Instant::now() + TAIL_WINDOW;
+
+    let send = |line: String| {
+        // the receiver only goes away once we're exiting anyway
+        let _ = tx.send(LogEvent::Line(LogLine {
+            prefix: prefix.to_owned(),
+            log_type,
+            line,
+        }));
+    };
+
+    loop {
+        let chunk = if backlog.is_some() {
+            match timeout_at(deadline, response.chunk()).await {
+                Ok(chunk) => chunk?,
+                Err(_) => {
+                    backlog
+                        .take()
+                        .unwrap_or_default()
+                        .into_iter()
+                        .for_each(&send);
+                    continue;
+                }
+            }
+        } else {
+            response.chunk().await?
+        };
+
+        let chunk = match chunk {
+            Some(chunk) => chunk,
+            None => break,
+        };
+
+        buf.extend_from_slice(&chunk);
+        while let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buf.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line[..line.len() - 1]).into_owned();
+            push_line(&mut backlog, tail, line, &send);
+        }
+    }
+
+    if !buf.is_empty() {
+        let line = String::from_utf8_lossy(&buf).into_owned();
+        push_line(&mut backlog, tail, line, &send);
+    }
+
+    backlog.unwrap_or_default().into_iter().for_each(send);
+
+    Ok(())
+}
+
+/// Keep only the last `tail` lines while the backlog is still collected,
+/// and pass lines straight through afterwards.
+fn push_line<F: Fn(String)>(
+    backlog: &mut Option<VecDeque<String>>,
+    tail: Option<usize>,
+    line: String,
+    send: &F,
+) {
+    match backlog.as_mut() {
+        Some(backlog) => {
+            backlog.push_back(line);
+            if backlog.len() > tail.unwrap_or_default() {
+                backlog.pop_front();
+            }
+        }
+        None => send(line),
+    }
+}
@@ -1,22 +1,69 @@
+pub mod asg;
+pub mod bootstrap;
+pub mod capacity;
+pub mod context;
+pub mod cp;
 pub mod deploy;
+pub mod describe;
+pub mod doctor;
+pub mod drain;
 pub mod info;
+pub mod job;
+pub mod logs;
+pub mod node;
+pub mod orphans;
+pub mod replace;
+pub mod snapshot;
 pub mod ssh;
+pub mod tunnel;
+pub mod vault;
 
 pub use self::deploy::deploy;
+pub use bootstrap::bootstrap;
+pub use capacity::capacity;
+pub use cp::cp;
+pub use describe::describe;
+pub use doctor::doctor;
+pub use drain::drain;
 pub use info::info;
+pub use logs::logs;
+pub use orphans::orphans;
+pub use replace::replace;
 pub use ssh::ssh;
+pub use tunnel::tunnel;
 
+use crate::bitte::age;
+use crate::bitte::endpoint::Service;
+use crate::bitte::tunnel::TunnelTarget;
 use crate::cli::opts::{Globals, Nomad};
 use crate::deploy_rs::data as deployData;
 use crate::deploy_rs::settings as deploySettings;
 use clap::Parser;
 use clap_complete::Shell;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser)]
 pub enum SubCommands {
     Info(Info),
+    Describe(Describe),
+    Capacity(Capacity),
+    Drain(Drain),
+    Replace(Replace),
+    Node(Node),
     Ssh(Ssh),
     Deploy(Deploy),
+    Logs(Logs),
+    Cp(Cp),
+    Job(Job),
+    Tunnel(Tunnel),
+    Asg(Asg),
+    Snapshot(Snapshot),
+    Vault(Vault),
+    Bootstrap(Bootstrap),
+    Doctor(Doctor),
+    Orphans(Orphans),
+    Context(Context),
     Completions(Completions),
 }
 
@@ -32,6 +79,135 @@ pub struct Info {
     nomad: Nomad,
 }
 
+#[derive(Parser)]
+/// Show everything about a single node: EC2, Nomad, allocations and Consul checks
+pub struct Describe {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(short, long)]
+    /// output as JSON
+    json: bool,
+    #[clap(flatten)]
+    nomad: Nomad,
+    /// the node to describe; matches private & public ip, node name,
+    /// aws client id and Nomad node id
+    needle: String,
+}
+
+#[derive(Parser)]
+/// Show the headroom of the Nomad clients per node class and auto-scaling group
+pub struct Capacity {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(short, long)]
+    /// output as JSON
+    json: bool,
+    #[clap(flatten)]
+    nomad: Nomad,
+}
+
+#[derive(Parser)]
+/// Simulate moving a node's allocations onto the other nodes of its class
+pub struct Drain {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(short, long)]
+    /// output as JSON
+    json: bool,
+    #[clap(long, required = true)]
+    /// only report where the allocations would go and what would fail to
+    /// place, without touching the cluster
+    what_if: bool,
+    #[clap(flatten)]
+    nomad: Nomad,
+    /// the node to drain; matches private & public ip, node name,
+    /// aws client id and Nomad node id
+    needle: String,
+}
+
+#[derive(Parser)]
+/// Replace a Nomad client with a fresh instance from its auto-scaling group;
+/// run it again to resume an interrupted replacement
+pub struct Replace {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(long)]
+    /// detach the instance from its group and leave it running, instead of
+    /// terminating it
+    detach: bool,
+    #[clap(long)]
+    /// only show what would be done
+    dry_run: bool,
+    #[clap(long, short)]
+    /// don't ask for confirmation
+    yes: bool,
+    #[clap(
+        long,
+        value_name = "DURATION",
+        default_value = "1h",
+        value_parser = humantime::parse_duration
+    )]
+    /// how long allocations get to migrate before Nomad stops them
+    deadline: Duration,
+    #[clap(
+        long,
+        value_name = "DURATION",
+        default_value = "20m",
+        value_parser = humantime::parse_duration
+    )]
+    /// how long to wait for the replacement to be ready in Nomad
+    wait: Duration,
+    #[clap(flatten)]
+    nomad: Nomad,
+    /// the client to replace; matches private & public ip, node name,
+    /// aws client id and Nomad node id
+    needle: String,
+}
+
+#[derive(Parser)]
+/// Reboot, stop or terminate instances, draining their Nomad clients first
+pub struct Node {
+    #[clap(subcommand)]
+    commands: NodeCommands,
+}
+
+#[derive(Parser)]
+pub enum NodeCommands {
+    /// Reboot the instances
+    Reboot(NodeAction),
+    /// Stop the instances; auto-scaling groups replace theirs
+    Stop(NodeAction),
+    /// Terminate the instances; auto-scaling groups replace theirs
+    Terminate(NodeAction),
+}
+
+#[derive(Parser)]
+pub struct NodeAction {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(long)]
+    /// act on more than one core node at once, or on one while Consul, Nomad
+    /// or Vault lack a leader or a raft peer
+    force: bool,
+    #[clap(long, short)]
+    /// don't ask for confirmation
+    yes: bool,
+    #[clap(
+        long,
+        value_name = "DURATION",
+        default_value = "1h",
+        value_parser = humantime::parse_duration
+    )]
+    /// how long allocations get to migrate before Nomad stops them
+    deadline: Duration,
+    #[clap(flatten)]
+    nomad: Nomad,
+    #[clap(required = true)]
+    /// the instances to act on; matches private & public ip, node name,
+    /// aws client id and Nomad node id
+    needles: Vec<String>,
+}
+
 #[derive(Parser, Default)]
 /// Deploy core and client nodes
 pub struct Deploy {
@@ -40,6 +216,27 @@ pub struct Deploy {
     #[clap(long, short = 'l')]
     /// (re-)deploy all client nodes
     pub clients: bool,
+    #[clap(long, conflicts_with = "clients")]
+    /// deploy the Consul, Nomad and Vault servers one at a time, followers
+    /// first and the leader last, waiting for quorum after each
+    pub core: bool,
+    #[clap(
+        long,
+        value_name = "DURATION",
+        default_value = "10m",
+        value_parser = humantime::parse_duration
+    )]
+    /// with '--core': how long to wait for quorum after each node
+    pub quorum_timeout: Duration,
+    #[clap(long, env = "BITTE_DEPLOY_CORE_NODE", hide = true)]
+    /// set by '--core' for the process that deploys a single core node
+    pub core_node: Option<String>,
+    #[clap(long)]
+    /// exit with an error if the deployment fails
+    pub fail_fast: bool,
+    #[clap(long)]
+    /// save snapshots of Consul, Nomad and Vault before deploying anything
+    pub snapshot_first: bool,
     #[clap(flatten)]
     pub flags: deployData::Flags,
 
@@ -56,6 +253,303 @@ pub struct Deploy {
     /// the Nomad node class to filter clients against
     class: Option<String>,
 }
+#[derive(Parser)]
+/// Forward local ports to the cluster's APIs and web UIs over ssh
+pub struct Tunnel {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(arg_enum, required = true, value_parser = clap::value_parser!(TunnelTarget))]
+    /// services to forward
+    targets: Vec<TunnelTarget>,
+    #[clap(long, short, value_name = "PORT", value_parser = clap::value_parser!(u16))]
+    /// local port to listen on; only with a single service
+    port: Option<u16>,
+    #[clap(flatten)]
+    nomad: Nomad,
+}
+
+#[derive(Parser)]
+/// Inspect and scale the auto-scaling groups of the Nomad clients
+pub struct Asg {
+    #[clap(subcommand)]
+    commands: AsgCommands,
+}
+
+#[derive(Parser)]
+pub enum AsgCommands {
+    List(ListAsgs),
+    Scale(ScaleAsg),
+}
+
+#[derive(Parser)]
+/// Show the capacity, launch configuration and instances of each group
+pub struct ListAsgs {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(short, long)]
+    /// output as JSON
+    json: bool,
+    #[clap(flatten)]
+    nomad: Nomad,
+}
+
+#[derive(Parser)]
+/// Set the desired capacity of a group
+pub struct ScaleAsg {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(long, short)]
+    /// don't ask for confirmation
+    yes: bool,
+    #[clap(flatten)]
+    nomad: Nomad,
+    #[clap(value_name = "NAME")]
+    /// the name of the auto-scaling group
+    group: String,
+    #[clap(value_parser = clap::value_parser!(u32))]
+    /// the number of instances it should have
+    desired: u32,
+}
+
+#[derive(Parser)]
+/// Save and restore snapshots of Consul, Nomad and Vault
+pub struct Snapshot {
+    #[clap(subcommand)]
+    commands: SnapshotCommands,
+}
+
+#[derive(Parser)]
+pub enum SnapshotCommands {
+    Take(TakeSnapshot),
+    List(ListSnapshots),
+    Fetch(FetchSnapshots),
+    Restore(RestoreSnapshot),
+}
+
+#[derive(Parser)]
+/// Save snapshots through the services' APIs
+pub struct TakeSnapshot {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(long = "service", short, arg_enum, value_parser = clap::value_parser!(Service))]
+    /// services to snapshot [default: all]
+    service: Vec<Service>,
+    #[clap(long, value_name = "DIR", value_parser = clap::value_parser!(PathBuf))]
+    /// where snapshots are stored [default: $BITTE_SNAPSHOT_DIR or ~/.local/share/bitte/snapshots]
+    dir: Option<PathBuf>,
+    #[clap(flatten)]
+    nomad: Nomad,
+}
+
+#[derive(Parser)]
+/// List the snapshots stored for the cluster
+pub struct ListSnapshots {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(long = "service", short, arg_enum, value_parser = clap::value_parser!(Service))]
+    /// only list snapshots of these services
+    service: Vec<Service>,
+    #[clap(long, value_name = "DIR", value_parser = clap::value_parser!(PathBuf))]
+    /// where snapshots are stored [default: $BITTE_SNAPSHOT_DIR or ~/.local/share/bitte/snapshots]
+    dir: Option<PathBuf>,
+}
+
+#[derive(Parser)]
+/// Copy the newest scheduled snapshots from the core nodes
+pub struct FetchSnapshots {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(long = "service", short, arg_enum, value_parser = clap::value_parser!(Service))]
+    /// services to fetch snapshots of [default: all]
+    service: Vec<Service>,
+    #[clap(long, short, default_value = "hourly", value_parser = ["hourly", "daily", "custom"])]
+    /// the snapshot job of hashi-snapshots to fetch from
+    job: String,
+    #[clap(long, value_name = "DIR", value_parser = clap::value_parser!(PathBuf))]
+    /// where snapshots are stored [default: $BITTE_SNAPSHOT_DIR or ~/.local/share/bitte/snapshots]
+    dir: Option<PathBuf>,
+    #[clap(flatten)]
+    nomad: Nomad,
+}
+
+#[derive(Parser)]
+/// Replace a service's state with a snapshot
+pub struct RestoreSnapshot {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(value_parser = clap::value_parser!(PathBuf))]
+    /// the snapshot file
+    file: PathBuf,
+    #[clap(long, short, arg_enum, value_parser = clap::value_parser!(Service))]
+    /// the service to restore into, for files not named by `bitte snapshot`
+    service: Option<Service>,
+    #[clap(long)]
+    /// let Vault restore a snapshot taken with other unseal keys
+    force: bool,
+    #[clap(long, short)]
+    /// don't ask for confirmation
+    yes: bool,
+    #[clap(flatten)]
+    nomad: Nomad,
+}
+
+#[derive(Parser)]
+/// Inspect and unseal the Vault servers on the core nodes
+pub struct Vault {
+    #[clap(subcommand)]
+    commands: VaultCommands,
+}
+
+#[derive(Parser)]
+pub enum VaultCommands {
+    Status(VaultStatus),
+    Unseal(VaultUnseal),
+}
+
+#[derive(Parser)]
+/// Show the seal, HA role and raft membership of each server
+pub struct VaultStatus {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(short, long)]
+    /// output as JSON
+    json: bool,
+    #[clap(flatten)]
+    nomad: Nomad,
+}
+
+#[derive(Parser)]
+/// Submit key shares to the sealed servers, one after another
+pub struct VaultUnseal {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(long = "key-file", short, value_name = "FILE", value_parser = clap::value_parser!(PathBuf))]
+    /// age-encrypted files with key shares, one per line or as the JSON
+    /// output of `vault operator init`; shares are asked for if not given
+    key_file: Vec<PathBuf>,
+    #[clap(
+        long,
+        short,
+        value_name = "FILE",
+        env = "BITTE_AGE_IDENTITY",
+        default_value = age::IDENTITY,
+        value_parser = clap::value_parser!(PathBuf)
+    )]
+    /// the age identity to decrypt the key files with
+    identity: PathBuf,
+    #[clap(flatten)]
+    nomad: Nomad,
+    /// only unseal these core nodes; takes needles to match against:
+    /// private & public ip, node name and aws client id
+    nodes: Vec<String>,
+}
+
+#[derive(Parser)]
+/// Initialize Vault and bootstrap the ACLs of Nomad once, or store what the
+/// core nodes bootstrapped them with
+pub struct Bootstrap {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(long, value_name = "DIR", default_value = "encrypted", value_parser = clap::value_parser!(PathBuf))]
+    /// where the encrypted keys and tokens are stored
+    dir: PathBuf,
+    #[clap(
+        long,
+        short,
+        value_name = "FILE",
+        env = "BITTE_AGE_IDENTITY",
+        default_value = age::IDENTITY,
+        value_parser = clap::value_parser!(PathBuf)
+    )]
+    /// the age identity to encrypt to
+    identity: PathBuf,
+    #[clap(long, value_name = "ARN")]
+    /// encrypt with sops and this KMS key instead of age, like AWS clusters do
+    kms: Option<String>,
+    #[clap(long, value_name = "N", default_value = "5", value_parser = clap::value_parser!(u32))]
+    /// how many Vault unseal or recovery key shares to create
+    key_shares: u32,
+    #[clap(long, value_name = "N", default_value = "3", value_parser = clap::value_parser!(u32))]
+    /// how many of the key shares unseal Vault
+    key_threshold: u32,
+    #[clap(flatten)]
+    nomad: Nomad,
+}
+
+#[derive(Parser)]
+/// Check the health of the cluster, failing if anything is broken
+pub struct Doctor {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(short, long)]
+    /// output as JSON
+    json: bool,
+    #[clap(long, value_name = "DAYS", default_value = "30", value_parser = clap::value_parser!(u32))]
+    /// warn about TLS certificates expiring within this many days
+    cert_days: u32,
+    #[clap(
+        long,
+        value_name = "DURATION",
+        default_value = "2s",
+        value_parser = humantime::parse_duration
+    )]
+    /// warn if the core nodes' clocks are further off than this, from each
+    /// other or from the local one
+    max_skew: Duration,
+    #[clap(flatten)]
+    nomad: Nomad,
+}
+
+#[derive(Parser)]
+/// Report Nomad clients and instances that don't match up
+pub struct Orphans {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(short, long)]
+    /// output as JSON
+    json: bool,
+    #[clap(long)]
+    /// purge the dead Nomad nodes that have no instance anymore
+    purge: bool,
+    #[clap(long, short, requires = "purge")]
+    /// don't ask for confirmation
+    yes: bool,
+    #[clap(flatten)]
+    nomad: Nomad,
+}
+
+#[derive(Parser)]
+/// Switch between clusters defined in ~/.config/bitte/config.toml
+pub struct Context {
+    #[clap(subcommand)]
+    commands: ContextCommands,
+}
+
+#[derive(Parser)]
+pub enum ContextCommands {
+    List(ListContexts),
+    Use(UseContext),
+    Show(ShowContext),
+}
+
+#[derive(Parser)]
+/// List the contexts, marking the one in use
+pub struct ListContexts {}
+
+#[derive(Parser)]
+/// Make a context the current one
+pub struct UseContext {
+    /// the context to use from now on
+    name: String,
+}
+
+#[derive(Parser)]
+/// Print a context's settings
+pub struct ShowContext {
+    /// the context to show, instead of the one in use
+    name: Option<String>,
+}
+
 #[derive(Parser)]
 /// Generate completions for the given shell
 pub struct Completions {
@@ -77,8 +571,20 @@ pub struct Ssh {
         value_names = &["JOB", "GROUP", "INDEX"],
     )]
     /// specify client by: job, group, alloc_index;
-    /// this will also 'cd' to the alloc dir if <ARGS> is empty
+    /// this will also 'cd' to the alloc dir if <ARGS> is empty.
+    /// The index may also be '*', a range like '0-2' or a list like '0,3'
+    /// to run <ARGS> on every matching allocation
     job: Option<String>,
+    #[clap(
+        long,
+        short = 's',
+        requires = "job",
+        value_name = "STATUS",
+        value_delimiter(',')
+    )]
+    /// client status of the allocations to match with `-j`, e.g. 'failed' or
+    /// 'pending,running' [default: running]
+    status: Vec<String>,
     #[clap(long, short, env = "NOMAD_NAMESPACE")]
     /// Nomad namespace to search for jobs with `-j`
     namespace: Option<String>,
@@ -115,3 +621,100 @@ pub struct Ssh {
     /// arguments to ssh
     args: Option<String>,
 }
+
+#[derive(Parser)]
+/// Stream task logs from all running allocations of a job
+pub struct Logs {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(long, short, env = "NOMAD_NAMESPACE")]
+    /// Nomad namespace of the job
+    namespace: Option<String>,
+    #[clap(flatten)]
+    nomad: Nomad,
+    #[clap(long, short, value_name = "LINES", conflicts_with = "since")]
+    /// start with roughly the last <LINES> lines of each task
+    tail: Option<usize>,
+    #[clap(long, short, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    /// skip rotated log files last written before this long ago, e.g. '2h'
+    since: Option<Duration>,
+    #[clap(long)]
+    /// exit after printing the current logs instead of following them
+    no_follow: bool,
+    /// the Nomad job to read logs from
+    job: String,
+    /// only read logs from this task group
+    group: Option<String>,
+    #[clap(requires = "group")]
+    /// only read logs from this task
+    task: Option<String>,
+}
+
+#[derive(Parser)]
+/// Copy files to and from allocation directories
+pub struct Cp {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(long, short, env = "NOMAD_NAMESPACE")]
+    /// Nomad namespace of the job
+    namespace: Option<String>,
+    #[clap(flatten)]
+    nomad: Nomad,
+    #[clap(long, short)]
+    /// copy directories recursively
+    recursive: bool,
+    /// file to copy; either a local path or JOB:GROUP:INDEX:/PATH
+    source: String,
+    /// where to copy to; either a local path or JOB:GROUP:INDEX:/PATH
+    destination: String,
+}
+
+#[derive(Parser)]
+/// Plan and submit Nomad jobs
+pub struct Job {
+    #[clap(subcommand)]
+    commands: JobCommands,
+}
+
+#[derive(Parser)]
+pub enum JobCommands {
+    Plan(PlanJob),
+    Run(RunJob),
+    Validate(ValidateJob),
+}
+
+#[derive(Parser)]
+/// Show what would change if a job was submitted
+pub struct PlanJob {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(short, long)]
+    /// output the plan as JSON
+    json: bool,
+    #[clap(flatten)]
+    nomad: Nomad,
+    /// the rendered job JSON file
+    file: String,
+}
+
+#[derive(Parser)]
+/// Plan a job and submit it after confirmation
+pub struct RunJob {
+    #[clap(flatten)]
+    globals: Globals,
+    #[clap(short, long)]
+    /// submit without asking for confirmation
+    yes: bool,
+    #[clap(flatten)]
+    nomad: Nomad,
+    /// the rendered job JSON file
+    file: String,
+}
+
+#[derive(Parser)]
+/// Check rendered jobs against the bundled Nomad job schema, offline
+pub struct ValidateJob {
+    #[clap(required = true)]
+    /// the rendered job JSON files
+    files: Vec<String>,
+}
@@ -0,0 +1,236 @@
+use super::drain::drain_client;
+use crate::bitte::node::BitteNode;
+use crate::bitte::raft::{Quorum, RaftState};
+use crate::bitte::{BitteCluster, BitteFind, ClusterHandle};
+use crate::cli;
+use anyhow::{anyhow, Context, Result};
+use clap::ArgMatches;
+use prettytable::{cell, format, row, Table};
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Reboot,
+    Stop,
+    Terminate,
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let action = match *self {
+            Action::Reboot => "reboot",
+            Action::Stop => "stop",
+            Action::Terminate => "terminate",
+        };
+        write!(f, "{}", action)
+    }
+}
+
+impl Action {
+    fn progressive(&self) -> &'static str {
+        match *self {
+            Action::Reboot => "rebooting",
+            Action::Stop => "stopping",
+            Action::Terminate => "terminating",
+        }
+    }
+}
+
+pub async fn reboot(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    act(sub, cluster, Action::Reboot).await
+}
+
+pub async fn stop(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    act(sub, cluster, Action::Stop).await
+}
+
+pub async fn terminate(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    act(sub, cluster, Action::Terminate).await
+}
+
+/// Every needle has to match, it's too easy to act on the wrong set of
+/// instances otherwise.
+fn find_nodes(cluster: &BitteCluster, needles: &[&String]) -> Result<Vec<BitteNode>> {
+    let mut nodes: Vec<BitteNode> = Vec::new();
+    for needle in needles {
+        let node = cluster.nodes.clone().find_needle(needle)?;
+        if !nodes.iter().any(|found| found.id == node.id) {
+            nodes.push(node);
+        }
+    }
+    nodes.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(nodes)
+}
+
+/// What becomes of the instance once the action is done.
+fn outcome(node: &BitteNode, action: Action) -> String {
+    match (&node.asg, action) {
+        (_, Action::Reboot) => "comes back".to_owned(),
+        (Some(asg), Action::Stop) => format!("replaced by {}, which sees it as unhealthy", asg),
+        (None, Action::Stop) => "stays stopped".to_owned(),
+        (Some(asg), Action::Terminate) => format!("replaced by {}", asg),
+        (None, Action::Terminate) => "not replaced".to_owned(),
+    }
+}
+
+/// Losing a core node is only safe while every service has a leader, a
+/// content autopilot and all core nodes among its raft peers.
+async fn quorum_problems(cluster: &BitteCluster) -> Result<Vec<String>> {
+    let quorum = Quorum::new(&cluster.endpoints, &cluster.policy)?;
+    let peers: Vec<String> = cluster
+        .nodes
+        .iter()
+        .filter(|node| node.is_core())
+        .map(|node| node.priv_ip.to_string())
+        .collect();
+    let problems = quorum
+        .states()
+        .await?
+        .iter()
+        .flat_map(|state| {
+            let expected = RaftState {
+                service: state.service,
+                servers: Some(peers.clone()),
+                leader: None,
+                healthy: None,
+            };
+            state.problems(&expected)
+        })
+        .collect();
+    Ok(problems)
+}
+
+async fn act(sub: &ArgMatches, cluster: ClusterHandle, action: Action) -> Result<()> {
+    let needles: Vec<&String> = sub.get_many::<String>("needles").unwrap().collect();
+    let force = sub.is_present("force");
+    let yes = sub.is_present("yes");
+    let deadline = *sub.get_one::<Duration>("deadline").unwrap();
+    let cluster = cluster.await??;
+
+    let nodes = find_nodes(&cluster, &needles)?;
+
+    let core = nodes.iter().filter(|node| node.is_core()).count();
+    if core > 1 && !force {
+        return Err(anyhow!(
+            "refusing to {} {} core nodes at once, it may cost Consul, Nomad and Vault their quorum; pass --force to do it anyway",
+            action,
+            core
+        ));
+    }
+    if core > 0 && !force {
+        let problems = quorum_problems(&cluster)
+            .await
+            .unwrap_or_else(|error| vec![format!("{:#}", error)]);
+        if !problems.is_empty() {
+            return Err(anyhow!(
+                "refusing to {} core nodes, the cluster isn't healthy: {}; pass --force to do it anyway",
+                action,
+                problems.join("; ")
+            ));
+        }
+    }
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    table.add_row(row![b => "NAME", "INSTANCE", "PRIVATE IP", "NOMAD", "AFTERWARDS"]);
+    for node in nodes.iter() {
+        let nomad = match &node.nomad_client {
+            Some(client) if client.is_down() => "down",
+            Some(_) => "drain first",
+            None => "-",
+        };
+        table.add_row(row![
+            node.name,
+            node.id,
+            node.priv_ip,
+            nomad,
+            outcome(node, action)
+        ]);
+    }
+    table.printstd();
+
+    let question = format!(
+        "Really {} {} instances of cluster {}?",
+        action,
+        nodes.len(),
+        cluster.name
+    );
+    if !yes && !cli::confirm(&question)? {
+        return Err(anyhow!("aborted"));
+    }
+
+    // allocations get to move while their clients are still up
+    for node in nodes.iter() {
+        if let Some(client) = node.nomad_client.as_ref().filter(|c| !c.is_down()) {
+            drain_client(&cluster, client.id, deadline).await?;
+        }
+    }
+
+    for node in nodes.iter() {
+        let region = node
+            .region()
+            .or_else(|| sub.get_one::<String>("aws-region").cloned())
+            .ok_or_else(|| anyhow!("the region of {} is unknown", node.id))?;
+        ec2_action(node, &region, action, &cluster).await?;
+        eprintln!("==> {} {} ({})", action.progressive(), node.name, node.id);
+    }
+
+    for node in nodes.iter() {
+        match (&node.asg, action, &node.nomad_client) {
+            (Some(asg), Action::Terminate | Action::Stop, _) => {
+                println!("{} will be replaced by {}.", node.id, asg)
+            }
+            (None, Action::Terminate, _) => {
+                println!(
+                    "{} isn't in an auto-scaling group, nothing replaces it.",
+                    node.id
+                )
+            }
+            (_, Action::Reboot, Some(client)) | (None, Action::Stop, Some(client))
+                if !client.is_down() =>
+            {
+                println!(
+                "{} stays ineligible once it's back, `nomad node eligibility -enable {}` puts it back to work.",
+                    node.name, client.id
+                )
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+async fn ec2_action(
+    node: &BitteNode,
+    region: &str,
+    action: Action,
+    cluster: &BitteCluster,
+) -> Result<()> {
+    let client = BitteNode::ec2_client(region, &cluster.policy).await?;
+    let result = match action {
+        Action::Reboot => client
+            .reboot_instances()
+            .instance_ids(&node.id)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(anyhow::Error::from),
+        Action::Stop => client
+            .stop_instances()
+            .instance_ids(&node.id)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(anyhow::Error::from),
+        Action::Terminate => client
+            .terminate_instances()
+            .instance_ids(&node.id)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(anyhow::Error::from),
+    };
+    result.with_context(|| format!("failed to {} {} in {}", action, node.id, region))
+}
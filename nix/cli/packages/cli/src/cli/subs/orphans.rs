@@ -0,0 +1,133 @@
+use crate::bitte::node::Orphans;
+use crate::bitte::ClusterHandle;
+use crate::cli;
+use anyhow::{anyhow, Result};
+use clap::ArgMatches;
+use prettytable::{cell, format, row, Table};
+use std::io;
+use std::sync::Arc;
+
+pub async fn orphans(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let json = sub.is_present("json");
+    let purge = sub.is_present("purge");
+    let yes = sub.is_present("yes");
+    if json && purge && !yes {
+        return Err(anyhow!("--purge with --json can't ask, pass --yes as well"));
+    }
+
+    let cluster = cluster.await??;
+    let (api, clients) = match (&cluster.nomad_api_client, &cluster.nomad_clients) {
+        (Some(api), Some(clients)) => (api, clients),
+        (None, _) => return Err(anyhow!("a Nomad token is required to find orphans")),
+        (Some(_), None) => return Err(anyhow!("the Nomad clients couldn't be listed")),
+    };
+    let orphans = Orphans::find(&cluster.nodes, clients);
+
+    if json {
+        let stdout = io::stdout();
+        serde_json::to_writer_pretty(stdout.lock(), &orphans)?;
+        println!();
+    } else {
+        print(&orphans);
+    }
+
+    if !purge {
+        return Ok(());
+    }
+
+    let dead: Vec<_> = orphans.dead().collect();
+    let live = orphans.clients.len() - dead.len();
+    if live > 0 {
+        eprintln!(
+            "{} live Nomad clients without an instance are left alone",
+            live
+        );
+    }
+    if dead.is_empty() {
+        eprintln!("No dead Nomad nodes to purge.");
+        return Ok(());
+    }
+
+    let question = format!(
+        "Purge {} dead Nomad nodes from cluster {}?",
+        dead.len(),
+        cluster.name
+    );
+    if !yes && !cli::confirm(&question)? {
+        return Err(anyhow!("aborted"));
+    }
+
+    for client in dead {
+        client
+            .purge(
+                Arc::clone(api),
+                cluster.endpoints.nomad.addr.clone(),
+                &cluster.policy,
+            )
+            .await?;
+        eprintln!(
+            "purged {} ({})",
+            client.name.as_deref().unwrap_or_default(),
+            client.id
+        );
+    }
+
+    Ok(())
+}
+
+fn print(orphans: &Orphans) {
+    if orphans.is_empty() {
+        println!("No orphans found.");
+        return;
+    }
+
+    if !orphans.clients.is_empty() {
+        println!("Nomad clients without an instance:");
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_CLEAN);
+        table.add_row(row![b => "NAME", "ID", "ADDRESS", "STATUS"]);
+        for client in orphans.clients.iter() {
+            table.add_row(row![
+                client.name.as_deref().unwrap_or_default(),
+                client.id,
+                client.address.map(|ip| ip.to_string()).unwrap_or_default(),
+                client.status.as_deref().unwrap_or_default(),
+            ]);
+        }
+        table.printstd();
+        println!();
+    }
+
+    if !orphans.instances.is_empty() {
+        println!("Instances whose Nomad client never registered:");
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_CLEAN);
+        table.add_row(row![b => "INSTANCE", "ASG", "PRIVATE IP", "ZONE"]);
+        for node in orphans.instances.iter() {
+            table.add_row(row![
+                node.id,
+                node.asg.as_deref().unwrap_or_default(),
+                node.priv_ip,
+                node.zone.as_deref().unwrap_or_default(),
+            ]);
+        }
+        table.printstd();
+        println!();
+    }
+
+    if !orphans.duplicates.is_empty() {
+        println!("Private IPs in use more than once:");
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_CLEAN);
+        table.add_row(row![b => "IP", "INSTANCES", "NOMAD CLIENTS"]);
+        for duplicate in orphans.duplicates.iter() {
+            table.add_row(row![
+                duplicate.ip,
+                duplicate.instances.join(", "),
+                duplicate.clients.join(", "),
+            ]);
+        }
+        table.printstd();
+        println!();
+    }
+}
@@ -0,0 +1,431 @@
+use super::drain::drain_client;
+use crate::bitte::asg::BitteAsg;
+use crate::bitte::node::BitteNode;
+use crate::bitte::{BitteCluster, BitteFind, ClusterHandle};
+use crate::cli;
+use crate::nomad::client::NomadClient;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Step {
+    Drain,
+    Remove,
+    Wait,
+}
+
+/// Whether the replacement is ready, or what it's still waiting for.
+enum Progress {
+    Ready(String, IpAddr, NomadClient),
+    Waiting(String),
+}
+
+/// A replacement in progress. It's saved before anything is touched and
+/// after every step, so an interrupted one picks up where it stopped even
+/// once the old instance is gone from discovery.
+#[derive(Debug, Serialize, Deserialize)]
+struct Replacement {
+    cluster: String,
+    instance: String,
+    name: String,
+    priv_ip: IpAddr,
+    nomad_id: Uuid,
+    asg: String,
+    region: String,
+    detach: bool,
+    /// The group's instances before the old one was removed; the
+    /// replacement is whichever instance is new
+    known: Vec<String>,
+    step: Step,
+    started: DateTime<Utc>,
+}
+
+/// `bitte/replacements` in the user's data directory, next to the snapshots.
+fn state_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("bitte")
+        .join("replacements")
+}
+
+impl Replacement {
+    fn path(&self) -> PathBuf {
+        state_dir().join(format!("{}-{}.json", self.cluster, self.instance))
+    }
+
+    /// The unfinished replacements of `cluster`.
+    fn load(cluster: &str) -> Result<Vec<Self>> {
+        let dir = state_dir();
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut result = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let file_name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default();
+            if !file_name.starts_with(&format!("{}-", cluster)) || !file_name.ends_with(".json") {
+                continue;
+            }
+            let file = std::fs::read(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            match serde_json::from_slice::<Self>(&file) {
+                Ok(replacement) if replacement.cluster == cluster => result.push(replacement),
+                Ok(_) => (),
+                Err(error) => warn!("ignoring {}: {}", path.display(), error),
+            }
+        }
+        Ok(result)
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = self.path();
+        std::fs::create_dir_all(state_dir())?;
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    fn finish(&self) -> Result<()> {
+        let path = self.path();
+        std::fs::remove_file(&path).with_context(|| format!("failed to remove {}", path.display()))
+    }
+
+    /// The same needles `find_needle` takes, for nodes that may be gone.
+    fn matches(&self, needle: &str) -> bool {
+        self.instance == needle
+            || self.name == needle
+            || self.priv_ip.to_string() == needle
+            || self.nomad_id.to_string() == needle
+    }
+
+    async fn start(cluster: &BitteCluster, node: BitteNode, detach: bool) -> Result<Self> {
+        let client = node.nomad_client.as_ref().ok_or_else(|| {
+            anyhow!(
+                "{} ({}) has no Nomad client; only clients are replaced",
+                node.name,
+                node.id
+            )
+        })?;
+        let asg = node.asg.clone().ok_or_else(|| {
+            anyhow!(
+                "{} ({}) isn't in an auto-scaling group, nothing would replace it",
+                node.name,
+                node.id
+            )
+        })?;
+        let region = node
+            .region()
+            .ok_or_else(|| anyhow!("the region of {} is unknown", node.id))?;
+        let group = find_asg(cluster, &asg, &region).await?;
+
+        Ok(Self {
+            cluster: cluster.name.clone(),
+            instance: node.id.clone(),
+            name: node.name.clone(),
+            priv_ip: node.priv_ip,
+            nomad_id: client.id,
+            asg,
+            region,
+            detach,
+            known: group.instances.into_iter().map(|i| i.id).collect(),
+            step: Step::Drain,
+            started: Utc::now(),
+        })
+    }
+
+    fn print_plan(&self, deadline: Duration, wait: Duration) {
+        let mark = |step: Step| if self.step > step { "[x]" } else { "[ ]" };
+        println!(
+            "Replacing {} ({}, {}) of {} in {}:",
+            self.name, self.instance, self.priv_ip, self.asg, self.region
+        );
+        println!(
+            "  {} drain Nomad client {}, stopping what's left after {}",
+            mark(Step::Drain),
+            self.nomad_id,
+            humantime::format_duration(deadline)
+        );
+        if self.detach {
+            println!(
+                "  {} detach {} from {}, leaving it running; the group starts a replacement",
+                mark(Step::Remove),
+                self.instance,
+                self.asg
+            );
+        } else {
+            println!(
+                "  {} terminate {} in {}; the group starts a replacement",
+                mark(Step::Remove),
+                self.instance,
+                self.asg
+            );
+        }
+        println!(
+            "  {} wait up to {} for the replacement to be ready and eligible in Nomad",
+            mark(Step::Wait),
+            humantime::format_duration(wait)
+        );
+    }
+
+    fn advance(&mut self, step: Step) -> Result<()> {
+        self.step = step;
+        self.save()
+    }
+}
+
+async fn find_asg(cluster: &BitteCluster, name: &str, region: &str) -> Result<BitteAsg> {
+    BitteAsg::find_asgs(&cluster.name, &[region.to_owned()], &cluster.policy)
+        .await?
+        .into_iter()
+        .find(|asg| asg.name == name)
+        .ok_or_else(|| anyhow!("no auto-scaling group {} in {}", name, region))
+}
+
+/// Replace a Nomad client with a fresh instance from its auto-scaling
+/// group: drain it, take it out of the group and wait for the replacement.
+pub async fn replace(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let needle = sub.get_one::<String>("needle").unwrap();
+    let detach = sub.is_present("detach");
+    let dry_run = sub.is_present("dry-run");
+    let yes = sub.is_present("yes");
+    let deadline = *sub.get_one::<Duration>("deadline").unwrap();
+    let wait = *sub.get_one::<Duration>("wait").unwrap();
+    let cluster = cluster.await??;
+
+    let saved = Replacement::load(&cluster.name)?
+        .into_iter()
+        .find(|replacement| replacement.matches(needle));
+    let resumed = saved.is_some();
+    let mut replacement = match saved {
+        Some(replacement) => {
+            eprintln!(
+                "==> resuming the replacement of {} started at {}",
+                replacement.instance,
+                replacement.started.format("%Y-%m-%d %H:%M:%S UTC")
+            );
+            replacement
+        }
+        None => {
+            let node = cluster.nodes.clone().find_needle(needle)?;
+            Replacement::start(&cluster, node, detach).await?
+        }
+    };
+
+    replacement.print_plan(deadline, wait);
+    if dry_run {
+        return Ok(());
+    }
+
+    if !resumed {
+        let question = format!("Replace {} ({})?", replacement.name, replacement.instance);
+        if !yes && !cli::confirm(&question)? {
+            return Err(anyhow!("aborted"));
+        }
+        replacement.save()?;
+    }
+
+    if replacement.step == Step::Drain {
+        drain_client(&cluster, replacement.nomad_id, deadline).await?;
+        replacement.advance(Step::Remove)?;
+    }
+
+    if replacement.step == Step::Remove {
+        remove(&cluster, &replacement).await?;
+        replacement.advance(Step::Wait)?;
+    }
+
+    let (instance, ip, client) = wait_for_replacement(&cluster, &replacement, wait).await?;
+    replacement.finish()?;
+
+    println!(
+        "Replaced {} with {} ({}), Nomad node {} ({}) is ready and eligible.",
+        replacement.instance,
+        instance,
+        ip,
+        client.name.as_deref().unwrap_or_default(),
+        client.id
+    );
+    if replacement.detach {
+        println!(
+            "{} is detached but still running; terminate it once you're done with it.",
+            replacement.instance
+        );
+    }
+
+    Ok(())
+}
+
+/// Detach or terminate the old instance, unless it already left the group.
+async fn remove(cluster: &BitteCluster, replacement: &Replacement) -> Result<()> {
+    let asg = find_asg(cluster, &replacement.asg, &replacement.region).await?;
+    let member = asg
+        .instances
+        .iter()
+        .find(|instance| instance.id == replacement.instance);
+    let leaving = member
+        .map(|instance| {
+            instance.lifecycle.starts_with("Terminat") || instance.lifecycle.starts_with("Detach")
+        })
+        .unwrap_or(true);
+    if leaving {
+        eprintln!(
+            "==> {} already left {}",
+            replacement.instance, replacement.asg
+        );
+        return Ok(());
+    }
+
+    if replacement.detach {
+        asg.detach(&replacement.instance, &cluster.policy).await?;
+        eprintln!("==> detached {} from {}", replacement.instance, asg.name);
+    } else {
+        asg.terminate(&replacement.instance, &cluster.policy)
+            .await?;
+        eprintln!("==> terminating {} in {}", replacement.instance, asg.name);
+    }
+    Ok(())
+}
+
+/// Poll the group for a new instance and Nomad for a client at its address
+/// that takes work, or fail after `wait`.
+async fn wait_for_replacement(
+    cluster: &BitteCluster,
+    replacement: &Replacement,
+    wait: Duration,
+) -> Result<(String, IpAddr, NomadClient)> {
+    let api = cluster
+        .nomad_api_client
+        .clone()
+        .ok_or_else(|| anyhow!("a Nomad token is required to see the replacement register"))?;
+    let ec2 = BitteNode::ec2_client(&replacement.region, &cluster.policy).await?;
+    let mut addresses: HashMap<String, IpAddr> = HashMap::new();
+    let mut last = String::new();
+    let started = Instant::now();
+
+    loop {
+        let status = match poll(cluster, replacement, &api, &ec2, &mut addresses).await {
+            Ok(Progress::Ready(instance, ip, client)) => return Ok((instance, ip, client)),
+            Ok(Progress::Waiting(status)) => status,
+            // the APIs may hiccup while instances come and go
+            Err(error) => format!("{:#}", error),
+        };
+        if status != last {
+            eprintln!("==> {}", status);
+            last = status;
+        }
+
+        if started.elapsed() > wait {
+            return Err(anyhow!(
+                "no replacement was ready within {}; run `bitte replace {}` again to keep waiting",
+                humantime::format_duration(wait),
+                replacement.instance
+            ));
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+async fn poll(
+    cluster: &BitteCluster,
+    replacement: &Replacement,
+    api: &Arc<reqwest::Client>,
+    ec2: &aws_sdk_ec2::Client,
+    addresses: &mut HashMap<String, IpAddr>,
+) -> Result<Progress> {
+    let asg = find_asg(cluster, &replacement.asg, &replacement.region).await?;
+    let new: Vec<_> = asg
+        .instances
+        .iter()
+        .filter(|instance| !replacement.known.contains(&instance.id))
+        .collect();
+    if new.is_empty() {
+        return Ok(Progress::Waiting(format!(
+            "waiting for {} to start a replacement",
+            asg.name
+        )));
+    }
+
+    let clients = NomadClient::find_nomad_nodes(
+        Arc::clone(api),
+        cluster.endpoints.nomad.addr.clone(),
+        cluster.policy.clone(),
+    )
+    .await?;
+
+    let mut waiting = Vec::new();
+    for instance in new {
+        if !instance.in_service() {
+            waiting.push(format!("{} is {}", instance.id, instance.lifecycle));
+            continue;
+        }
+
+        if !addresses.contains_key(&instance.id) {
+            let output = ec2
+                .describe_instances()
+                .instance_ids(&instance.id)
+                .send()
+                .await
+                .with_context(|| format!("failed to describe {}", instance.id))?;
+            let ip = output
+                .reservations
+                .unwrap_or_default()
+                .into_iter()
+                .flat_map(|reservation| reservation.instances.unwrap_or_default())
+                .find(|found| found.instance_id.as_deref() == Some(instance.id.as_str()))
+                .and_then(|found| found.private_ip_address)
+                .and_then(|ip| ip.parse().ok());
+            match ip {
+                Some(ip) => {
+                    addresses.insert(instance.id.clone(), ip);
+                }
+                None => {
+                    waiting.push(format!("{} has no private IP yet", instance.id));
+                    continue;
+                }
+            }
+        }
+        let ip = addresses[&instance.id];
+
+        // the address may have belonged to a client that's down since
+        let client = clients
+            .iter()
+            .filter(|client| client.address == Some(ip))
+            .min_by_key(|client| (!client.takes_work(), client.is_down()));
+        match client {
+            Some(client) if client.takes_work() => {
+                return Ok(Progress::Ready(instance.id.clone(), ip, client.clone()));
+            }
+            Some(client) => waiting.push(format!(
+                "Nomad node {} of {} is {}, {}",
+                client.name.as_deref().unwrap_or_default(),
+                instance.id,
+                client.status.as_deref().unwrap_or("unknown"),
+                client.eligibility.as_deref().unwrap_or("unknown"),
+            )),
+            None => waiting.push(format!(
+                "{} ({}) hasn't registered with Nomad",
+                instance.id, ip
+            )),
+        }
+    }
+
+    Ok(Progress::Waiting(format!(
+        "waiting: {}",
+        waiting.join("; ")
+    )))
+}
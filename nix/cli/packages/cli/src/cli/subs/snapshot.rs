@@ -0,0 +1,260 @@
+use super::ssh::ssh_opts;
+use crate::bitte::endpoint::Service;
+use crate::bitte::node::BitteNode;
+use crate::bitte::snapshot::{self, Snapshot, TIME_FORMAT};
+use crate::bitte::{BitteCluster, ClusterHandle};
+use crate::cli;
+use anyhow::{anyhow, Context, Result};
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use clap::ArgMatches;
+use log::{info, warn};
+use prettytable::{cell, format, row, Table};
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Where `modules/hashi-snapshots.nix` keeps the scheduled snapshots on the
+/// servers, by default.
+fn server_dir(service: Service, job: &str) -> String {
+    format!("/var/lib/private/{}/snapshots/{}", service, job)
+}
+
+fn services(sub: &ArgMatches) -> Vec<Service> {
+    let services: Vec<Service> = sub
+        .get_many("service")
+        .unwrap_or_default()
+        .copied()
+        .collect();
+    if services.is_empty() {
+        Service::ALL.to_vec()
+    } else {
+        services
+    }
+}
+
+fn dir(sub: &ArgMatches) -> PathBuf {
+    sub.get_one::<PathBuf>("dir")
+        .cloned()
+        .unwrap_or_else(snapshot::default_dir)
+}
+
+pub async fn take(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let cluster = cluster.await??;
+    take_all(&cluster, &services(sub), &dir(sub)).await?;
+    Ok(())
+}
+
+/// Snapshot each of `services` in turn, stopping at the first failure.
+pub async fn take_all(
+    cluster: &BitteCluster,
+    services: &[Service],
+    dir: &Path,
+) -> Result<Vec<Snapshot>> {
+    let mut result = Vec::with_capacity(services.len());
+    for service in services {
+        let endpoint = cluster.endpoints.get(*service);
+        let snapshot = Snapshot::take(endpoint, &cluster.policy, &cluster.name, dir)
+            .await
+            .with_context(|| format!("failed to take a snapshot of {}", service))?;
+        println!(
+            "{}: saved {} bytes to {}",
+            service,
+            snapshot.size,
+            snapshot.path.display()
+        );
+        result.push(snapshot);
+    }
+    Ok(result)
+}
+
+pub async fn list(sub: &ArgMatches) -> Result<()> {
+    let name = sub.get_one::<String>("name").unwrap();
+    let dir = dir(sub);
+    let services = services(sub);
+
+    let snapshots: Vec<Snapshot> = Snapshot::list(&dir, name)?
+        .into_iter()
+        .filter(|snapshot| services.contains(&snapshot.service))
+        .collect();
+    if snapshots.is_empty() {
+        println!("No snapshots of {} in {}", name, dir.display());
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    table.add_row(row![b => "SERVICE", "TAKEN", "SIZE", "FILE"]);
+    for snapshot in snapshots.iter() {
+        table.add_row(row![
+            snapshot.service,
+            snapshot.taken.format("%Y-%m-%d %H:%M:%S UTC"),
+            r->snapshot.size,
+            snapshot.path.display(),
+        ]);
+    }
+    table.printstd();
+
+    Ok(())
+}
+
+/// Copy the newest scheduled snapshot of each service from the core nodes.
+pub async fn fetch(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let job = sub.get_one::<String>("job").unwrap();
+    let dir = dir(sub);
+    let cluster = cluster.await??;
+
+    let mut nodes: Vec<&BitteNode> = cluster.nodes.iter().filter(|node| node.is_core()).collect();
+    nodes.sort_by(|a, b| a.name.cmp(&b.name));
+    if nodes.is_empty() {
+        return Err(anyhow!("no core nodes found to fetch snapshots from"));
+    }
+
+    std::fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+
+    for service in services(sub) {
+        let remote_dir = server_dir(service, job);
+
+        // servers may skip snapshots depending on their role, so look on all
+        let mut newest: Option<(&BitteNode, String, NaiveDateTime)> = None;
+        for node in nodes.iter() {
+            for path in remote_snapshots(&cluster.name, node, &remote_dir)? {
+                let taken = match remote_time(&path) {
+                    Some(taken) => taken,
+                    None => continue,
+                };
+                if newest.as_ref().map(|(_, _, t)| taken > *t).unwrap_or(true) {
+                    newest = Some((node, path, taken));
+                }
+            }
+        }
+
+        let (node, path, taken) = match newest {
+            Some(newest) => newest,
+            None => {
+                warn!("no {} snapshots found in {}", service, remote_dir);
+                continue;
+            }
+        };
+
+        let target = dir.join(Snapshot::file_name(
+            &cluster.name,
+            service,
+            &Utc.from_utc_datetime(&taken),
+        ));
+        let mut cmd = Command::new("scp");
+        cmd.args(["-P", "22"])
+            .args(ssh_opts(&cluster.name))
+            .arg(format!("root@{}:{}", node.pub_ip, path))
+            .arg(&target);
+        info!("cmd: {:?}", cmd);
+
+        let status = cmd
+            .spawn()
+            .with_context(|| "scp command failed")?
+            .wait()
+            .with_context(|| "scp command didn't finish?")?;
+        if !status.success() {
+            return Err(anyhow!("scp exited with {}", status));
+        }
+
+        println!(
+            "{}: fetched {} from {} to {}",
+            service,
+            path,
+            node.name,
+            target.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// The snapshot files in `dir` on `node`, if there are any.
+fn remote_snapshots(cluster: &str, node: &BitteNode, dir: &str) -> Result<Vec<String>> {
+    let mut cmd = Command::new("ssh");
+    cmd.args(["-x", "-p", "22"])
+        .args(ssh_opts(cluster))
+        .arg(format!("root@{}", node.pub_ip))
+        .arg(format!("ls -1 {}/*.snap 2>/dev/null || true", dir));
+    info!("cmd: {:?}", cmd);
+
+    let output = cmd.output().with_context(|| "ssh command failed")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "listing {} on {} failed with {}: {}",
+            dir,
+            node.name,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.trim().to_owned())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+/// The time in a name like `consul-core-1-2022-08-01_120000Z-hourly.snap`.
+fn remote_time(path: &str) -> Option<NaiveDateTime> {
+    let pattern = Regex::new(r"\d{4}-\d{2}-\d{2}_\d{6}Z").unwrap();
+    let found = pattern.find_iter(path).last()?;
+    NaiveDateTime::parse_from_str(found.as_str(), TIME_FORMAT).ok()
+}
+
+pub async fn restore(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let path = sub.get_one::<PathBuf>("file").unwrap();
+    let force = sub.is_present("force");
+    let cluster = cluster.await??;
+
+    let snapshot = match (
+        Snapshot::parse(&cluster.name, path),
+        sub.get_one::<Service>("service"),
+    ) {
+        (Some(snapshot), None) => snapshot,
+        (Some(snapshot), Some(service)) if snapshot.service == *service => snapshot,
+        (Some(snapshot), Some(service)) => {
+            return Err(anyhow!(
+                "{} is a snapshot of {}, not {}",
+                path.display(),
+                snapshot.service,
+                service
+            ))
+        }
+        // a file from elsewhere, e.g. fetched by hand or of another cluster
+        (None, Some(service)) => Snapshot {
+            cluster: cluster.name.clone(),
+            service: *service,
+            taken: Utc::now(),
+            path: path.clone(),
+            size: path.metadata().map(|m| m.len()).unwrap_or_default(),
+        },
+        (None, None) => {
+            return Err(anyhow!(
+                "{} isn't named like a snapshot of {}, pass --service to restore it anyway",
+                path.display(),
+                cluster.name
+            ))
+        }
+    };
+    if !snapshot.path.is_file() {
+        return Err(anyhow!("{} does not exist", snapshot.path.display()));
+    }
+
+    let question = format!(
+        "Replace the state of {} in cluster {} with {}?",
+        snapshot.service,
+        cluster.name,
+        snapshot.path.display()
+    );
+    if !sub.is_present("yes") && !cli::confirm(&question)? {
+        return Err(anyhow!("aborted"));
+    }
+
+    let endpoint = cluster.endpoints.get(snapshot.service);
+    snapshot.restore(endpoint, &cluster.policy, force).await?;
+    println!("{}: restored {}", snapshot.service, snapshot.path.display());
+
+    Ok(())
+}
@@ -1,13 +1,18 @@
+use crate::bitte::node::BitteNode;
 use crate::bitte::{BitteFind, ClusterHandle};
+use crate::flake::{self, DeployNode, DeployNodes};
 use anyhow::{anyhow, Context, Result};
 use clap::ArgMatches;
 use log::info;
-use std::net::IpAddr;
+use std::env;
 use std::path::Path;
 use std::process::Command;
 use std::time::Duration;
 use tokio::task::JoinHandle;
 
+/// Where Nomad keeps allocation directories on the clients.
+pub(crate) const ALLOC_DIR: &str = "/var/lib/nomad/alloc";
+
 pub async fn ssh(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
     let mut args: Vec<String> = sub.get_many("args").unwrap_or_default().cloned().collect();
     let job: Vec<String> = sub.get_many("job").unwrap_or_default().cloned().collect();
@@ -19,7 +24,7 @@ pub async fn ssh(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
         .unwrap_or(&"default".to_string())
         .to_owned();
 
-    let ip: IpAddr;
+    let target: SshTarget;
 
     let cluster = cluster.await??;
 
@@ -30,10 +35,12 @@ pub async fn ssh(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
             cluster.nodes
         };
 
+        let deploy_nodes = flake::deploy_nodes(sub, &nixos_names(&nodes));
         let mut iter = nodes.iter().peekable();
 
         while let Some(node) = iter.next() {
-            init_ssh(node.pub_ip, args.clone(), cluster.name.clone()).await?;
+            let target = SshTarget::new(&cluster.name, node, &deploy_nodes);
+            init_ssh(target, args.clone()).await?;
             if sub.is_present("delay") && iter.peek().is_some() {
                 tokio::time::sleep(delay).await;
             }
@@ -47,12 +54,13 @@ pub async fn ssh(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
             cluster.nodes
         };
 
+        let deploy_nodes = flake::deploy_nodes(sub, &nixos_names(&nodes));
         let mut handles: Vec<JoinHandle<Result<()>>> = Vec::with_capacity(nodes.len());
 
-        for node in nodes.into_iter() {
+        for node in nodes.iter() {
             let args = args.clone();
-            let name = cluster.name.clone();
-            let handle = tokio::spawn(async move { init_ssh(node.pub_ip, args, name).await });
+            let target = SshTarget::new(&cluster.name, node, &deploy_nodes);
+            let handle = tokio::spawn(async move { init_ssh(target, args).await });
             handles.push(handle);
         }
 
@@ -64,13 +72,55 @@ pub async fn ssh(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
     } else if sub.is_present("job") {
         let (name, group, index) = (&job[0], &job[1], &job[2]);
 
+        let statuses: Vec<String> = sub
+            .get_many("status")
+            .unwrap_or_default()
+            .cloned()
+            .collect();
+        let statuses: Vec<&str> = if statuses.is_empty() {
+            vec!["running"]
+        } else {
+            statuses.iter().map(AsRef::as_ref).collect()
+        };
+
         let nodes = cluster.nodes;
-        let (node, alloc) = nodes.find_with_job(name, group, index, &namespace.clone())?;
-        ip = node.pub_ip;
+        let mut found = nodes.find_with_jobs(name, group, index, &namespace, &statuses)?;
+        let names: Vec<String> = found.iter().map(|(node, _)| node.nixos.clone()).collect();
+        let deploy_nodes = flake::deploy_nodes(sub, &names);
+
+        if found.len() > 1 {
+            if args.is_empty() {
+                return Err(anyhow!(
+                    "{}, {}, {} matches {} allocations; <ARGS> are required to run on all of them",
+                    name,
+                    group,
+                    index,
+                    found.len()
+                ));
+            }
+
+            for (node, alloc) in found.into_iter() {
+                eprintln!(
+                    "==> {}, {}, {} ({}) on {}",
+                    alloc.job_id,
+                    alloc.task_group,
+                    alloc.index.get().unwrap_or_default(),
+                    alloc.status,
+                    node.pub_ip
+                );
+                let target = SshTarget::new(&cluster.name, &node, &deploy_nodes);
+                init_ssh(target, args.clone()).await?;
+            }
+
+            return Ok(());
+        }
+
+        let (node, alloc) = found.remove(0);
+        target = SshTarget::new(&cluster.name, &node, &deploy_nodes);
         if args.is_empty() {
             args.extend(vec![
                 "-t".into(),
-                format!("cd /var/lib/nomad/alloc/{} && exec $SHELL", alloc.id),
+                format!("cd {}/{} && exec $SHELL", ALLOC_DIR, alloc.id),
             ]);
         };
     } else {
@@ -84,26 +134,91 @@ pub async fn ssh(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
         args = args.drain(1..).collect();
 
         let nodes = cluster.nodes;
-        let node = nodes.find_needle(&needle)?;
-
-        ip = node.pub_ip;
+        target = match nodes.find_needle(&needle) {
+            Ok(node) => {
+                let deploy_nodes = flake::deploy_nodes(sub, std::slice::from_ref(&node.nixos));
+                SshTarget::new(&cluster.name, &node, &deploy_nodes)
+            }
+            // nodes outside of EC2 are only known to the flake, by their name
+            Err(error) => {
+                let deploy_nodes = flake::deploy_nodes(sub, std::slice::from_ref(&needle));
+                match deploy_nodes.get(&needle) {
+                    Some(node) => match &node.hostname {
+                        Some(host) => SshTarget::with_host(&cluster.name, host.clone(), Some(node)),
+                        None => return Err(error),
+                    },
+                    None => return Err(error),
+                }
+            }
+        };
     };
 
-    init_ssh(ip, args, cluster.name).await
+    init_ssh(target, args).await
 }
 
-async fn init_ssh(ip: IpAddr, args: Vec<String>, cluster: String) -> Result<()> {
-    let user_host = &*format!("root@{}", ip);
-    let mut flags = vec!["-x", "-p", "22"];
+/// Where and as whom to log in to a node.
+#[derive(Debug, Clone)]
+pub(crate) struct SshTarget {
+    host: String,
+    user: String,
+    opts: Vec<String>,
+}
 
-    let ssh_key_path = format!("secrets/ssh-{}", cluster);
+impl SshTarget {
+    /// Log in to `node` the way deploy-rs does if the flake's `deploy.nodes`
+    /// knows it, otherwise as root on its public IP with the cluster's key.
+    pub(crate) fn new(cluster: &str, node: &BitteNode, deploy_nodes: &DeployNodes) -> Self {
+        let deploy_node = deploy_nodes.get(&node.nixos);
+        let host = deploy_node
+            .and_then(|deploy_node| deploy_node.hostname.clone())
+            .unwrap_or_else(|| node.pub_ip.to_string());
+
+        Self::with_host(cluster, host, deploy_node)
+    }
+
+    fn with_host(cluster: &str, host: String, deploy_node: Option<&DeployNode>) -> Self {
+        let user = deploy_node
+            .and_then(|deploy_node| deploy_node.ssh_user.clone())
+            .unwrap_or_else(|| "root".to_owned());
+        let opts = match deploy_node {
+            Some(deploy_node) if !deploy_node.ssh_opts.is_empty() => deploy_node.ssh_opts.clone(),
+            _ => ssh_opts(cluster),
+        };
+
+        Self { host, user, opts }
+    }
+}
+
+fn nixos_names(nodes: &[BitteNode]) -> Vec<String> {
+    nodes.iter().map(|node| node.nixos.clone()).collect()
+}
+
+/// Identity and host key options shared by `ssh` and `scp`.
+pub(crate) fn ssh_opts(cluster: &str) -> Vec<String> {
+    let mut opts: Vec<String> = Vec::new();
+
+    let ssh_key_path = match env::var("BITTE_SSH_KEY") {
+        Ok(path) if !path.is_empty() => path,
+        _ => format!("secrets/ssh-{}", cluster),
+    };
     let ssh_key = Path::new(&ssh_key_path);
     if ssh_key.is_file() {
-        flags.push("-i");
-        flags.push(&*ssh_key_path);
+        opts.push("-i".into());
+        opts.push(ssh_key_path);
     }
 
-    flags.append(&mut vec!["-o", "StrictHostKeyChecking=accept-new"]);
+    opts.append(&mut vec![
+        "-o".into(),
+        "StrictHostKeyChecking=accept-new".into(),
+    ]);
+    opts
+}
+
+async fn init_ssh(target: SshTarget, args: Vec<String>) -> Result<()> {
+    let user_host = &*format!("{}@{}", target.user, target.host);
+    let mut flags = vec!["-x", "-p", "22"];
+
+    flags.append(&mut target.opts.iter().map(AsRef::as_ref).collect());
 
     flags.push(user_host);
 
@@ -0,0 +1,35 @@
+use crate::bitte::tunnel::{Tunnel, TunnelTarget};
+use crate::bitte::ClusterHandle;
+use anyhow::{anyhow, Result};
+use clap::ArgMatches;
+
+pub async fn tunnel(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let targets: Vec<TunnelTarget> = sub
+        .get_many::<TunnelTarget>("targets")
+        .unwrap_or_default()
+        .copied()
+        .collect();
+    let port = sub.get_one::<u16>("port").copied();
+
+    if port.is_some() && targets.len() > 1 {
+        return Err(anyhow!("--port can only be used with a single service"));
+    }
+
+    let cluster = cluster.await??;
+
+    let mut tunnels = Vec::with_capacity(targets.len());
+    for target in targets {
+        tunnels.push(Tunnel::open(&cluster.name, target, &cluster.nodes, port).await?);
+    }
+
+    for tunnel in tunnels.iter() {
+        println!("{} ({}): {}", tunnel.target, tunnel.node, tunnel.url());
+    }
+    println!("Press Ctrl-C to close.");
+
+    for tunnel in tunnels {
+        tunnel.wait().await?;
+    }
+
+    Ok(())
+}
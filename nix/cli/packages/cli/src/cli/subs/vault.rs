@@ -0,0 +1,291 @@
+use crate::bitte::age;
+use crate::bitte::node::BitteNode;
+use crate::bitte::tunnel::{Tunnel, TunnelTarget};
+use crate::bitte::vault::{Health, RaftPeer, Role, SealStatus, VaultServer};
+use crate::bitte::{BitteCluster, BitteFind, ClusterHandle};
+use crate::cli;
+use anyhow::{anyhow, Result};
+use clap::ArgMatches;
+use log::warn;
+use prettytable::{cell, format, row, Table};
+use serde::Serialize;
+use serde_json::Value;
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+/// One core node's Vault, as far as it could be asked.
+#[derive(Debug, Serialize)]
+struct ServerStatus {
+    node: String,
+    ip: IpAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seal: Option<SealStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    health: Option<Health>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<Role>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raft: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// The core nodes, or those of them matching the needles, by name.
+pub(crate) fn core_nodes(cluster: &BitteCluster, needles: Vec<&str>) -> Result<Vec<BitteNode>> {
+    let mut nodes: Vec<BitteNode> = cluster
+        .nodes
+        .iter()
+        .filter(|node| node.is_core())
+        .cloned()
+        .collect();
+    if !needles.is_empty() {
+        nodes = nodes.find_needles(needles);
+    }
+    nodes.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if nodes.is_empty() {
+        return Err(anyhow!("no core nodes found"));
+    }
+    Ok(nodes)
+}
+
+/// Reach the Vault server on `node` itself through a tunnel; its own API is
+/// only served to the node and its peers.
+async fn connect(cluster: &BitteCluster, node: &BitteNode) -> Result<(Tunnel, VaultServer)> {
+    let tunnel = Tunnel::open_on(&cluster.name, TunnelTarget::Vault, node, None).await?;
+    let server = VaultServer::new(&tunnel.endpoint(&cluster.endpoints.vault), &cluster.policy)?;
+    Ok((tunnel, server))
+}
+
+pub async fn status(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let json = sub.is_present("json");
+    let cluster = cluster.await??;
+
+    let mut result = Vec::new();
+    let mut peers: Option<Vec<RaftPeer>> = None;
+    for node in core_nodes(&cluster, Vec::new())? {
+        let mut status = ServerStatus {
+            node: node.name.clone(),
+            ip: node.priv_ip,
+            seal: None,
+            health: None,
+            role: None,
+            raft: None,
+            error: None,
+        };
+
+        let server = match connect(&cluster, &node).await {
+            Ok((tunnel, server)) => Some((tunnel, server)),
+            Err(error) => {
+                status.error = Some(format!("{:#}", error));
+                None
+            }
+        };
+        if let Some((_tunnel, server)) = server {
+            match server.seal_status().await {
+                Ok(seal) => status.seal = Some(seal),
+                Err(error) => status.error = Some(format!("{:#}", error)),
+            }
+            match server.health().await {
+                Ok(health) => {
+                    status.role = Some(health.role());
+                    status.health = Some(health);
+                }
+                Err(error) => status.error = Some(format!("{:#}", error)),
+            }
+
+            // every unsealed server knows the whole configuration
+            let unsealed = status.seal.as_ref().map(|s| !s.sealed).unwrap_or(false);
+            if peers.is_none() && unsealed {
+                match server.raft_peers().await {
+                    Ok(found) => peers = Some(found),
+                    Err(error) => warn!("raft peers: {:#}", error),
+                }
+            }
+        }
+
+        result.push(status);
+    }
+
+    if let Some(peers) = &peers {
+        for status in result.iter_mut() {
+            let ip = status.ip.to_string();
+            let peer = peers.iter().find(|peer| peer.host() == ip);
+            status.raft = Some(
+                match peer {
+                    Some(peer) if peer.leader => "leader",
+                    Some(peer) if peer.voter => "follower",
+                    Some(_) => "non-voter",
+                    None => "not a peer",
+                }
+                .to_owned(),
+            );
+        }
+    }
+
+    if json {
+        let stdout = io::stdout();
+        serde_json::to_writer_pretty(stdout.lock(), &result)?;
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    table.add_row(
+        row![b => "NODE", "PRIVATE IP", "SEAL", "SEALED", "UNSEAL", "ROLE", "RAFT", "VERSION"],
+    );
+    for status in result.iter() {
+        let seal = status.seal.as_ref();
+        table.add_row(row![
+            status.node,
+            status.ip,
+            seal.map(|s| s.seal_type.as_str()).unwrap_or("?"),
+            seal.map(|s| s.sealed.to_string())
+                .unwrap_or_else(|| "?".to_owned()),
+            seal.filter(|s| s.sealed)
+                .map(|s| format!("{}/{}", s.progress, s.t))
+                .unwrap_or_else(|| "-".to_owned()),
+            status
+                .role
+                .map(|role| role.to_string())
+                .unwrap_or_else(|| "unreachable".to_owned()),
+            status.raft.as_deref().unwrap_or("?"),
+            seal.map(|s| s.version.as_str()).unwrap_or("?"),
+        ]);
+    }
+    table.printstd();
+
+    for status in result.iter() {
+        if let Some(error) = &status.error {
+            eprintln!("{}: {}", status.node, error);
+        }
+    }
+
+    Ok(())
+}
+
+/// Unseal the sealed servers one by one, with key shares from age-encrypted
+/// files or typed in. Shares typed for one server are reused for the next.
+pub async fn unseal(sub: &ArgMatches, cluster: ClusterHandle) -> Result<()> {
+    let needles: Vec<&str> = sub
+        .get_many::<String>("nodes")
+        .unwrap_or_default()
+        .map(String::as_str)
+        .collect();
+    let identity = sub.get_one::<PathBuf>("identity").unwrap();
+    let files: Vec<&PathBuf> = sub
+        .get_many::<PathBuf>("key-file")
+        .unwrap_or_default()
+        .collect();
+
+    let mut keys = Vec::new();
+    for file in files.iter() {
+        keys.extend(key_shares(&age::decrypt(identity, file)?));
+    }
+    if !files.is_empty() && keys.is_empty() {
+        return Err(anyhow!("the key files don't contain any key shares"));
+    }
+
+    let cluster = cluster.await??;
+    let nodes = core_nodes(&cluster, needles)?;
+    let ask = files.is_empty();
+    if unseal_nodes(&cluster, &nodes, &mut keys, ask).await? == 0 {
+        println!("No sealed Vault servers found.");
+    }
+
+    Ok(())
+}
+
+/// Unseal each sealed server of `nodes`, returning how many were. With
+/// `ask`, shares beyond `keys` are asked for and added to it.
+pub(crate) async fn unseal_nodes(
+    cluster: &BitteCluster,
+    nodes: &[BitteNode],
+    keys: &mut Vec<String>,
+    ask: bool,
+) -> Result<usize> {
+    let mut unsealed = 0;
+    for node in nodes {
+        let (_tunnel, server) = connect(cluster, node).await?;
+        let mut seal = server.seal_status().await?;
+
+        if !seal.initialized {
+            warn!("{}: vault isn't initialized yet, skipping it", node.name);
+            continue;
+        }
+        if !seal.sealed {
+            println!("{}: already unsealed", node.name);
+            continue;
+        }
+        if seal.recovery_seal || seal.seal_type != "shamir" {
+            warn!(
+                "{}: vault uses the {} seal, which unseals it by itself; check its access to it",
+                node.name, seal.seal_type
+            );
+            continue;
+        }
+
+        eprintln!("==> unsealing {} ({})", node.name, node.priv_ip);
+        let mut used = 0;
+        while seal.sealed {
+            let key = match keys.get(used) {
+                Some(key) => key.clone(),
+                None if ask => {
+                    let key = cli::read_secret(&format!(
+                        "Key share ({}/{}) for {}: ",
+                        seal.progress + 1,
+                        seal.t,
+                        node.name
+                    ))?;
+                    if key.is_empty() {
+                        return Err(anyhow!("aborted"));
+                    }
+                    keys.push(key.clone());
+                    key
+                }
+                None => {
+                    return Err(anyhow!(
+                        "{} is still sealed after all {} key shares, {} of {} needed",
+                        node.name,
+                        keys.len(),
+                        seal.progress,
+                        seal.t
+                    ))
+                }
+            };
+            used += 1;
+
+            seal = server.unseal(&key).await?;
+            eprintln!("    {}/{} key shares", seal.progress, seal.t);
+        }
+
+        println!("{}: unsealed", node.name);
+        unsealed += 1;
+    }
+
+    Ok(unsealed)
+}
+
+/// Key shares from a decrypted file: the JSON output of `vault operator
+/// init` or of its API, or one share per line.
+fn key_shares(content: &str) -> Vec<String> {
+    if let Ok(init) = serde_json::from_str::<Value>(content) {
+        for field in ["unseal_keys_b64", "keys_base64"] {
+            if let Some(keys) = init.get(field).and_then(Value::as_array) {
+                return keys
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_owned)
+                    .collect();
+            }
+        }
+    }
+
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
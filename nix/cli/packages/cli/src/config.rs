@@ -0,0 +1,245 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use uuid::Uuid;
+
+/// The user's settings from `~/.config/bitte/config.toml`.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct Config {
+    /// The context used unless another one is selected with `--context`
+    pub current_context: Option<String>,
+    /// Named clusters to switch between, in place of environment variables
+    #[serde(default)]
+    pub contexts: BTreeMap<String, ClusterContext>,
+    /// Settings that only apply to the cluster of the same name
+    #[serde(default)]
+    pub clusters: BTreeMap<String, ClusterConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct ClusterConfig {
+    /// Always reach the APIs through ssh tunnels to a core node
+    #[serde(default)]
+    pub tunnel: bool,
+    pub nomad: Option<EndpointConfig>,
+    pub consul: Option<EndpointConfig>,
+    pub vault: Option<EndpointConfig>,
+}
+
+/// Overrides for how one of a cluster's APIs is reached; anything unset
+/// falls back to the environment and then to `https://<service>.<domain>`.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct EndpointConfig {
+    pub addr: Option<String>,
+    pub cacert: Option<PathBuf>,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+}
+
+/// Everything needed to work with one cluster. Plain values come first, so
+/// the context can be written back out as TOML.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct ClusterContext {
+    pub cluster: String,
+    pub domain: Option<String>,
+    pub provider: Option<String>,
+    pub aws_region: Option<String>,
+    pub aws_asg_regions: Option<Vec<String>>,
+    pub ssh_key: Option<PathBuf>,
+    #[serde(default)]
+    pub tunnel: bool,
+    pub token: Option<TokenSource>,
+    pub nomad: Option<EndpointConfig>,
+    pub consul: Option<EndpointConfig>,
+    pub vault: Option<EndpointConfig>,
+}
+
+/// Where to get the Nomad token of a context from; the first one set is
+/// used.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct TokenSource {
+    /// an environment variable
+    pub env: Option<String>,
+    /// a file containing only the token
+    pub file: Option<PathBuf>,
+    /// a shell command printing the token, e.g. `vault read -field secret_id ...`
+    pub command: Option<String>,
+}
+
+impl Config {
+    /// `$BITTE_CONFIG`, or `config.toml` in `$XDG_CONFIG_HOME/bitte`, which
+    /// defaults to `~/.config/bitte`.
+    pub fn path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os("BITTE_CONFIG") {
+            return Some(PathBuf::from(path));
+        }
+
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => dirs::home_dir()?.join(".config"),
+        };
+        Some(dir.join("bitte").join("config.toml"))
+    }
+
+    /// Read the config file, which is optional.
+    pub fn load() -> Result<Self> {
+        let path = match Self::path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Self::default()),
+        };
+
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    /// The name of the context in use: the one selected with `--context`,
+    /// otherwise the current one.
+    pub fn active_name(&self) -> Option<String> {
+        env::var("BITTE_CONTEXT")
+            .ok()
+            .filter(|name| !name.is_empty())
+            .or_else(|| self.current_context.clone())
+    }
+
+    pub fn active(&self) -> Option<&ClusterContext> {
+        self.active_name().and_then(|name| self.contexts.get(&name))
+    }
+
+    pub fn context(&self, name: &str) -> Result<&ClusterContext> {
+        self.contexts.get(name).ok_or_else(|| {
+            anyhow!(
+                "there is no context named \"{}\", see `bitte context list`",
+                name
+            )
+        })
+    }
+
+    /// Settings for the named cluster, from `[clusters.<name>]` and, with
+    /// precedence, from the active context if it is for the same cluster.
+    pub fn cluster(&self, name: &str) -> ClusterConfig {
+        let mut config = self.clusters.get(name).cloned().unwrap_or_default();
+
+        if let Some(context) = self.active().filter(|context| context.cluster == name) {
+            config.tunnel |= context.tunnel;
+            config.nomad = context.nomad.clone().or(config.nomad);
+            config.consul = context.consul.clone().or(config.consul);
+            config.vault = context.vault.clone().or(config.vault);
+        }
+
+        config
+    }
+
+    /// Make `name` the current context, editing the file in place so that
+    /// comments and formatting survive.
+    pub fn set_current(&self, name: &str) -> Result<()> {
+        self.context(name)?;
+        let path = Self::path().context("failed to determine the config file path")?;
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+
+        let line = format!("current_context = {}", toml::Value::from(name));
+        let mut lines: Vec<String> = content.lines().map(String::from).collect();
+
+        // only keys before the first table header belong to the root table
+        let root = lines
+            .iter()
+            .position(|l| l.trim_start().starts_with('['))
+            .unwrap_or(lines.len());
+        match lines[..root]
+            .iter()
+            .position(|l| is_key(l, "current_context"))
+        {
+            Some(index) => lines[index] = line,
+            None => lines.insert(0, line),
+        }
+
+        std::fs::write(&path, lines.join("\n") + "\n")
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+fn is_key(line: &str, key: &str) -> bool {
+    match line.trim_start().strip_prefix(key) {
+        Some(rest) => rest.trim_start().starts_with('='),
+        None => false,
+    }
+}
+
+impl ClusterContext {
+    /// The environment variables that Bitte's global options are read from.
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        let mut vars = vec![("BITTE_CLUSTER", self.cluster.clone())];
+
+        if let Some(domain) = &self.domain {
+            vars.push(("BITTE_DOMAIN", domain.clone()));
+        }
+        if let Some(provider) = &self.provider {
+            vars.push(("BITTE_PROVIDER", provider.clone()));
+        }
+        if let Some(region) = &self.aws_region {
+            vars.push(("AWS_DEFAULT_REGION", region.clone()));
+        }
+        if let Some(regions) = &self.aws_asg_regions {
+            vars.push(("AWS_ASG_REGIONS", regions.join(":")));
+        }
+        if let Some(key) = &self.ssh_key {
+            vars.push(("BITTE_SSH_KEY", expand_home(key).display().to_string()));
+        }
+
+        vars
+    }
+}
+
+impl TokenSource {
+    pub fn read(&self) -> Result<Uuid> {
+        let (token, origin) = if let Some(var) = &self.env {
+            (
+                env::var(var).with_context(|| format!("{} is not set", var))?,
+                format!("${}", var),
+            )
+        } else if let Some(path) = &self.file {
+            let path = expand_home(path);
+            (
+                std::fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?,
+                path.display().to_string(),
+            )
+        } else if let Some(command) = &self.command {
+            let output = Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .output()
+                .with_context(|| format!("failed to run `{}`", command))?;
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "`{}` exited with {}: {}",
+                    command,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            (
+                String::from_utf8(output.stdout)
+                    .with_context(|| format!("`{}` printed invalid UTF-8", command))?,
+                format!("`{}`", command),
+            )
+        } else {
+            return Err(anyhow!("the token needs one of env, file or command"));
+        };
+
+        Uuid::parse_str(token.trim())
+            .with_context(|| format!("the token from {} is not a Nomad token", origin))
+    }
+}
+
+/// Paths in the config file may start with `~/`.
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
@@ -0,0 +1,373 @@
+use crate::cli;
+use anyhow::{anyhow, Context, Result};
+use clap::ArgMatches;
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The variables `Globals` needs that a flake can provide; if any of them is
+/// missing in a checkout with a `flake.nix`, the flake is asked.
+const REQUIRED: [&str; 4] = [
+    "BITTE_CLUSTER",
+    "BITTE_DOMAIN",
+    "AWS_DEFAULT_REGION",
+    "AWS_ASG_REGIONS",
+];
+
+/// Subcommands that don't talk to a cluster, and so never need the flake.
+const OFFLINE: [&str; 3] = ["completions", "context", "help"];
+
+/// Picks the cluster out of the flake's `clusters` output, as built by
+/// `lib/clusters.nix`, and keeps only what the CLI needs so that nothing
+/// else gets evaluated.
+const SUMMARY: &str = r#"given: clusters:
+  let
+    names = builtins.attrNames clusters;
+    name =
+      if given != null then given
+      else if builtins.length names == 1 then builtins.head names
+      else null;
+    summarize = c: let cfg = c._proto.config.cluster; in {
+      inherit (cfg) name domain region;
+      asgRegions = map (asg: asg.region) (builtins.attrValues cfg.awsAutoScalingGroups);
+    };
+  in
+    if name != null && builtins.hasAttr name clusters
+    then { inherit names; cluster = summarize clusters.${name}; }
+    else { inherit names; }"#;
+
+/// How deploy-rs reaches the named nodes; the profiles aren't touched, so no
+/// system gets built.
+const DEPLOY_NODES: &str = r#"names: nodes:
+  builtins.listToAttrs (map (name: {
+    inherit name;
+    value = let node = nodes.${name}; in {
+      hostname = node.hostname or null;
+      sshUser = node.sshUser or null;
+      sshOpts = node.sshOpts or [];
+    };
+  }) (builtins.filter (name: builtins.hasAttr name nodes) names))"#;
+
+/// What the flake knows about a cluster.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FlakeCluster {
+    pub name: String,
+    pub domain: String,
+    pub region: String,
+    #[serde(rename = "asgRegions")]
+    pub asg_regions: Vec<String>,
+}
+
+/// How deploy-rs reaches a node, from the flake's `deploy.nodes` as built by
+/// `lib/mk-deploy.nix`, which derives these from the node's `deployType`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DeployNode {
+    /// Set for `awsExt` and `prem` nodes, which don't have a public IP we
+    /// know of
+    pub hostname: Option<String>,
+    #[serde(rename = "sshUser")]
+    pub ssh_user: Option<String>,
+    #[serde(rename = "sshOpts", default)]
+    pub ssh_opts: Vec<String>,
+}
+
+/// Deploy settings by the name of the node's NixOS configuration.
+pub type DeployNodes = BTreeMap<String, DeployNode>;
+
+#[derive(Debug, Deserialize)]
+struct Summary {
+    names: Vec<String>,
+    cluster: Option<FlakeCluster>,
+}
+
+/// Fill in the environment from the cluster definition of the flake given
+/// with `--flake` or `BITTE_FLAKE`, or of the flake in the current directory
+/// if that is needed. Like with contexts, an explicitly given flake wins over
+/// variables that are already set, e.g. by another cluster's devshell.
+pub fn apply(args: &[String]) -> Result<()> {
+    let explicit = cli::arg_value(args, "--flake").or_else(|| env::var("BITTE_FLAKE").ok());
+
+    let flake = match explicit.clone().filter(|flake| !flake.is_empty()) {
+        Some(flake) => flake,
+        None if Path::new("flake.nix").is_file() && needed(args) => ".".to_owned(),
+        None => return Ok(()),
+    };
+    let name = cli::arg_value(args, "--cluster").or_else(|| env::var("BITTE_CLUSTER").ok());
+
+    let cluster = match (FlakeCluster::eval(&flake, name.as_deref()), &explicit) {
+        (Ok(cluster), _) => cluster,
+        (Err(error), Some(_)) => return Err(error),
+        (Err(error), None) => {
+            // logging isn't set up before the arguments are parsed
+            eprintln!(
+                "WARNING: failed to read the cluster from flake.nix: {:#}",
+                error
+            );
+            return Ok(());
+        }
+    };
+
+    for (var, value) in cluster.env() {
+        if explicit.is_some() || env::var_os(var).is_none() {
+            env::set_var(var, value);
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the command needs a cluster that isn't fully described by the
+/// environment yet.
+fn needed(args: &[String]) -> bool {
+    let offline = args
+        .iter()
+        .skip(1)
+        .find(|arg| !arg.starts_with('-'))
+        .map(|sub| OFFLINE.contains(&sub.as_str()))
+        .unwrap_or(true);
+
+    !offline && REQUIRED.iter().any(|var| env::var_os(var).is_none())
+}
+
+impl FlakeCluster {
+    /// Evaluate the cluster named `name`, or the only one there is, from
+    /// `flake`. Evaluation takes a while, so the result is cached for as long
+    /// as a local flake's `flake.lock` doesn't change.
+    pub fn eval(flake: &str, name: Option<&str>) -> Result<Self> {
+        cached(
+            flake,
+            &format!("cluster\0{}", name.unwrap_or_default()),
+            || {
+                let given = name.map(nix_string).unwrap_or_else(|| "null".to_owned());
+                let summary: Summary = nix_eval(
+                    &format!("{}#clusters", flake),
+                    &format!("({}) {}", SUMMARY, given),
+                )?;
+
+                match (summary.cluster, name) {
+                    (Some(cluster), _) => Ok(cluster),
+                    (None, Some(name)) => Err(anyhow!(
+                        "{} has no cluster named \"{}\", only: {}",
+                        flake,
+                        name,
+                        summary.names.join(", ")
+                    )),
+                    (None, None) => Err(anyhow!(
+                        "{} defines several clusters, pick one with --cluster: {}",
+                        flake,
+                        summary.names.join(", ")
+                    )),
+                }
+            },
+        )
+    }
+
+    /// The environment variables that Bitte's global options are read from.
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        let mut asg_regions: Vec<&str> = Vec::new();
+        for region in self.asg_regions.iter() {
+            if !asg_regions.contains(&region.as_str()) {
+                asg_regions.push(region);
+            }
+        }
+        // a cluster without client groups still needs a region to look in
+        if asg_regions.is_empty() {
+            asg_regions.push(&self.region);
+        }
+
+        vec![
+            ("BITTE_CLUSTER", self.name.clone()),
+            ("BITTE_DOMAIN", self.domain.clone()),
+            ("BITTE_PROVIDER", "AWS".to_owned()),
+            ("AWS_DEFAULT_REGION", self.region.clone()),
+            ("AWS_ASG_REGIONS", asg_regions.join(":")),
+        ]
+    }
+}
+
+impl DeployNode {
+    /// Evaluate the deploy settings of those of `names` the flake defines.
+    pub fn eval(flake: &str, names: &[String]) -> Result<DeployNodes> {
+        let mut names = names.to_vec();
+        names.sort();
+        names.dedup();
+
+        let mut nodes: DeployNodes =
+            cached(flake, &format!("deploy\0{}", names.join("\0")), || {
+                let names = nix_string(&serde_json::to_string(&names)?);
+                nix_eval(
+                    &format!("{}#deploy.nodes", flake),
+                    &format!("({}) (builtins.fromJSON {})", DEPLOY_NODES, names),
+                )
+            })?;
+
+        for node in nodes.values_mut() {
+            node.resolve_paths(flake);
+        }
+        Ok(nodes)
+    }
+
+    /// The key in `sshOpts` is relative to the flake, which needn't be the
+    /// current directory.
+    fn resolve_paths(&mut self, flake: &str) {
+        let dir = match local_dir(flake) {
+            Some(dir) => dir,
+            None => return,
+        };
+
+        let mut iter = self.ssh_opts.iter_mut().peekable();
+        while let Some(opt) = iter.next() {
+            if opt != "-i" {
+                continue;
+            }
+            if let Some(key) = iter.next() {
+                if Path::new(key).is_relative() {
+                    *key = dir.join(&key).display().to_string();
+                }
+            }
+        }
+    }
+}
+
+/// The flake to read node details from: the one given with `--flake`, or
+/// the one in the current directory.
+pub fn selected(sub: &ArgMatches) -> Option<String> {
+    sub.get_one::<String>("flake")
+        .filter(|flake| !flake.is_empty())
+        .cloned()
+        .or_else(|| Path::new("flake.nix").is_file().then(|| ".".to_owned()))
+}
+
+/// The deploy settings of the named nodes, if there is a flake to read them
+/// from; without one, nodes are reached the way plain AWS nodes are.
+pub fn deploy_nodes(sub: &ArgMatches, names: &[String]) -> DeployNodes {
+    let flake = match selected(sub) {
+        Some(flake) => flake,
+        None => return DeployNodes::new(),
+    };
+
+    match DeployNode::eval(&flake, names) {
+        Ok(nodes) => {
+            for (name, node) in nodes.iter() {
+                info!(
+                    "{}: hostname {}",
+                    name,
+                    node.hostname.as_deref().unwrap_or("unset")
+                );
+            }
+            nodes
+        }
+        Err(error) => {
+            warn!(
+                "failed to read deploy.nodes from {}, using public IPs: {:#}",
+                flake, error
+            );
+            DeployNodes::new()
+        }
+    }
+}
+
+/// Reuse what was evaluated for the same lock file, or evaluate and store it.
+fn cached<T, F>(flake: &str, key: &str, eval: F) -> Result<T>
+where
+    T: Serialize + DeserializeOwned,
+    F: FnOnce() -> Result<T>,
+{
+    let cache = cache_path(flake, key);
+    if let Some(cached) = cache
+        .as_ref()
+        .and_then(|path| std::fs::read(path).ok())
+        .and_then(|content| serde_json::from_slice(&content).ok())
+    {
+        return Ok(cached);
+    }
+
+    let value = eval()?;
+
+    if let Some(path) = cache {
+        if let Err(error) = store(&path, &value) {
+            eprintln!(
+                "WARNING: failed to cache the evaluation of {}: {:#}",
+                flake, error
+            );
+        }
+    }
+
+    Ok(value)
+}
+
+fn store<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    std::fs::write(path, serde_json::to_vec(value)?)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+fn nix_eval<T: DeserializeOwned>(installable: &str, apply: &str) -> Result<T> {
+    eprintln!("Evaluating {}...", installable);
+    let output = Command::new("nix")
+        .args(["eval", "--json"])
+        .args(["--extra-experimental-features", "nix-command flakes"])
+        .arg(installable)
+        .arg("--apply")
+        .arg(apply)
+        .output()
+        .with_context(|| "failed to run nix eval")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "nix eval of {} exited with {}: {}",
+            installable,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    serde_json::from_slice(&output.stdout)
+        .with_context(|| format!("failed to decode the evaluation of {}", installable))
+}
+
+/// A Nix string literal; JSON's escapes are a subset of Nix's, except that
+/// Nix would interpolate `${`.
+fn nix_string(value: &str) -> String {
+    serde_json::to_string(value)
+        .unwrap_or_default()
+        .replace("${", "\\${")
+}
+
+/// The directory of a flake given as a local path.
+fn local_dir(flake: &str) -> Option<PathBuf> {
+    Path::new(flake.strip_prefix("path:").unwrap_or(flake))
+        .canonicalize()
+        .ok()
+}
+
+/// `~/.cache/bitte/flake-<hash>.json`, keyed by the flake's directory, the
+/// content of its lock file and what was asked for. Flakes without a local
+/// lock file aren't cached, since there is nothing cheap to tell whether they
+/// changed.
+fn cache_path(flake: &str, key: &str) -> Option<PathBuf> {
+    let dir = local_dir(flake)?;
+    let lock = std::fs::read(dir.join("flake.lock")).ok()?;
+
+    let mut content = dir.display().to_string().into_bytes();
+    content.extend_from_slice(b"\0");
+    content.extend_from_slice(&lock);
+    content.extend_from_slice(b"\0");
+    content.extend_from_slice(key.as_bytes());
+    let hash: String = openssl::sha::sha256(&content)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    Some(
+        dirs::cache_dir()?
+            .join("bitte")
+            .join(format!("flake-{}.json", hash)),
+    )
+}
@@ -1,6 +1,10 @@
 mod bitte;
 mod cli;
+mod config;
+mod flake;
 mod nomad;
+#[cfg(test)]
+mod testing;
 
 use anyhow::Result;
 use bitte::BitteCluster;
@@ -8,7 +12,6 @@ use clap::{App, ArgMatches, IntoApp};
 use clap_complete::Shell;
 use cli::opts::Bitte;
 use cli::subs;
-use uuid::Uuid;
 
 use deploy as deploy_rs;
 
@@ -16,6 +19,11 @@ use deploy as deploy_rs;
 async fn main() -> Result<()> {
     let _toml = include_str!("../Cargo.toml");
 
+    // clap reads the environment while the app is built
+    let args: Vec<String> = std::env::args().collect();
+    subs::context::apply(&args)?;
+    flake::apply(&args)?;
+
     let app: App = <Bitte as IntoApp>::into_app();
 
     let matches = app.clone().get_matches();
@@ -24,14 +32,68 @@ async fn main() -> Result<()> {
         if init_log {
             cli::init_log(matches.occurrences_of("verbose"))
         };
-        let token = sub.get_one::<Uuid>("nomad").copied();
+        let token = subs::context::token(sub);
         BitteCluster::init(sub.clone(), token)
     };
 
     match matches.subcommand() {
         Some(("deploy", sub)) => subs::deploy(sub, run(sub, false)).await?,
         Some(("info", sub)) => subs::info(sub, run(sub, true)).await?,
+        Some(("describe", sub)) => subs::describe(sub, run(sub, true)).await?,
+        Some(("capacity", sub)) => subs::capacity(sub, run(sub, true)).await?,
+        Some(("drain", sub)) => subs::drain(sub, run(sub, true)).await?,
+        Some(("replace", sub)) => subs::replace(sub, run(sub, true)).await?,
         Some(("ssh", sub)) => subs::ssh(sub, run(sub, true)).await?,
+        Some(("logs", sub)) => subs::logs(sub, run(sub, true)).await?,
+        Some(("cp", sub)) => subs::cp(sub, run(sub, true)).await?,
+        Some(("tunnel", sub)) => subs::tunnel(sub, run(sub, true)).await?,
+        Some(("bootstrap", sub)) => subs::bootstrap(sub, run(sub, true)).await?,
+        Some(("doctor", sub)) => subs::doctor(sub, run(sub, true)).await?,
+        Some(("orphans", sub)) => subs::orphans(sub, run(sub, true)).await?,
+        Some(("job", sub)) => match sub.subcommand() {
+            Some(("plan", sub)) => subs::job::plan(sub, run(sub, true)).await?,
+            Some(("run", sub)) => subs::job::run(sub, run(sub, true)).await?,
+            Some(("validate", sub)) => {
+                cli::init_log(matches.occurrences_of("verbose"));
+                subs::job::validate(sub).await?
+            }
+            _ => (),
+        },
+        Some(("node", sub)) => match sub.subcommand() {
+            Some(("reboot", sub)) => subs::node::reboot(sub, run(sub, true)).await?,
+            Some(("stop", sub)) => subs::node::stop(sub, run(sub, true)).await?,
+            Some(("terminate", sub)) => subs::node::terminate(sub, run(sub, true)).await?,
+            _ => (),
+        },
+        Some(("asg", sub)) => match sub.subcommand() {
+            Some(("list", sub)) => subs::asg::list(sub, run(sub, true)).await?,
+            Some(("scale", sub)) => subs::asg::scale(sub, run(sub, true)).await?,
+            _ => (),
+        },
+        Some(("snapshot", sub)) => match sub.subcommand() {
+            Some(("take", sub)) => subs::snapshot::take(sub, run(sub, true)).await?,
+            Some(("list", sub)) => {
+                cli::init_log(matches.occurrences_of("verbose"));
+                subs::snapshot::list(sub).await?
+            }
+            Some(("fetch", sub)) => subs::snapshot::fetch(sub, run(sub, true)).await?,
+            Some(("restore", sub)) => subs::snapshot::restore(sub, run(sub, true)).await?,
+            _ => (),
+        },
+        Some(("vault", sub)) => match sub.subcommand() {
+            Some(("status", sub)) => subs::vault::status(sub, run(sub, true)).await?,
+            Some(("unseal", sub)) => subs::vault::unseal(sub, run(sub, true)).await?,
+            _ => (),
+        },
+        Some(("context", sub)) => {
+            cli::init_log(matches.occurrences_of("verbose"));
+            match sub.subcommand() {
+                Some(("list", _)) => subs::context::list().await?,
+                Some(("use", sub)) => subs::context::switch(sub).await?,
+                Some(("show", sub)) => subs::context::show(sub).await?,
+                _ => (),
+            }
+        }
         Some(("completions", sub)) => {
             if let Some(shell) = sub.get_one::<Shell>("shell").copied() {
                 cli::completions(shell, app).await;
@@ -1,7 +1,12 @@
-use anyhow::{Context, Result};
+use super::node::{CpuResources, DiskResources, MemoryResources};
+use crate::bitte::policy::RequestPolicy;
+use anyhow::{anyhow, Context, Result};
 use regex::Regex;
 use reqwest::Client;
 use serde::{de::Deserializer, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
 use std::sync::Arc;
 use tokio::task::JoinHandle;
 use uuid::Uuid;
@@ -29,15 +34,69 @@ pub struct NomadAlloc {
     pub index: AllocIndex,
     #[serde(rename = "NodeID")]
     pub node_id: Uuid,
+    #[serde(
+        rename = "AllocatedResources",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub resources: Option<AllocResources>,
+}
+
+/// What the scheduler set aside for an allocation. The listing flattens the
+/// tasks' resources, the allocation itself has them per task.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AllocResources {
+    #[serde(rename = "Flattened", default, skip_serializing_if = "Option::is_none")]
+    pub flattened: Option<TaskResources>,
+    #[serde(rename = "Tasks", default, skip_serializing_if = "Option::is_none")]
+    pub tasks: Option<HashMap<String, TaskResources>>,
+    #[serde(rename = "Shared", default)]
+    pub shared: DiskResources,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct TaskResources {
+    #[serde(rename = "Cpu", default)]
+    pub cpu: CpuResources,
+    #[serde(rename = "Memory", default)]
+    pub memory: MemoryResources,
+}
+
+impl AllocResources {
+    fn sum(&self, get: impl Fn(&TaskResources) -> u64) -> u64 {
+        match (&self.flattened, &self.tasks) {
+            (Some(flattened), _) => get(flattened),
+            (None, Some(tasks)) => tasks.values().map(get).sum(),
+            (None, None) => 0,
+        }
+    }
+
+    pub fn cpu(&self) -> u64 {
+        self.sum(|task| task.cpu.cpu_shares)
+    }
+
+    pub fn memory(&self) -> u64 {
+        self.sum(|task| task.memory.memory_mb)
+    }
+
+    pub fn disk(&self) -> u64 {
+        self.shared.disk_mb
+    }
 }
 
 impl NomadAlloc {
-    pub async fn find_allocs(client: Arc<Client>, domain: String) -> Result<NomadAllocs> {
-        let url = format!("https://nomad.{}/v1/allocations", domain);
-        let allocs = client
-            .get(&url)
-            .query(&[("namespace", "*"), ("task_states", "false")])
-            .send()
+    pub async fn find_allocs(
+        client: Arc<Client>,
+        addr: String,
+        policy: RequestPolicy,
+    ) -> Result<NomadAllocs> {
+        let url = format!("{}/v1/allocations", addr);
+        let allocs = policy
+            .send(client.get(&url).query(&[
+                ("namespace", "*"),
+                ("task_states", "false"),
+                ("resources", "true"),
+            ]))
             .await
             .with_context(|| format!("failed to query: {}", &url))?
             .json::<NomadAllocs>()
@@ -45,6 +104,37 @@ impl NomadAlloc {
             .with_context(|| format!("failed to decode response from: {}", &url))?;
         Ok(allocs)
     }
+
+    /// Names of the tasks that have been started in this allocation.
+    pub async fn find_tasks(
+        &self,
+        client: Arc<Client>,
+        addr: String,
+        policy: &RequestPolicy,
+    ) -> Result<Vec<String>> {
+        let url = format!("{}/v1/allocation/{}", addr, self.id);
+        let details = policy
+            .send(client.get(&url))
+            .await
+            .with_context(|| format!("failed to query: {}", &url))?
+            .json::<AllocDetails>()
+            .await
+            .with_context(|| format!("failed to decode response from: {}", &url))?;
+        let mut tasks: Vec<String> = details
+            .task_states
+            .unwrap_or_default()
+            .into_keys()
+            .collect();
+        tasks.sort();
+        Ok(tasks)
+    }
+}
+
+/// The parts of a single allocation's details that aren't in the listing.
+#[derive(Debug, Deserialize)]
+struct AllocDetails {
+    #[serde(rename = "TaskStates")]
+    task_states: Option<HashMap<String, serde_json::Value>>,
 }
 
 /// Collection of Nomad allocations.
@@ -66,6 +156,67 @@ impl AllocIndex {
     }
 }
 
+/// Selects allocations by index: `*` for all of them, a single index, an
+/// inclusive range like `2-4`, or a comma separated list of those.
+#[derive(Debug, Clone)]
+pub enum IndexSelector {
+    All,
+    Ranges(Vec<RangeInclusive<u32>>),
+}
+
+impl IndexSelector {
+    pub fn matches(&self, index: &AllocIndex) -> bool {
+        match self {
+            Self::All => true,
+            Self::Ranges(ranges) => match index.get() {
+                Some(i) => ranges.iter().any(|range| range.contains(&i)),
+                None => false,
+            },
+        }
+    }
+}
+
+impl FromStr for IndexSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.trim() == "*" {
+            return Ok(Self::All);
+        }
+
+        let index = |i: &str| {
+            i.trim().parse::<u32>().map_err(|_| {
+                anyhow!(
+                    "{} is not a valid index; expected '*', '3', '1-3' or '0,2,5'",
+                    s
+                )
+            })
+        };
+
+        let mut ranges = Vec::new();
+        for part in s.split(',') {
+            let range = match part.split_once('-') {
+                Some((start, end)) => index(start)?..=index(end)?,
+                None => {
+                    let i = index(part)?;
+                    i..=i
+                }
+            };
+            if range.is_empty() {
+                return Err(anyhow!(
+                    "{} is a reversed range; did you mean {}-{}?",
+                    part.trim(),
+                    range.end(),
+                    range.start()
+                ));
+            }
+            ranges.push(range);
+        }
+
+        Ok(Self::Ranges(ranges))
+    }
+}
+
 fn pull_index<'de, D>(deserializer: D) -> Result<AllocIndex, D::Error>
 where
     D: Deserializer<'de>,
@@ -84,3 +235,52 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matching(selector: &str) -> Vec<u32> {
+        let selector: IndexSelector = selector.parse().unwrap();
+        (0..10)
+            .filter(|i| selector.matches(&AllocIndex::Int(*i)))
+            .collect()
+    }
+
+    #[test]
+    fn index_selector_grammar() {
+        assert_eq!(matching("*"), (0..10).collect::<Vec<u32>>());
+        assert_eq!(matching("3"), vec![3]);
+        assert_eq!(matching("1-3"), vec![1, 2, 3]);
+        assert_eq!(matching("2-2"), vec![2]);
+        assert_eq!(matching("0,2,5"), vec![0, 2, 5]);
+        assert_eq!(matching(" 1 - 2 , 7-8,4 "), vec![1, 2, 4, 7, 8]);
+    }
+
+    #[test]
+    fn index_selector_rejects_invalid() {
+        for selector in ["", "a", "1-", "-1", "1-2-3", "1,,2", "**", "-"] {
+            assert!(
+                selector.parse::<IndexSelector>().is_err(),
+                "{:?} should be rejected",
+                selector
+            );
+        }
+    }
+
+    #[test]
+    fn index_selector_rejects_reversed_range() {
+        let error = "0,3-1".parse::<IndexSelector>().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "3-1 is a reversed range; did you mean 1-3?"
+        );
+    }
+
+    #[test]
+    fn index_selector_skips_unknown_index() {
+        let selector: IndexSelector = "0-9".parse().unwrap();
+        assert!(!selector.matches(&AllocIndex::String("web[1]".to_owned())));
+        assert!(IndexSelector::All.matches(&AllocIndex::String("web[1]".to_owned())));
+    }
+}
@@ -1,4 +1,6 @@
 use super::alloc::NomadAllocs;
+use super::job::error_for_body;
+use crate::bitte::policy::RequestPolicy;
 use anyhow::{Context, Result};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
@@ -19,17 +21,28 @@ pub struct NomadClient {
     pub address: Option<IpAddr>,
     #[serde(rename = "NodeClass")]
     pub node_class: Option<String>,
+    #[serde(rename = "Name", default)]
+    pub name: Option<String>,
+    /// `ready`, `down` or `initializing`
+    #[serde(rename = "Status", default)]
+    pub status: Option<String>,
+    /// `eligible` or `ineligible` for new allocations
+    #[serde(rename = "SchedulingEligibility", default)]
+    pub eligibility: Option<String>,
 }
 
 /// Collection of Nomad clients
 pub type NomadClients = Vec<NomadClient>;
 
 impl NomadClient {
-    pub async fn find_nomad_nodes(client: Arc<Client>, domain: String) -> Result<NomadClients> {
-        let url = format!("https://nomad.{}/v1/nodes", domain);
-        let nodes = client
-            .get(&url)
-            .send()
+    pub async fn find_nomad_nodes(
+        client: Arc<Client>,
+        addr: String,
+        policy: RequestPolicy,
+    ) -> Result<NomadClients> {
+        let url = format!("{}/v1/nodes", addr);
+        let nodes = policy
+            .send(client.get(&url))
             .await
             .with_context(|| format!("failed to query: {}", &url))?
             .json::<NomadClients>()
@@ -37,4 +50,33 @@ impl NomadClient {
             .with_context(|| format!("failed to decode response from: {}", &url))?;
         Ok(nodes)
     }
+
+    pub fn is_down(&self) -> bool {
+        self.status.as_deref() == Some("down")
+    }
+
+    /// Whether the scheduler would place new allocations on the node.
+    pub fn takes_work(&self) -> bool {
+        self.status.as_deref() == Some("ready") && self.eligibility.as_deref() == Some("eligible")
+    }
+
+    /// Remove the node from Nomad for good, along with its allocations.
+    pub async fn purge(
+        &self,
+        client: Arc<Client>,
+        addr: String,
+        policy: &RequestPolicy,
+    ) -> Result<()> {
+        let url = format!("{}/v1/node/{}/purge", addr, self.id);
+        let response = client
+            .post(&url)
+            .timeout(policy.timeout)
+            .send()
+            .await
+            .with_context(|| format!("failed to query: {}", &url))?;
+        error_for_body(response)
+            .await
+            .with_context(|| format!("failed to purge Nomad node {}", self.id))?;
+        Ok(())
+    }
 }
@@ -0,0 +1,288 @@
+use super::node::NomadNode;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+
+/// A hard placement rule of a job, group or task.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Constraint {
+    #[serde(rename = "LTarget", default)]
+    pub ltarget: String,
+    #[serde(rename = "RTarget", default)]
+    pub rtarget: String,
+    #[serde(rename = "Operand", default)]
+    pub operand: String,
+}
+
+impl Display for Constraint {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let parts = [&self.ltarget, &self.operand, &self.rtarget];
+        let parts: Vec<&str> = parts
+            .iter()
+            .map(|part| part.as_str())
+            .filter(|part| !part.is_empty())
+            .collect();
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+impl Constraint {
+    pub fn is_distinct_hosts(&self) -> bool {
+        self.operand == "distinct_hosts" && self.rtarget != "false"
+    }
+
+    /// Whether the node satisfies the constraint, as far as can be told from
+    /// the node alone. Constraints that depend on other allocations, like
+    /// `distinct_hosts` and `distinct_property`, and unknown operands are
+    /// left to the caller and always match here.
+    pub fn matches(&self, node: &NomadNode) -> bool {
+        let left = resolve(&self.ltarget, node);
+        let right = resolve(&self.rtarget, node);
+
+        match self.operand.as_str() {
+            "=" | "==" | "is" => left.is_some() && left == right,
+            "!=" | "not" => left != right,
+            "<" | "<=" | ">" | ">=" => match (left, right) {
+                (Some(left), Some(right)) => {
+                    let ordering = compare(&left, &right);
+                    match self.operand.as_str() {
+                        "<" => ordering == Ordering::Less,
+                        "<=" => ordering != Ordering::Greater,
+                        ">" => ordering == Ordering::Greater,
+                        _ => ordering != Ordering::Less,
+                    }
+                }
+                _ => false,
+            },
+            "regexp" => match (left, right) {
+                (Some(left), Some(right)) => Regex::new(&right)
+                    .map(|re| re.is_match(&left))
+                    .unwrap_or(false),
+                _ => false,
+            },
+            "set_contains" | "set_contains_all" | "set_contains_any" => match (left, right) {
+                (Some(left), Some(right)) => {
+                    let set: Vec<&str> = left.split(',').map(str::trim).collect();
+                    let mut wanted = right.split(',').map(str::trim);
+                    if self.operand == "set_contains_any" {
+                        wanted.any(|item| set.contains(&item))
+                    } else {
+                        wanted.all(|item| set.contains(&item))
+                    }
+                }
+                _ => false,
+            },
+            "version" | "semver" => match (left, right) {
+                (Some(left), Some(right)) => version_matches(&left, &right),
+                _ => false,
+            },
+            "is_set" => left.is_some(),
+            "is_not_set" => left.is_none(),
+            _ => true,
+        }
+    }
+}
+
+/// Interpolate a constraint target against the node; targets that aren't
+/// `${...}` are literals.
+fn resolve(target: &str, node: &NomadNode) -> Option<String> {
+    let name = match target
+        .strip_prefix("${")
+        .and_then(|target| target.strip_suffix('}'))
+    {
+        Some(name) => name,
+        None if target.is_empty() => return None,
+        None => return Some(target.to_owned()),
+    };
+
+    match name {
+        "node.unique.id" => Some(node.id.to_string()),
+        "node.unique.name" => Some(node.name.clone()),
+        "node.datacenter" => Some(node.datacenter.clone()),
+        "node.class" => Some(node.node_class.clone()),
+        _ => {
+            if let Some(attr) = name.strip_prefix("attr.") {
+                node.attributes.get(attr).cloned()
+            } else if let Some(key) = name.strip_prefix("meta.") {
+                node.meta.as_ref().and_then(|meta| meta.get(key)).cloned()
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// Numbers compare as numbers, everything else lexically.
+fn compare(left: &str, right: &str) -> Ordering {
+    match (left.parse::<f64>(), right.parse::<f64>()) {
+        (Ok(left), Ok(right)) => left.partial_cmp(&right).unwrap_or(Ordering::Equal),
+        _ => left.cmp(right),
+    }
+}
+
+/// Check a version against requirements like `>= 1.2.0, < 2.0`. The
+/// pessimistic `~>` keeps all but the last given component: `~> 1.2.3` is
+/// at least 1.2.3 but below 1.3, `~> 1.2` at least 1.2 but below 2.
+fn version_matches(version: &str, requirements: &str) -> bool {
+    let version = version_parts(version);
+    requirements.split(',').all(|requirement| {
+        let requirement = requirement.trim();
+        let split = requirement
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(requirement.len());
+        let (operator, wanted) = requirement.split_at(split);
+        let wanted_parts = version_parts(wanted);
+        let ordering = version.cmp(&wanted_parts);
+        match operator.trim_end_matches('v').trim() {
+            "<" => ordering == Ordering::Less,
+            "<=" => ordering != Ordering::Greater,
+            ">" => ordering == Ordering::Greater,
+            ">=" => ordering != Ordering::Less,
+            "!=" => ordering != Ordering::Equal,
+            "" | "=" | "==" => ordering == Ordering::Equal,
+            "~>" => {
+                let fixed = wanted
+                    .split(['-', '+'])
+                    .next()
+                    .unwrap_or("")
+                    .split('.')
+                    .count()
+                    - 1;
+                let part = |parts: &[u64], i: usize| parts.get(i).copied().unwrap_or(0);
+                ordering != Ordering::Less
+                    && (0..fixed).all(|i| part(&version, i) == part(&wanted_parts, i))
+            }
+            _ => false,
+        }
+    })
+}
+
+/// The numeric components of a version, ignoring any pre-release suffix;
+/// missing components count as zero.
+fn version_parts(version: &str) -> Vec<u64> {
+    let version = version.trim().trim_start_matches('v');
+    let version = version.split(['-', '+']).next().unwrap_or("");
+    let mut parts: Vec<u64> = version
+        .split('.')
+        .map(|part| {
+            let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+            digits.parse().unwrap_or(0)
+        })
+        .collect();
+    while parts.len() < 3 {
+        parts.push(0);
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn node() -> NomadNode {
+        serde_json::from_value(json!({
+            "ID": "6f3b4a7e-0000-4000-8000-000000000001",
+            "Name": "client-1",
+            "Datacenter": "eu-central-1",
+            "NodeClass": "highmem",
+            "Status": "ready",
+            "SchedulingEligibility": "eligible",
+            "Attributes": {
+                "kernel.name": "linux",
+                "cpu.numcores": "8",
+                "nomad.version": "1.3.5",
+                "driver.docker.volumes": "a,b,c",
+            },
+            "Meta": { "rack": "r1" },
+        }))
+        .unwrap()
+    }
+
+    fn constraint(ltarget: &str, operand: &str, rtarget: &str) -> Constraint {
+        Constraint {
+            ltarget: ltarget.to_owned(),
+            rtarget: rtarget.to_owned(),
+            operand: operand.to_owned(),
+        }
+    }
+
+    #[test]
+    fn matches_equality() {
+        let node = node();
+        assert!(constraint("${attr.kernel.name}", "=", "linux").matches(&node));
+        assert!(!constraint("${attr.kernel.name}", "=", "darwin").matches(&node));
+        assert!(constraint("${node.class}", "!=", "lowmem").matches(&node));
+        assert!(constraint("${meta.rack}", "==", "r1").matches(&node));
+        // an unset attribute equals nothing
+        assert!(!constraint("${attr.unset}", "=", "").matches(&node));
+    }
+
+    #[test]
+    fn matches_ordering() {
+        let node = node();
+        assert!(constraint("${attr.cpu.numcores}", ">=", "8").matches(&node));
+        assert!(!constraint("${attr.cpu.numcores}", ">", "10").matches(&node));
+        assert!(constraint("${node.datacenter}", "<", "us-east-1").matches(&node));
+        assert!(!constraint("${attr.unset}", "<", "1").matches(&node));
+    }
+
+    #[test]
+    fn matches_regexp_and_sets() {
+        let node = node();
+        assert!(constraint("${node.unique.name}", "regexp", "^client-[0-9]+$").matches(&node));
+        assert!(!constraint("${node.unique.name}", "regexp", "(").matches(&node));
+        assert!(constraint("${attr.driver.docker.volumes}", "set_contains", "a,c").matches(&node));
+        assert!(
+            !constraint("${attr.driver.docker.volumes}", "set_contains_all", "a,d").matches(&node)
+        );
+        assert!(
+            constraint("${attr.driver.docker.volumes}", "set_contains_any", "d,b").matches(&node)
+        );
+    }
+
+    #[test]
+    fn matches_versions() {
+        let node = node();
+        assert!(constraint("${attr.nomad.version}", "version", ">= 1.3, < 1.4").matches(&node));
+        assert!(constraint("${attr.nomad.version}", "semver", "~> 1.3.0").matches(&node));
+        assert!(!constraint("${attr.nomad.version}", "version", "~> 1.2.0").matches(&node));
+    }
+
+    #[test]
+    fn matches_presence_and_unknown_operands() {
+        let node = node();
+        assert!(constraint("${meta.rack}", "is_set", "").matches(&node));
+        assert!(constraint("${meta.row}", "is_not_set", "").matches(&node));
+        assert!(constraint("", "distinct_hosts", "true").matches(&node));
+        assert!(constraint("${attr.kernel.name}", "frobnicate", "x").matches(&node));
+    }
+
+    #[test]
+    fn version_operators() {
+        assert!(version_matches("1.2.3", "1.2.3"));
+        assert!(version_matches("v1.2.3", "= 1.2.3"));
+        assert!(version_matches("1.2", "== 1.2.0"));
+        assert!(version_matches("1.2.4", "!= 1.2.3"));
+        assert!(version_matches("1.2.3", "> 1.2"));
+        assert!(version_matches("1.10.0", "> 1.9.9"));
+        assert!(version_matches("1.2.3-beta1", "<= 1.2.3"));
+        assert!(version_matches("0.9", ">= 0.8, < 1"));
+        assert!(!version_matches("1.0", ">= 0.8, < 1"));
+        assert!(!version_matches("1.2.3", "=~ 1.2.3"));
+    }
+
+    #[test]
+    fn version_pessimistic() {
+        assert!(version_matches("1.2.3", "~> 1.2.3"));
+        assert!(version_matches("1.2.9", "~> 1.2.3"));
+        assert!(!version_matches("1.3.0", "~> 1.2.3"));
+        assert!(!version_matches("1.2.2", "~> 1.2.3"));
+        assert!(version_matches("1.9.0", "~> 1.2"));
+        assert!(!version_matches("2.0.0", "~> 1.2"));
+        assert!(!version_matches("1.1.9", "~> 1.2"));
+        assert!(version_matches("7.0", "~> 1"));
+        assert!(version_matches("1.2.5", "~> 1.2.3, != 1.2.4"));
+    }
+}
@@ -0,0 +1,202 @@
+use super::job::error_for_body;
+use crate::bitte::policy::RequestPolicy;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, FixedOffset};
+use reqwest::{Client, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// One of the two log streams Nomad keeps for every task.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogType {
+    Stdout,
+    Stderr,
+}
+
+impl Display for LogType {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let log_type = match *self {
+            LogType::Stdout => "stdout",
+            LogType::Stderr => "stderr",
+        };
+        write!(f, "{}", log_type)
+    }
+}
+
+/// The end of a log stream that an offset is counted from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogOrigin {
+    Start,
+    End,
+}
+
+impl Display for LogOrigin {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let origin = match *self {
+            LogOrigin::Start => "start",
+            LogOrigin::End => "end",
+        };
+        write!(f, "{}", origin)
+    }
+}
+
+/// A file or directory inside an allocation's filesystem.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AllocFile {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "IsDir")]
+    pub is_dir: bool,
+    #[serde(rename = "Size")]
+    pub size: u64,
+    #[serde(rename = "FileMode")]
+    pub mode: String,
+    #[serde(rename = "ModTime")]
+    pub mod_time: DateTime<FixedOffset>,
+}
+
+/// Collection of files in an allocation directory.
+pub type AllocFiles = Vec<AllocFile>;
+
+/// Parameters for reading a task's logs through `/v1/client/fs/logs`.
+#[derive(Debug, Clone)]
+pub struct LogRequest {
+    pub task: String,
+    pub log_type: LogType,
+    pub origin: LogOrigin,
+    pub offset: u64,
+    pub follow: bool,
+}
+
+/// List the entries of `path` inside the allocation directory.
+pub async fn ls(
+    client: Arc<Client>,
+    addr: String,
+    alloc: Uuid,
+    path: &str,
+    policy: &RequestPolicy,
+) -> Result<AllocFiles> {
+    let url = format!("{}/v1/client/fs/ls/{}", addr, alloc);
+    let files = policy
+        .send(client.get(&url).query(&[("path", path)]))
+        .await
+        .with_context(|| format!("failed to query: {}", &url))?
+        .error_for_status()
+        .with_context(|| format!("failed to list {} in allocation {}", path, alloc))?
+        .json::<AllocFiles>()
+        .await
+        .with_context(|| format!("failed to decode response from: {}", &url))?;
+    Ok(files)
+}
+
+/// Describe a single file or directory inside the allocation directory.
+pub async fn stat(
+    client: Arc<Client>,
+    addr: String,
+    alloc: Uuid,
+    path: &str,
+    policy: &RequestPolicy,
+) -> Result<AllocFile> {
+    let url = format!("{}/v1/client/fs/stat/{}", addr, alloc);
+    let response = policy
+        .send(client.get(&url).query(&[("path", path)]))
+        .await
+        .with_context(|| format!("failed to query: {}", &url))?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Err(anyhow!("{} does not exist in allocation {}", path, alloc));
+    }
+    let file = error_for_body(response)
+        .await
+        .with_context(|| format!("failed to stat {} in allocation {}", path, alloc))?
+        .json::<AllocFile>()
+        .await
+        .with_context(|| format!("failed to decode response from: {}", &url))?;
+    Ok(file)
+}
+
+/// Open a file inside the allocation directory for reading; the body is
+/// read incrementally with `Response::chunk`.
+pub async fn cat(client: Arc<Client>, addr: String, alloc: Uuid, path: &str) -> Result<Response> {
+    let url = format!("{}/v1/client/fs/cat/{}", addr, alloc);
+    let response = client
+        .get(&url)
+        .query(&[("path", path)])
+        .send()
+        .await
+        .with_context(|| format!("failed to query: {}", &url))?
+        .error_for_status()
+        .with_context(|| format!("failed to read {} in allocation {}", path, alloc))?;
+    Ok(response)
+}
+
+/// Open a plain-text log stream for a task; the body is read incrementally
+/// with `Response::chunk` and never ends while `follow` is set.
+pub async fn logs(
+    client: Arc<Client>,
+    addr: String,
+    alloc: Uuid,
+    request: &LogRequest,
+) -> Result<Response> {
+    let url = format!("{}/v1/client/fs/logs/{}", addr, alloc);
+    let response = client
+        .get(&url)
+        .query(&[
+            ("task", request.task.clone()),
+            ("type", request.log_type.to_string()),
+            ("origin", request.origin.to_string()),
+            ("offset", request.offset.to_string()),
+            ("follow", request.follow.to_string()),
+            ("plain", "true".to_owned()),
+        ])
+        .send()
+        .await
+        .with_context(|| format!("failed to query: {}", &url))?
+        .error_for_status()
+        .with_context(|| {
+            format!(
+                "failed to read {} of task {} in allocation {}",
+                request.log_type, request.task, alloc
+            )
+        })?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    async fn stat_error(status: &'static str, body: &'static str) -> String {
+        let (addr, _) = testing::serve(move |_, _| (status, body.to_owned())).await;
+        let client = Arc::new(Client::new());
+        let error = stat(
+            client,
+            addr,
+            Uuid::nil(),
+            "local/x",
+            &RequestPolicy::default(),
+        )
+        .await
+        .unwrap_err();
+        format!("{:#}", error)
+    }
+
+    #[tokio::test]
+    async fn stat_missing_file() {
+        let error = stat_error("404 Not Found", "file not found").await;
+        assert!(error.starts_with("local/x does not exist"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn stat_reports_other_errors() {
+        let error = stat_error("403 Forbidden", "Permission denied").await;
+        assert!(!error.contains("does not exist"), "{}", error);
+        assert!(
+            error.contains("403 Forbidden: Permission denied"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use super::constraint::Constraint;
+use crate::bitte::policy::RequestPolicy;
+use anyhow::{anyhow, Context, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A rendered Nomad job in its JSON API form.
+#[derive(Debug, Clone)]
+pub struct NomadJob {
+    pub id: String,
+    pub namespace: String,
+    pub spec: Value,
+}
+
+impl NomadJob {
+    /// Read a job from a JSON file, either wrapped as `{"Job": {...}}` like
+    /// the API expects, or as the bare job object.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        let value: Value = serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("{} is not valid JSON", path.display()))?;
+        Self::from_value(value).with_context(|| format!("{} is not a Nomad job", path.display()))
+    }
+
+    pub fn from_value(mut value: Value) -> Result<Self> {
+        let spec = match value.get_mut("Job") {
+            Some(job) => job.take(),
+            None => value,
+        };
+
+        let id = spec
+            .get("ID")
+            .or_else(|| spec.get("Name"))
+            .and_then(Value::as_str)
+            .context("the job has neither an ID nor a Name")?
+            .to_owned();
+
+        let namespace = spec
+            .get("Namespace")
+            .and_then(Value::as_str)
+            .unwrap_or("default")
+            .to_owned();
+
+        Ok(Self {
+            id,
+            namespace,
+            spec,
+        })
+    }
+
+    /// Ask the scheduler what would change if this job was submitted.
+    pub async fn plan(
+        &self,
+        client: Arc<Client>,
+        addr: String,
+        policy: &RequestPolicy,
+    ) -> Result<JobPlan> {
+        let url = format!("{}/v1/job/{}/plan", addr, self.id);
+        // planning changes nothing, so it's as safe to retry as a read
+        let response = policy
+            .send(
+                client
+                    .post(&url)
+                    .query(&[("namespace", &self.namespace)])
+                    .json(&json!({ "Job": self.spec, "Diff": true })),
+            )
+            .await
+            .with_context(|| format!("failed to query: {}", &url))?;
+
+        let plan = error_for_body(response)
+            .await
+            .with_context(|| format!("failed to plan job {}", self.id))?
+            .json::<JobPlan>()
+            .await
+            .with_context(|| format!("failed to decode response from: {}", &url))?;
+        Ok(plan)
+    }
+
+    /// Submit the job, but only if it wasn't modified since `modify_index`
+    /// was returned by a plan. A retry would fail on the index once the
+    /// first attempt got through, so there is only one.
+    pub async fn register(
+        &self,
+        client: Arc<Client>,
+        addr: String,
+        modify_index: u64,
+        policy: &RequestPolicy,
+    ) -> Result<JobRegistration> {
+        let url = format!("{}/v1/jobs", addr);
+        let response = client
+            .post(&url)
+            .timeout(policy.timeout)
+            .query(&[("namespace", &self.namespace)])
+            .json(&json!({
+                "Job": self.spec,
+                "EnforceIndex": true,
+                "JobModifyIndex": modify_index,
+            }))
+            .send()
+            .await
+            .with_context(|| format!("failed to query: {}", &url))?;
+
+        let registration = error_for_body(response)
+            .await
+            .with_context(|| format!("failed to register job {}", self.id))?
+            .json::<JobRegistration>()
+            .await
+            .with_context(|| format!("failed to decode response from: {}", &url))?;
+        Ok(registration)
+    }
+}
+
+/// The parts of a registered job that decide where its allocations may run.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JobDefinition {
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(rename = "Namespace", default)]
+    pub namespace: String,
+    /// `service`, `batch`, `system` or `sysbatch`
+    #[serde(rename = "Type", default)]
+    pub job_type: String,
+    #[serde(rename = "Datacenters", default)]
+    pub datacenters: Vec<String>,
+    #[serde(rename = "Constraints", default)]
+    pub constraints: Option<Vec<Constraint>>,
+    #[serde(rename = "TaskGroups", default)]
+    pub task_groups: Vec<TaskGroupDefinition>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TaskGroupDefinition {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Constraints", default)]
+    pub constraints: Option<Vec<Constraint>>,
+    #[serde(rename = "Volumes", default)]
+    pub volumes: Option<BTreeMap<String, VolumeRequest>>,
+    #[serde(rename = "Tasks", default)]
+    pub tasks: Vec<TaskDefinition>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TaskDefinition {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Driver", default)]
+    pub driver: String,
+    #[serde(rename = "Constraints", default)]
+    pub constraints: Option<Vec<Constraint>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VolumeRequest {
+    /// `host` or `csi`
+    #[serde(rename = "Type", default)]
+    pub volume_type: String,
+    #[serde(rename = "Source", default)]
+    pub source: String,
+}
+
+impl JobDefinition {
+    pub async fn get(
+        client: Arc<Client>,
+        addr: String,
+        namespace: String,
+        id: String,
+        policy: RequestPolicy,
+    ) -> Result<Self> {
+        let url = format!("{}/v1/job/{}", addr, id);
+        policy
+            .send(client.get(&url).query(&[("namespace", &namespace)]))
+            .await
+            .with_context(|| format!("failed to query: {}", &url))?
+            .error_for_status()
+            .with_context(|| format!("failed to query: {}", &url))?
+            .json::<Self>()
+            .await
+            .with_context(|| format!("failed to decode response from: {}", &url))
+    }
+
+    /// Jobs that run on every eligible node aren't moved off a drained one.
+    pub fn is_system(&self) -> bool {
+        self.job_type == "system" || self.job_type == "sysbatch"
+    }
+
+    pub fn task_group(&self, name: &str) -> Option<&TaskGroupDefinition> {
+        self.task_groups.iter().find(|group| group.name == name)
+    }
+}
+
+/// Nomad explains rejected jobs in the response body, which is far more
+/// useful than the bare status code.
+pub(crate) async fn error_for_body(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(anyhow!("{}: {}", status, body.trim()))
+}
+
+/// The scheduler's dry-run result for a job.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JobPlan {
+    #[serde(rename = "JobModifyIndex")]
+    pub job_modify_index: u64,
+    #[serde(rename = "Diff")]
+    pub diff: Option<ObjectDiff>,
+    #[serde(rename = "Annotations")]
+    pub annotations: Option<PlanAnnotations>,
+    #[serde(rename = "FailedTGAllocs", default)]
+    pub failed_tg_allocs: Option<BTreeMap<String, AllocMetric>>,
+    #[serde(rename = "Warnings", default)]
+    pub warnings: Option<String>,
+}
+
+/// A diff of the job, a task group, a task, or any object nested in them.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ObjectDiff {
+    #[serde(rename = "Type")]
+    pub diff_type: DiffType,
+    #[serde(rename = "Name", alias = "ID", default)]
+    pub name: String,
+    #[serde(rename = "Fields", default)]
+    pub fields: Option<Vec<FieldDiff>>,
+    #[serde(rename = "Objects", default)]
+    pub objects: Option<Vec<ObjectDiff>>,
+    #[serde(rename = "TaskGroups", default)]
+    pub task_groups: Option<Vec<ObjectDiff>>,
+    #[serde(rename = "Tasks", default)]
+    pub tasks: Option<Vec<ObjectDiff>>,
+    #[serde(rename = "Annotations", default)]
+    pub annotations: Option<Vec<String>>,
+    #[serde(rename = "Updates", default)]
+    pub updates: Option<BTreeMap<String, u64>>,
+}
+
+/// A change to a single field.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FieldDiff {
+    #[serde(rename = "Type")]
+    pub diff_type: DiffType,
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Old")]
+    pub old: String,
+    #[serde(rename = "New")]
+    pub new: String,
+    #[serde(rename = "Annotations", default)]
+    pub annotations: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum DiffType {
+    Added,
+    Deleted,
+    Edited,
+    None,
+}
+
+impl DiffType {
+    /// The marker Nomad itself uses in front of changed lines.
+    pub fn marker(&self) -> &'static str {
+        match self {
+            DiffType::Added => "+",
+            DiffType::Deleted => "-",
+            DiffType::Edited => "+/-",
+            DiffType::None => " ",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlanAnnotations {
+    #[serde(rename = "DesiredTGUpdates", default)]
+    pub desired_tg_updates: Option<BTreeMap<String, DesiredUpdates>>,
+}
+
+/// How many allocations of a task group the scheduler would touch.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct DesiredUpdates {
+    pub ignore: u64,
+    pub place: u64,
+    pub migrate: u64,
+    pub stop: u64,
+    pub in_place_update: u64,
+    pub destructive_update: u64,
+    pub canary: u64,
+    pub preemptions: u64,
+}
+
+/// Why allocations of a task group could not be placed.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AllocMetric {
+    #[serde(rename = "NodesEvaluated", default)]
+    pub nodes_evaluated: u64,
+    #[serde(rename = "NodesFiltered", default)]
+    pub nodes_filtered: u64,
+    #[serde(rename = "NodesAvailable", default)]
+    pub nodes_available: Option<BTreeMap<String, u64>>,
+    #[serde(rename = "ClassFiltered", default)]
+    pub class_filtered: Option<BTreeMap<String, u64>>,
+    #[serde(rename = "ConstraintFiltered", default)]
+    pub constraint_filtered: Option<BTreeMap<String, u64>>,
+    #[serde(rename = "NodesExhausted", default)]
+    pub nodes_exhausted: u64,
+    #[serde(rename = "DimensionExhausted", default)]
+    pub dimension_exhausted: Option<BTreeMap<String, u64>>,
+    #[serde(rename = "QuotaExhausted", default)]
+    pub quota_exhausted: Option<Vec<String>>,
+    #[serde(rename = "CoalescedFailures", default)]
+    pub coalesced_failures: u64,
+}
+
+/// The response to a successful job submission.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JobRegistration {
+    #[serde(rename = "EvalID")]
+    pub eval_id: String,
+    #[serde(rename = "JobModifyIndex")]
+    pub job_modify_index: u64,
+    #[serde(rename = "Warnings", default)]
+    pub warnings: Option<String>,
+}
@@ -1,2 +1,7 @@
 pub mod alloc;
 pub mod client;
+pub mod constraint;
+pub mod fs;
+pub mod job;
+pub mod node;
+pub mod schema;
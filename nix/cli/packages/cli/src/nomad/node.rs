@@ -0,0 +1,137 @@
+use super::job::error_for_body;
+use crate::bitte::policy::RequestPolicy;
+use anyhow::{Context, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Everything Nomad knows about a single client node.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NomadNode {
+    #[serde(rename = "ID")]
+    pub id: Uuid,
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Datacenter")]
+    pub datacenter: String,
+    #[serde(rename = "NodeClass", default)]
+    pub node_class: String,
+    #[serde(rename = "Status")]
+    pub status: String,
+    #[serde(rename = "SchedulingEligibility")]
+    pub eligibility: String,
+    #[serde(rename = "Drain", default)]
+    pub drain: bool,
+    #[serde(rename = "Attributes", default)]
+    pub attributes: BTreeMap<String, String>,
+    #[serde(rename = "Meta", default)]
+    pub meta: Option<BTreeMap<String, String>>,
+    #[serde(rename = "Drivers", default)]
+    pub drivers: Option<BTreeMap<String, DriverInfo>>,
+    #[serde(rename = "HostVolumes", default)]
+    pub host_volumes: Option<BTreeMap<String, HostVolume>>,
+    #[serde(rename = "NodeResources")]
+    pub resources: Option<NodeResources>,
+    #[serde(rename = "ReservedResources")]
+    pub reserved: Option<NodeResources>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DriverInfo {
+    #[serde(rename = "Detected")]
+    pub detected: bool,
+    #[serde(rename = "Healthy")]
+    pub healthy: bool,
+    #[serde(rename = "HealthDescription", default)]
+    pub description: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HostVolume {
+    #[serde(rename = "Path")]
+    pub path: String,
+    #[serde(rename = "ReadOnly")]
+    pub read_only: bool,
+}
+
+/// The resources of a node, or the part of them reserved for the system.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct NodeResources {
+    #[serde(rename = "Cpu", default)]
+    pub cpu: CpuResources,
+    #[serde(rename = "Memory", default)]
+    pub memory: MemoryResources,
+    #[serde(rename = "Disk", default)]
+    pub disk: DiskResources,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct CpuResources {
+    #[serde(rename = "CpuShares", default)]
+    pub cpu_shares: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct MemoryResources {
+    #[serde(rename = "MemoryMB", default)]
+    pub memory_mb: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct DiskResources {
+    #[serde(rename = "DiskMB", default)]
+    pub disk_mb: u64,
+}
+
+impl NomadNode {
+    pub async fn get(
+        client: Arc<Client>,
+        addr: String,
+        id: Uuid,
+        policy: RequestPolicy,
+    ) -> Result<Self> {
+        let url = format!("{}/v1/node/{}", addr, id);
+        policy
+            .send(client.get(&url))
+            .await
+            .with_context(|| format!("failed to query: {}", &url))?
+            .error_for_status()
+            .with_context(|| format!("failed to query: {}", &url))?
+            .json::<Self>()
+            .await
+            .with_context(|| format!("failed to decode response from: {}", &url))
+    }
+
+    /// Mark the node ineligible and have Nomad migrate its allocations,
+    /// stopping whatever is left after `deadline`.
+    pub async fn drain(
+        client: Arc<Client>,
+        addr: String,
+        id: Uuid,
+        deadline: Duration,
+        policy: &RequestPolicy,
+    ) -> Result<()> {
+        let url = format!("{}/v1/node/{}/drain", addr, id);
+        let response = client
+            .post(&url)
+            .timeout(policy.timeout)
+            .json(&json!({
+                "DrainSpec": {
+                    "Deadline": deadline.as_nanos() as u64,
+                    "IgnoreSystemJobs": false,
+                },
+                "Meta": { "message": "drained by bitte" },
+            }))
+            .send()
+            .await
+            .with_context(|| format!("failed to query: {}", &url))?;
+        error_for_body(response)
+            .await
+            .with_context(|| format!("failed to drain Nomad node {}", id))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,1104 @@
+//! Rust equivalents of the `#json` types in `schemas/nomad/types.cue`, used to
+//! check rendered jobs before they are submitted.
+//!
+//! Fields starting with `_` aren't checked beyond their type; they're only
+//! there so that they aren't reported as unknown. `rename_all` ignores the
+//! leading underscore.
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+const JOB_TYPES: &[&str] = &["service", "system", "batch"];
+const DRIVERS: &[&str] = &["exec", "docker", "nspawn"];
+/// Drivers Nomad or bitte know about that the schema doesn't cover.
+const OTHER_DRIVERS: &[&str] = &["nix", "raw_exec", "java", "qemu"];
+const CONSTRAINT_OPERANDS: &[&str] = &[
+    "regexp",
+    "set_contains",
+    "distinct_hosts",
+    "distinct_property",
+    "=",
+    "==",
+    "is",
+    "!=",
+    "not",
+    ">",
+    ">=",
+    "<",
+    "<=",
+];
+const AFFINITY_OPERANDS: &[&str] = &[
+    "regexp",
+    "set_contains_all",
+    "set_contains",
+    "set_contains_any",
+    "=",
+    "==",
+    "is",
+    "!=",
+    "not",
+    ">",
+    ">=",
+    "<",
+    "<=",
+    "version",
+];
+/// Operands Nomad accepts that the schema doesn't cover.
+const OTHER_OPERANDS: &[&str] = &[
+    "version",
+    "semver",
+    "is_set",
+    "is_not_set",
+    "set_contains_all",
+    "set_contains_any",
+];
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Job {
+    pub namespace: Option<String>,
+    #[serde(rename = "ID")]
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub r#type: Option<String>,
+    pub priority: Option<u64>,
+    pub datacenters: Option<Vec<String>>,
+    pub task_groups: Option<Vec<TaskGroup>>,
+    pub affinities: Option<Vec<Affinity>>,
+    pub constraints: Option<Vec<Constraint>>,
+    pub spreads: Option<Vec<Spread>>,
+    _consul_token: Option<String>,
+    _vault_token: Option<String>,
+    pub vault: Option<Vault>,
+    pub update: Option<Update>,
+    pub migrate: Option<Migrate>,
+    pub periodic: Option<Periodic>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Affinity {
+    #[serde(rename = "LTarget")]
+    _l_target: Option<String>,
+    #[serde(rename = "RTarget")]
+    _r_target: Option<String>,
+    pub operand: Option<String>,
+    pub weight: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Constraint {
+    #[serde(rename = "LTarget")]
+    _l_target: Option<String>,
+    #[serde(rename = "RTarget")]
+    _r_target: Option<String>,
+    pub operand: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Spread {
+    pub attribute: Option<String>,
+    pub weight: Option<i64>,
+    pub spread_target: Option<Vec<SpreadTarget>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SpreadTarget {
+    _value: Option<String>,
+    pub percent: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RestartPolicy {
+    _attempts: Option<u64>,
+    _interval: Option<u64>,
+    _delay: Option<u64>,
+    pub mode: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Volume {
+    _name: Option<String>,
+    pub r#type: Option<String>,
+    pub source: Option<String>,
+    _read_only: Option<bool>,
+    _mount_options: Option<MountOptions>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MountOptions {
+    #[serde(rename = "FsType")]
+    _fs_type: Option<String>,
+    #[serde(rename = "mountFlags")]
+    _mount_flags: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ReschedulePolicy {
+    _attempts: Option<u64>,
+    pub delay_function: Option<String>,
+    pub delay: Option<u64>,
+    _interval: Option<u64>,
+    _max_delay: Option<u64>,
+    _unlimited: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Migrate {
+    pub health_check: Option<String>,
+    _healthy_deadline: Option<u64>,
+    _max_parallel: Option<u64>,
+    _min_healthy_time: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Periodic {
+    _enabled: Option<bool>,
+    _time_zone: Option<String>,
+    pub spec_type: Option<String>,
+    pub spec: Option<String>,
+    _prohibit_overlap: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Update {
+    _auto_promote: Option<bool>,
+    _auto_revert: Option<bool>,
+    _canary: Option<u64>,
+    pub health_check: Option<String>,
+    _healthy_deadline: Option<u64>,
+    _max_parallel: Option<u64>,
+    _min_healthy_time: Option<u64>,
+    _progress_deadline: Option<u64>,
+    _stagger: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TaskGroup {
+    pub affinities: Option<Vec<Affinity>>,
+    pub constraints: Option<Vec<Constraint>>,
+    pub spreads: Option<Vec<Spread>>,
+    pub count: Option<i64>,
+    _meta: Option<HashMap<String, String>>,
+    pub name: Option<String>,
+    pub restart_policy: Option<RestartPolicy>,
+    pub services: Option<Vec<Service>>,
+    _shutdown_delay: Option<u64>,
+    pub tasks: Option<Vec<Task>>,
+    pub volumes: Option<HashMap<String, Volume>>,
+    pub reschedule_policy: Option<ReschedulePolicy>,
+    _ephemeral_disk: Option<EphemeralDisk>,
+    pub migrate: Option<Migrate>,
+    pub update: Option<Update>,
+    pub networks: Option<Vec<Network>>,
+    _stop_after_client_disconnect: Option<u64>,
+    _scaling: Option<Value>,
+    pub vault: Option<Vault>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EphemeralDisk {
+    _migrate: Option<bool>,
+    #[serde(rename = "SizeMB")]
+    _size_mb: Option<u64>,
+    _sticky: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Dns {
+    _servers: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Port {
+    pub label: Option<String>,
+    _value: Option<u64>,
+    _to: Option<u64>,
+    _host_network: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Network {
+    pub mode: Option<String>,
+    _device: Option<String>,
+    #[serde(rename = "CIDR")]
+    _cidr: Option<String>,
+    #[serde(rename = "IP")]
+    _ip: Option<String>,
+    #[serde(rename = "DNS")]
+    _dns: Option<Dns>,
+    pub reserved_ports: Option<Vec<Port>>,
+    pub dynamic_ports: Option<Vec<Port>>,
+    #[serde(rename = "MBits")]
+    _mbits: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ServiceCheck {
+    pub address_mode: Option<String>,
+    _args: Option<Vec<String>>,
+    _check_restart: Option<CheckRestart>,
+    _command: Option<String>,
+    _expose: Option<bool>,
+    _failures_before_critical: Option<u64>,
+    _id: Option<String>,
+    _initial_status: Option<String>,
+    _interval: Option<u64>,
+    _method: Option<String>,
+    _name: Option<String>,
+    pub path: Option<String>,
+    pub port_label: Option<String>,
+    _protocol: Option<String>,
+    _success_before_passing: Option<u64>,
+    _task_name: Option<String>,
+    pub timeout: Option<u64>,
+    #[serde(rename = "TLSSkipVerify")]
+    _tls_skip_verify: Option<bool>,
+    pub on_update: Option<String>,
+    pub r#type: Option<String>,
+    _body: Option<String>,
+    _header: Option<HashMap<String, Vec<String>>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CheckRestart {
+    _limit: Option<u64>,
+    _grace: Option<u64>,
+    _ignore_warnings: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Lifecycle {
+    pub hook: Option<String>,
+    _sidecar: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LogConfig {
+    pub max_files: Option<u64>,
+    #[serde(rename = "MaxFileSizeMB")]
+    pub max_file_size_mb: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Connect {
+    _gateway: Option<Value>,
+    _native: Option<bool>,
+    _sidecar_service: Option<Value>,
+    _sidecar_task: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Service {
+    _id: Option<String>,
+    pub name: Option<String>,
+    _tags: Option<Vec<String>>,
+    _canary_tags: Option<Vec<String>>,
+    _enable_tag_override: Option<bool>,
+    pub port_label: Option<String>,
+    pub address_mode: Option<String>,
+    pub checks: Option<Vec<ServiceCheck>>,
+    _check_restart: Option<CheckRestart>,
+    _connect: Option<Connect>,
+    _meta: Option<HashMap<String, String>>,
+    _task_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Resources {
+    #[serde(rename = "CPU")]
+    pub cpu: Option<u64>,
+    _cores: Option<u64>,
+    #[serde(rename = "MemoryMB")]
+    pub memory_mb: Option<u64>,
+    #[serde(rename = "MemoryMaxMB")]
+    pub memory_max_mb: Option<u64>,
+    #[serde(rename = "DiskMB")]
+    _disk_mb: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Task {
+    pub name: Option<String>,
+    pub driver: Option<String>,
+    _config: Option<Value>,
+    pub constraints: Option<Vec<Constraint>>,
+    pub affinities: Option<Vec<Affinity>>,
+    _env: Option<HashMap<String, String>>,
+    pub services: Option<Vec<Service>>,
+    pub resources: Option<Resources>,
+    _meta: Option<HashMap<String, String>>,
+    pub restart_policy: Option<RestartPolicy>,
+    _shutdown_delay: Option<u64>,
+    _user: Option<String>,
+    pub lifecycle: Option<Lifecycle>,
+    _kill_timeout: Option<u64>,
+    pub log_config: Option<LogConfig>,
+    pub artifacts: Option<Vec<Artifact>>,
+    pub templates: Option<Vec<Template>>,
+    _dispatch_payload: Option<Value>,
+    pub volume_mounts: Option<Vec<VolumeMount>>,
+    _leader: Option<bool>,
+    _kill_signal: Option<String>,
+    _scaling_policies: Option<Value>,
+    pub vault: Option<Vault>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct VolumeMount {
+    _destination: Option<String>,
+    _propagation_mode: Option<String>,
+    _read_only: Option<bool>,
+    pub volume: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Artifact {
+    pub getter_source: Option<String>,
+    _getter_options: Option<HashMap<String, String>>,
+    _getter_headers: Option<HashMap<String, String>>,
+    pub getter_mode: Option<String>,
+    _relative_dest: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Template {
+    pub source_path: Option<String>,
+    pub dest_path: Option<String>,
+    pub embedded_tmpl: Option<String>,
+    pub change_mode: Option<String>,
+    pub change_signal: Option<String>,
+    _splay: Option<u64>,
+    pub perms: Option<String>,
+    _left_delim: Option<String>,
+    _right_delim: Option<String>,
+    _envvars: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Vault {
+    pub change_mode: Option<String>,
+    _change_signal: Option<String>,
+    _env: Option<bool>,
+    _namespace: Option<String>,
+    pub policies: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in a job, located by its field path.
+#[derive(Debug)]
+pub struct Issue {
+    pub severity: Severity,
+    pub path: String,
+    pub message: String,
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", severity, self.path, self.message)
+    }
+}
+
+/// Check a job specification against the schema. Type mismatches stop the
+/// check at the first one; all other problems are collected.
+pub fn validate(spec: Value) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut unknown = Vec::new();
+
+    let mut record_unknown = |path: serde_ignored::Path| unknown.push(ignored_path(&path));
+    let deserializer = serde_ignored::Deserializer::new(spec, &mut record_unknown);
+
+    match serde_path_to_error::deserialize::<_, Job>(deserializer) {
+        Ok(job) => Checker::new(&mut issues).job(&job),
+        Err(err) => issues.push(Issue {
+            severity: Severity::Error,
+            path: job_path(&err.path().to_string()),
+            message: err.into_inner().to_string(),
+        }),
+    }
+
+    for path in unknown.into_iter() {
+        issues.push(Issue {
+            severity: Severity::Warning,
+            path: job_path(&path),
+            message: "unknown field, it's not part of the schema".to_owned(),
+        });
+    }
+
+    issues.sort_by_key(|issue| issue.severity);
+    issues
+}
+
+/// Render paths of unknown fields the same way as `serde_path_to_error`.
+fn ignored_path(path: &serde_ignored::Path) -> String {
+    use serde_ignored::Path;
+
+    match path {
+        Path::Root => String::new(),
+        Path::Seq { parent, index } => format!("{}[{}]", ignored_path(parent), index),
+        Path::Map { parent, key } => match ignored_path(parent) {
+            parent if parent.is_empty() => key.to_owned(),
+            parent => format!("{}.{}", parent, key),
+        },
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => ignored_path(parent),
+    }
+}
+
+fn job_path(path: &str) -> String {
+    match path {
+        "" | "." => "Job".to_owned(),
+        path => format!("Job.{}", path),
+    }
+}
+
+/// Walks a parsed job and records everything the schema would reject.
+struct Checker<'a> {
+    issues: &'a mut Vec<Issue>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(issues: &'a mut Vec<Issue>) -> Self {
+        Self { issues }
+    }
+
+    fn error(&mut self, path: &str, message: String) {
+        self.issues.push(Issue {
+            severity: Severity::Error,
+            path: path.to_owned(),
+            message,
+        });
+    }
+
+    fn warning(&mut self, path: &str, message: String) {
+        self.issues.push(Issue {
+            severity: Severity::Warning,
+            path: path.to_owned(),
+            message,
+        });
+    }
+
+    fn required<T>(&mut self, path: &str, value: &Option<T>) {
+        if value.is_none() {
+            self.error(path, "missing required field".to_owned());
+        }
+    }
+
+    fn one_of(&mut self, path: &str, value: &Option<String>, allowed: &[&str]) {
+        if let Some(value) = value {
+            if !allowed.contains(&value.as_str()) {
+                self.error(
+                    path,
+                    format!("\"{}\" is not one of: {}", value, allowed.join(", ")),
+                );
+            }
+        }
+    }
+
+    fn job(&mut self, job: &Job) {
+        match &job.namespace {
+            Some(namespace) if !namespace.is_empty() => (),
+            _ => self.error(
+                "Job.Namespace",
+                "missing namespace; the job would silently land in 'default'".to_owned(),
+            ),
+        }
+        if job.id.is_none() && job.name.is_none() {
+            self.error("Job.ID", "the job needs an ID or a Name".to_owned());
+        }
+        self.required("Job.Type", &job.r#type);
+        self.one_of("Job.Type", &job.r#type, JOB_TYPES);
+        if let Some(priority) = job.priority {
+            if !(1..=100).contains(&priority) {
+                self.error(
+                    "Job.Priority",
+                    format!("{} is outside of 1..=100", priority),
+                );
+            }
+        }
+        match &job.datacenters {
+            Some(dcs) if !dcs.is_empty() => (),
+            _ => self.error(
+                "Job.Datacenters",
+                "at least one datacenter is required".to_owned(),
+            ),
+        }
+
+        self.constraints("Job.Constraints", &job.constraints);
+        self.affinities("Job.Affinities", &job.affinities);
+        self.spreads("Job.Spreads", &job.spreads);
+        if let Some(vault) = &job.vault {
+            self.vault("Job.Vault", vault);
+        }
+        if let Some(update) = &job.update {
+            self.update("Job.Update", update);
+        }
+        if let Some(migrate) = &job.migrate {
+            self.migrate("Job.Migrate", migrate);
+        }
+        if let Some(periodic) = &job.periodic {
+            self.one_of("Job.Periodic.SpecType", &periodic.spec_type, &["cron"]);
+            self.required("Job.Periodic.Spec", &periodic.spec);
+        }
+
+        let groups = match &job.task_groups {
+            Some(groups) if !groups.is_empty() => groups,
+            _ => {
+                self.error(
+                    "Job.TaskGroups",
+                    "at least one task group is required".to_owned(),
+                );
+                return;
+            }
+        };
+
+        let mut names = HashSet::new();
+        for (i, group) in groups.iter().enumerate() {
+            let path = format!("Job.TaskGroups[{}]", i);
+            if let Some(name) = &group.name {
+                if !names.insert(name) {
+                    self.error(
+                        &format!("{}.Name", path),
+                        format!("duplicate task group \"{}\"", name),
+                    );
+                }
+            }
+            self.task_group(&path, group);
+        }
+    }
+
+    fn task_group(&mut self, path: &str, group: &TaskGroup) {
+        self.required(&format!("{}.Name", path), &group.name);
+        if let Some(count) = group.count {
+            if count <= 0 {
+                self.error(
+                    &format!("{}.Count", path),
+                    format!("{} must be greater than 0", count),
+                );
+            }
+        }
+
+        self.constraints(&format!("{}.Constraints", path), &group.constraints);
+        self.affinities(&format!("{}.Affinities", path), &group.affinities);
+        self.spreads(&format!("{}.Spreads", path), &group.spreads);
+        if let Some(policy) = &group.restart_policy {
+            self.restart_policy(&format!("{}.RestartPolicy", path), policy);
+        }
+        if let Some(policy) = &group.reschedule_policy {
+            self.one_of(
+                &format!("{}.ReschedulePolicy.DelayFunction", path),
+                &policy.delay_function,
+                &["constant", "exponential", "fibonacci"],
+            );
+            if let Some(delay) = policy.delay {
+                if delay < 5_000_000_000 {
+                    self.error(
+                        &format!("{}.ReschedulePolicy.Delay", path),
+                        "must be at least 5s".to_owned(),
+                    );
+                }
+            }
+        }
+        if let Some(update) = &group.update {
+            self.update(&format!("{}.Update", path), update);
+        }
+        if let Some(migrate) = &group.migrate {
+            self.migrate(&format!("{}.Migrate", path), migrate);
+        }
+        if let Some(vault) = &group.vault {
+            self.vault(&format!("{}.Vault", path), vault);
+        }
+        for (name, volume) in group.volumes.iter().flatten() {
+            let volume_path = format!("{}.Volumes.{}", path, name);
+            self.required(&format!("{}.Source", volume_path), &volume.source);
+            self.one_of(
+                &format!("{}.Type", volume_path),
+                &volume.r#type,
+                &["host", "csi"],
+            );
+        }
+
+        let mut ports = HashSet::new();
+        for (i, network) in group.networks.iter().flatten().enumerate() {
+            let network_path = format!("{}.Networks[{}]", path, i);
+            self.one_of(
+                &format!("{}.Mode", network_path),
+                &network.mode,
+                &["host", "bridge"],
+            );
+            for port in network
+                .reserved_ports
+                .iter()
+                .flatten()
+                .chain(network.dynamic_ports.iter().flatten())
+            {
+                if let Some(label) = &port.label {
+                    ports.insert(label.clone());
+                }
+            }
+        }
+
+        for (i, service) in group.services.iter().flatten().enumerate() {
+            self.service(&format!("{}.Services[{}]", path, i), service, &ports);
+        }
+
+        let tasks = match &group.tasks {
+            Some(tasks) if !tasks.is_empty() => tasks,
+            _ => {
+                self.error(
+                    &format!("{}.Tasks", path),
+                    "at least one task is required".to_owned(),
+                );
+                return;
+            }
+        };
+
+        let mut names = HashSet::new();
+        for (i, task) in tasks.iter().enumerate() {
+            let task_path = format!("{}.Tasks[{}]", path, i);
+            if let Some(name) = &task.name {
+                if !names.insert(name) {
+                    self.error(
+                        &format!("{}.Name", task_path),
+                        format!("duplicate task \"{}\"", name),
+                    );
+                }
+            }
+            self.task(&task_path, task, &ports, group.volumes.as_ref());
+        }
+    }
+
+    fn task(
+        &mut self,
+        path: &str,
+        task: &Task,
+        ports: &HashSet<String>,
+        volumes: Option<&HashMap<String, Volume>>,
+    ) {
+        self.required(&format!("{}.Name", path), &task.name);
+        self.required(&format!("{}.Driver", path), &task.driver);
+
+        if let Some(driver) = &task.driver {
+            let driver_path = format!("{}.Driver", path);
+            if OTHER_DRIVERS.contains(&driver.as_str()) {
+                self.warning(
+                    &driver_path,
+                    format!("driver \"{}\" is not covered by the schema", driver),
+                );
+            } else if !DRIVERS.contains(&driver.as_str()) {
+                self.error(
+                    &driver_path,
+                    format!(
+                        "unknown driver \"{}\"; expected one of: {}",
+                        driver,
+                        DRIVERS.join(", ")
+                    ),
+                );
+            }
+        }
+
+        self.constraints(&format!("{}.Constraints", path), &task.constraints);
+        self.affinities(&format!("{}.Affinities", path), &task.affinities);
+        if let Some(policy) = &task.restart_policy {
+            self.restart_policy(&format!("{}.RestartPolicy", path), policy);
+        }
+        if let Some(vault) = &task.vault {
+            self.vault(&format!("{}.Vault", path), vault);
+        }
+        if let Some(lifecycle) = &task.lifecycle {
+            self.one_of(
+                &format!("{}.Lifecycle.Hook", path),
+                &lifecycle.hook,
+                &["prestart", "poststart", "poststop"],
+            );
+        }
+        if let Some(log_config) = &task.log_config {
+            if log_config.max_files == Some(0) {
+                self.error(
+                    &format!("{}.LogConfig.MaxFiles", path),
+                    "must be greater than 0".to_owned(),
+                );
+            }
+            if log_config.max_file_size_mb == Some(0) {
+                self.error(
+                    &format!("{}.LogConfig.MaxFileSizeMB", path),
+                    "must be greater than 0".to_owned(),
+                );
+            }
+        }
+
+        if let Some(resources) = &task.resources {
+            let resources_path = format!("{}.Resources", path);
+            if let Some(cpu) = resources.cpu {
+                if cpu < 100 {
+                    self.error(
+                        &format!("{}.CPU", resources_path),
+                        format!("{} MHz is below the minimum of 100", cpu),
+                    );
+                }
+            }
+            if let Some(memory) = resources.memory_mb {
+                if memory < 32 {
+                    self.error(
+                        &format!("{}.MemoryMB", resources_path),
+                        format!("{} MB is below the minimum of 32", memory),
+                    );
+                }
+            }
+            if let (Some(max), Some(memory)) = (resources.memory_max_mb, resources.memory_mb) {
+                if max < memory {
+                    self.error(
+                        &format!("{}.MemoryMaxMB", resources_path),
+                        format!("{} MB is less than MemoryMB ({} MB)", max, memory),
+                    );
+                }
+            }
+        }
+
+        for (i, service) in task.services.iter().flatten().enumerate() {
+            self.service(&format!("{}.Services[{}]", path, i), service, ports);
+        }
+
+        for (i, template) in task.templates.iter().flatten().enumerate() {
+            let template_path = format!("{}.Templates[{}]", path, i);
+            self.required(&format!("{}.DestPath", template_path), &template.dest_path);
+            if template.embedded_tmpl.is_none() && template.source_path.is_none() {
+                self.error(
+                    &template_path,
+                    "either EmbeddedTmpl or SourcePath is required".to_owned(),
+                );
+            }
+            self.one_of(
+                &format!("{}.ChangeMode", template_path),
+                &template.change_mode,
+                &["restart", "noop", "signal"],
+            );
+            if template.change_mode.as_deref() == Some("signal")
+                && template
+                    .change_signal
+                    .as_deref()
+                    .unwrap_or_default()
+                    .is_empty()
+            {
+                self.error(
+                    &format!("{}.ChangeSignal", template_path),
+                    "ChangeMode \"signal\" requires a ChangeSignal".to_owned(),
+                );
+            }
+            if let Some(perms) = &template.perms {
+                if perms.len() != 4 || !perms.chars().all(|c| ('0'..='7').contains(&c)) {
+                    self.error(
+                        &format!("{}.Perms", template_path),
+                        format!("\"{}\" is not an octal mode like \"0644\"", perms),
+                    );
+                }
+            }
+        }
+
+        for (i, artifact) in task.artifacts.iter().flatten().enumerate() {
+            let artifact_path = format!("{}.Artifacts[{}]", path, i);
+            self.required(
+                &format!("{}.GetterSource", artifact_path),
+                &artifact.getter_source,
+            );
+            self.one_of(
+                &format!("{}.GetterMode", artifact_path),
+                &artifact.getter_mode,
+                &["any", "file", "dir"],
+            );
+        }
+
+        for (i, mount) in task.volume_mounts.iter().flatten().enumerate() {
+            if let Some(volume) = &mount.volume {
+                if volumes.and_then(|volumes| volumes.get(volume)).is_none() {
+                    self.error(
+                        &format!("{}.VolumeMounts[{}].Volume", path, i),
+                        format!("volume \"{}\" is not defined in the task group", volume),
+                    );
+                }
+            }
+        }
+    }
+
+    fn service(&mut self, path: &str, service: &Service, ports: &HashSet<String>) {
+        self.required(&format!("{}.Name", path), &service.name);
+        self.one_of(
+            &format!("{}.AddressMode", path),
+            &service.address_mode,
+            &["alloc", "auto", "driver", "host"],
+        );
+        self.port_label(&format!("{}.PortLabel", path), &service.port_label, ports);
+
+        for (i, check) in service.checks.iter().flatten().enumerate() {
+            let check_path = format!("{}.Checks[{}]", path, i);
+            self.one_of(
+                &format!("{}.Type", check_path),
+                &check.r#type,
+                &["http", "tcp", "script", "grpc"],
+            );
+            self.one_of(
+                &format!("{}.AddressMode", check_path),
+                &check.address_mode,
+                &["alloc", "driver", "host"],
+            );
+            self.one_of(
+                &format!("{}.OnUpdate", check_path),
+                &check.on_update,
+                &["", "require_healthy", "ignore_warnings", "ignore"],
+            );
+            self.required(&format!("{}.Timeout", check_path), &check.timeout);
+            self.port_label(
+                &format!("{}.PortLabel", check_path),
+                &check.port_label,
+                ports,
+            );
+            if check.r#type.as_deref() == Some("http")
+                && check.path.as_deref().unwrap_or_default().is_empty()
+            {
+                self.error(
+                    &format!("{}.Path", check_path),
+                    "http checks require a Path".to_owned(),
+                );
+            }
+        }
+    }
+
+    /// Services and checks usually refer to a port declared in the group
+    /// network, but numeric ports are fine too.
+    fn port_label(&mut self, path: &str, label: &Option<String>, ports: &HashSet<String>) {
+        if let Some(label) = label {
+            if !label.is_empty() && label.parse::<u16>().is_err() && !ports.contains(label) {
+                self.error(
+                    path,
+                    format!("port \"{}\" is not declared in the group network", label),
+                );
+            }
+        }
+    }
+
+    fn constraints(&mut self, path: &str, constraints: &Option<Vec<Constraint>>) {
+        for (i, constraint) in constraints.iter().flatten().enumerate() {
+            let operand_path = format!("{}[{}].Operand", path, i);
+            self.operand(&operand_path, &constraint.operand, CONSTRAINT_OPERANDS);
+        }
+    }
+
+    fn affinities(&mut self, path: &str, affinities: &Option<Vec<Affinity>>) {
+        for (i, affinity) in affinities.iter().flatten().enumerate() {
+            let operand_path = format!("{}[{}].Operand", path, i);
+            self.operand(&operand_path, &affinity.operand, AFFINITY_OPERANDS);
+            match affinity.weight {
+                Some(0) | None => self.error(
+                    &format!("{}[{}].Weight", path, i),
+                    "a non-zero weight is required".to_owned(),
+                ),
+                Some(weight) if !(-100..=100).contains(&weight) => self.error(
+                    &format!("{}[{}].Weight", path, i),
+                    format!("{} is outside of -100..=100", weight),
+                ),
+                _ => (),
+            }
+        }
+    }
+
+    fn spreads(&mut self, path: &str, spreads: &Option<Vec<Spread>>) {
+        for (i, spread) in spreads.iter().flatten().enumerate() {
+            let spread_path = format!("{}[{}]", path, i);
+            self.required(&format!("{}.Attribute", spread_path), &spread.attribute);
+            if let Some(weight) = spread.weight {
+                if !(-100..=100).contains(&weight) {
+                    self.error(
+                        &format!("{}.Weight", spread_path),
+                        format!("{} is outside of -100..=100", weight),
+                    );
+                }
+            }
+            let total: u64 = spread
+                .spread_target
+                .iter()
+                .flatten()
+                .filter_map(|target| target.percent)
+                .sum();
+            if total > 100 {
+                self.error(
+                    &format!("{}.SpreadTarget", spread_path),
+                    format!("percentages add up to {}", total),
+                );
+            }
+        }
+    }
+
+    fn operand(&mut self, path: &str, operand: &Option<String>, allowed: &[&str]) {
+        match operand {
+            None => self.error(path, "missing required field".to_owned()),
+            Some(operand) if allowed.contains(&operand.as_str()) => (),
+            Some(operand) if OTHER_OPERANDS.contains(&operand.as_str()) => self.warning(
+                path,
+                format!("operand \"{}\" is not covered by the schema", operand),
+            ),
+            Some(operand) => self.error(
+                path,
+                format!(
+                    "unknown operand \"{}\"; expected one of: {}",
+                    operand,
+                    allowed.join(" ")
+                ),
+            ),
+        }
+    }
+
+    fn restart_policy(&mut self, path: &str, policy: &RestartPolicy) {
+        self.one_of(&format!("{}.Mode", path), &policy.mode, &["delay", "fail"]);
+    }
+
+    fn update(&mut self, path: &str, update: &Update) {
+        self.one_of(
+            &format!("{}.HealthCheck", path),
+            &update.health_check,
+            &["checks", "task_states", "manual"],
+        );
+    }
+
+    fn migrate(&mut self, path: &str, migrate: &Migrate) {
+        self.one_of(
+            &format!("{}.HealthCheck", path),
+            &migrate.health_check,
+            &["checks", "task_states"],
+        );
+    }
+
+    fn vault(&mut self, path: &str, vault: &Vault) {
+        self.one_of(
+            &format!("{}.ChangeMode", path),
+            &vault.change_mode,
+            &["noop", "restart", "signal"],
+        );
+        match &vault.policies {
+            Some(policies) if !policies.is_empty() => (),
+            _ => self.error(
+                &format!("{}.Policies", path),
+                "at least one policy is required".to_owned(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn job() -> Value {
+        json!({
+            "Namespace": "default",
+            "ID": "web",
+            "Type": "service",
+            "Datacenters": ["eu-central-1"],
+            "TaskGroups": [{
+                "Name": "web",
+                "Count": 2,
+                "RestartPolicy": {"Attempts": 3, "Interval": 600000000000u64, "Mode": "delay"},
+                "EphemeralDisk": {"SizeMB": 300, "Sticky": false},
+                "Networks": [{"Mode": "bridge", "DynamicPorts": [{"Label": "http", "To": 8080}]}],
+                "Services": [{
+                    "Name": "web",
+                    "PortLabel": "http",
+                    "Checks": [{"Type": "http", "Path": "/health", "Timeout": 2000000000u64}],
+                }],
+                "Tasks": [{
+                    "Name": "web",
+                    "Driver": "exec",
+                    "Config": {"command": "/bin/web"},
+                    "Resources": {"CPU": 100, "MemoryMB": 64},
+                }],
+            }],
+        })
+    }
+
+    #[test]
+    fn validate_valid_job() {
+        let issues = validate(job());
+        assert!(issues.is_empty(), "{:?}", issues);
+    }
+
+    #[test]
+    fn validate_unknown_field() {
+        let mut spec = job();
+        spec["TaskGroups"][0]["Tasks"][0]["Resources"]["Memory"] = json!(64);
+        let issues = validate(spec);
+        assert_eq!(issues.len(), 1, "{:?}", issues);
+        assert_eq!(issues[0].severity, Severity::Warning);
+        assert_eq!(
+            issues[0].path,
+            "Job.TaskGroups[0].Tasks[0].Resources.Memory"
+        );
+    }
+
+    #[test]
+    fn validate_type_mismatch() {
+        let mut spec = job();
+        spec["TaskGroups"][0]["Count"] = json!("two");
+        let issues = validate(spec);
+        assert_eq!(issues.len(), 1, "{:?}", issues);
+        assert_eq!(issues[0].severity, Severity::Error);
+        assert_eq!(issues[0].path, "Job.TaskGroups[0].Count");
+    }
+
+    #[test]
+    fn validate_type_mismatch_of_unchecked_field() {
+        let mut spec = job();
+        spec["TaskGroups"][0]["RestartPolicy"]["Attempts"] = json!("three");
+        let issues = validate(spec);
+        assert_eq!(issues.len(), 1, "{:?}", issues);
+        assert_eq!(issues[0].path, "Job.TaskGroups[0].RestartPolicy.Attempts");
+    }
+
+    #[test]
+    fn validate_schema_errors() {
+        let mut spec = job();
+        spec["Namespace"] = json!("");
+        spec["TaskGroups"][0]["Tasks"][0]["Driver"] = json!("podman");
+        let paths: Vec<String> = validate(spec).into_iter().map(|issue| issue.path).collect();
+        assert_eq!(
+            paths,
+            ["Job.Namespace", "Job.TaskGroups[0].Tasks[0].Driver"]
+        );
+    }
+}
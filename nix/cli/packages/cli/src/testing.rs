@@ -0,0 +1,101 @@
+//! A local HTTP server for tests to stand in for Nomad, Vault or AWS.
+
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A request the stub answered.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: String,
+}
+
+pub type Requests = Arc<Mutex<Vec<Request>>>;
+
+/// Serve HTTP/1.1 on a free local port, answering every request with the
+/// status line and body `answer` picks for it, given how many requests came
+/// before. Returns the base URL and the requests seen so far.
+pub async fn serve<F>(answer: F) -> (String, Requests)
+where
+    F: Fn(usize, &Request) -> (&'static str, String) + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Requests::default();
+    let seen = Arc::clone(&requests);
+    let answer = Arc::new(answer);
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(connection(stream, Arc::clone(&seen), Arc::clone(&answer)));
+        }
+    });
+
+    (url, requests)
+}
+
+/// Answer requests on a kept-alive connection until the client is done.
+async fn connection<F>(stream: TcpStream, seen: Requests, answer: Arc<F>)
+where
+    F: Fn(usize, &Request) -> (&'static str, String),
+{
+    let mut stream = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+            return;
+        }
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_owned();
+        let path = parts.next().unwrap_or_default().to_owned();
+
+        let mut length = 0;
+        loop {
+            line.clear();
+            if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                return;
+            }
+            if line == "\r\n" {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; length];
+        if stream.read_exact(&mut body).await.is_err() {
+            return;
+        }
+
+        let request = Request {
+            method,
+            path,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        };
+        let (status, body) = {
+            let mut seen = seen.lock().unwrap();
+            let reply = answer(seen.len(), &request);
+            seen.push(request);
+            reply
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\ncontent-length: {}\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        if stream
+            .get_mut()
+            .write_all(response.as_bytes())
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}